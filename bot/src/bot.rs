@@ -1,24 +1,34 @@
 use log::{debug, error, info};
 use reqwest::{Client, Url};
-use shared::chat_config::PermissionsConfig;
-use shared::config::Config;
+use shared::chat_config::{append_audit_log, save_config, PermissionsConfig, Role};
+use shared::config::{Config, SharedConfig};
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use once_cell::sync::Lazy;
 use teloxide::prelude::{Message, Requester};
 use teloxide::utils::command::{BotCommands, ParseError};
 use tokio::sync::Mutex;
 use crate::process_message::process_message;
 use crate::queue::{FileQueueType, get_queue_snapshot, clear_queue_all};
-use shared::file_storage::{list_all_files, delete_file_metadata, save_file_metadata};
-use teloxide::types::ChatId;
+use shared::api_keys::{self, DEFAULT_RATE_LIMIT};
+use shared::link_utils::build_upload_url;
+use shared::utils::{format_timestamp, parse_duration};
+use shared::upload_tokens::{issue_upload_token, DEFAULT_UPLOAD_TOKEN_TTL, MAX_UPLOAD_TOKEN_TTL};
+use shared::file_storage::{list_all_files, delete_file_metadata, get_file_metadata, save_file_metadata};
+use crate::membership::group_role;
+use crate::repair::{check_and_repair, repair_all, RepairOutcome};
+use crate::storage::delete_stored_file;
+use teloxide::types::{ChatId, User};
 
 pub trait Bot {
-    fn new(config: Arc<Config>, permissions: Arc<Mutex<PermissionsConfig>>, queue: FileQueueType) -> Result<Self, String> where Self: Sized;
+    fn new(config: SharedConfig, permissions: Arc<Mutex<PermissionsConfig>>, queue: FileQueueType) -> Result<Self, String> where Self: Sized;
     fn run(&self, tx: tokio::sync::mpsc::Sender<()>) -> impl std::future::Future<Output=()> + Send;
 }
 
 #[derive(Debug, Clone)]
 pub struct TeloxideBot {
+    config: SharedConfig,
     permissions: Arc<Mutex<PermissionsConfig>>,
     queue: FileQueueType,
     teloxide_bot: Arc<teloxide::Bot>,
@@ -28,10 +38,15 @@ impl TeloxideBot {
     pub fn get_teloxide_bot(&self) -> Arc<teloxide::Bot> {
         self.teloxide_bot.clone()
     }
+
+    /// The current configuration; reloads are picked up on the next call
+    pub fn config(&self) -> Arc<Config> {
+        self.config.get()
+    }
 }
 
 impl Bot for TeloxideBot {
-    fn new(config: Arc<Config>, permissions: Arc<Mutex<PermissionsConfig>>, queue: FileQueueType) -> Result<Self, String> {
+    fn new(config: SharedConfig, permissions: Arc<Mutex<PermissionsConfig>>, queue: FileQueueType) -> Result<Self, String> {
         let client = Client::builder()
             .connect_timeout(Duration::from_secs(5))
             .timeout(Duration::from_secs(300))
@@ -42,7 +57,9 @@ impl Bot for TeloxideBot {
                 Client::new()
             });
 
-        let token = match config.bot_token() {
+        let current = config.get();
+
+        let token = match current.bot_token() {
             Ok(t) => { t }
             Err(_) => {
                 error!("Failed to get bot token");
@@ -53,12 +70,16 @@ impl Bot for TeloxideBot {
 
         let mut bot = teloxide::Bot::with_client(token, client);
 
-        bot = bot.set_api_url(Url::parse(config.telegram_api_url().as_str()).unwrap());
+        let api_url = Url::parse(current.telegram_api_url().as_str())
+            .map_err(|e| format!("Invalid TELEGRAM_API_URL '{}': {}", current.telegram_api_url(), e))?;
+
+        bot = bot.set_api_url(api_url);
 
         let bot_ref = Arc::new(bot);
 
         Ok(TeloxideBot {
             teloxide_bot: bot_ref,
+            config,
             permissions,
             queue,
         })
@@ -67,6 +88,7 @@ impl Bot for TeloxideBot {
     async fn run(&self, tx: tokio::sync::mpsc::Sender<()>) {
         let file_queue = Arc::clone(&self.queue);
         let permissions = Arc::clone(&self.permissions);
+        let config = self.config.clone();
         let bot = self.teloxide_bot.clone();
 
     teloxide::repl(bot.clone(), move |msg: Message| {
@@ -76,11 +98,10 @@ impl Bot for TeloxideBot {
             let bot_clone = Arc::clone(&bot);
             let permissions = Arc::clone(&permissions);
             let file_queue = Arc::clone(&file_queue);
+            let config = config.get();
             let tx = tx.clone();
 
             async move {
-                let permissions = permissions.lock().await;
-
                 let from = match msg.from() {
                     Some(from) => from,
                     None => {
@@ -89,28 +110,63 @@ impl Bot for TeloxideBot {
                     }
                 };
 
-                if !permissions.user_has_access(msg.chat.id.to_string(), &from.id.to_string()) {
-                    info!(
-                        "User {} does not have access to chat {}",
-                        msg.from().unwrap().id,
-                        msg.clone().chat.id
-                    );
+                // Only hold the lock for the lookup, commands may edit the permissions
+                let username = from.username.as_deref();
+                let (role, denial, group_rules, pin) = {
+                    let permissions = permissions.lock().await;
+                    let chat_id = msg.chat.id.to_string();
+                    let user_id = from.id.to_string();
+                    let role = permissions.role_for(&chat_id, &user_id, username);
+                    let denial = permissions.denial_for(&chat_id, &user_id, username);
+                    let pin = username.filter(|username| permissions.needs_pin(username));
+
+                    (role, denial.clone(), if denial.is_none() { permissions.group_rules_for(&chat_id, role) } else { Vec::new() }, pin)
+                };
+
+                // The first time a configured @username shows up, its id is pinned so the
+                // entry keeps working after a rename and can't be taken over by someone else
+                if let Some(username) = pin {
+                    let action = format!("pinned @{} to {}", username, from.id);
+                    let id = from.id.0 as i64;
 
-                    return Ok(());
+                    if let Err(e) = update_permissions(&permissions, "bot", &action, |config| config.pin_username(username, id)).await {
+                        error!("Failed to pin @{}: {}", username, e);
+                    }
                 }
 
-                info!(
-                    "User {} has access to chat {}",
-                    msg.from().unwrap().id,
-                    msg.clone().chat.id
-                );
+                // Group rules need Telegram lookups, so only the ones that could raise the role are checked
+                let role = if group_rules.is_empty() {
+                    role
+                } else {
+                    role.max(group_role(&bot_clone, from.id.0, group_rules).await)
+                };
+
+                let role = match role {
+                    Some(role) => role,
+                    None => {
+                        info!("User {} does not have access to chat {}", from.id, msg.chat.id);
+
+                        // Blocked users hear about it once; strangers only in private chats,
+                        // so the bot stays quiet in groups it shares with them
+                        if denial.is_some() || msg.chat.is_private() {
+                            notify_blocked_once(&bot_clone, msg.chat.id, from.id.0, denial.and_then(|d| d.until)).await;
+                        }
+
+                        return Ok(());
+                    }
+                };
+
+                info!("User {} has role {} in chat {}", from.id, role, msg.chat.id);
 
                 // Try to parse commands first
                 if let Some(text) = msg.text() {
                     let chat_id = msg.chat.id;
                     // Allow spaces in /find query
                     if let Some(rest) = text.strip_prefix("/find ") {
-                        handle_command(bot_clone.clone(), chat_id, file_queue.clone(), Command::Find { query: rest.trim().to_string() }).await;
+                        let cmd = Command::Find { query: rest.trim().to_string() };
+                        if ensure_role(&bot_clone, chat_id, role, required_role(&cmd)).await {
+                            handle_command(bot_clone.clone(), chat_id, &config, file_queue.clone(), &permissions, from, cmd).await;
+                        }
                         return Ok(());
                     }
                     // Handle /list with optional page number
@@ -119,15 +175,28 @@ impl Bot for TeloxideBot {
                             .split_whitespace()
                             .nth(1)
                             .and_then(|s| s.parse::<usize>().ok());
-                        handle_list_command(bot_clone.clone(), chat_id, page).await;
+                        if ensure_role(&bot_clone, chat_id, role, required_role(&Command::List)).await {
+                            handle_list_command(bot_clone.clone(), chat_id, &config, page).await;
+                        }
                         return Ok(());
                     }
                     if let Ok(cmd) = Command::parse(text, "") {
-                        handle_command(bot_clone.clone(), chat_id, file_queue.clone(), cmd).await;
+                        if ensure_role(&bot_clone, chat_id, role, required_role(&cmd)).await {
+                            handle_command(bot_clone.clone(), chat_id, &config, file_queue.clone(), &permissions, from, cmd).await;
+                        }
                         return Ok(());
                     }
                 }
 
+                // Plain text from viewers is just chat, not an upload attempt
+                if role < Role::Uploader && msg.text().is_some() {
+                    return Ok(());
+                }
+
+                if !ensure_role(&bot_clone, msg.chat.id, role, Role::Uploader).await {
+                    return Ok(());
+                }
+
                 if let Err(e) = process_message(bot_clone.clone(), msg.clone(), file_queue, tx).await {
                     error!("Failed to process message: {}", e);
                 }
@@ -155,6 +224,105 @@ enum Command {
     Edit { id: String, new_name: String },
     #[command(description = "search files by name: /find <query>")]
     Find { query: String },
+    #[command(description = "get a one-time browser upload link: /uploadlink [hours]")]
+    UploadLink(String),
+    #[command(description = "manage API keys: /apikey create <name> [scopes] [rate_limit], /apikey list, /apikey revoke <id>")]
+    ApiKey(String),
+    #[command(description = "re-resolve broken files from the storage channel: /repair or /repair <id>")]
+    Repair(String),
+    #[command(description = "let a user upload: /allow <user_id|@username> [chat_id|here]")]
+    Allow(String),
+    #[command(description = "block a user: /deny <user_id|@username> [chat_id|here]")]
+    Deny(String),
+    #[command(description = "block a user for a while: /ban <user_id|@username> <30m|12h|7d> [chat_id|here]")]
+    Ban(String),
+    #[command(description = "show or set a role: /role <user_id|@username> [viewer|uploader|admin] [chat_id|here]")]
+    Role(String),
+    #[command(description = "show who has access")]
+    Permissions,
+}
+
+/// Lowest role allowed to run a command
+fn required_role(cmd: &Command) -> Role {
+    match cmd {
+        Command::Help | Command::List | Command::ShowQueue | Command::Find { .. } => Role::Viewer,
+        Command::UploadLink(_) => Role::Uploader,
+        Command::ClearQueue | Command::Delete(_) | Command::Edit { .. } | Command::ApiKey(_) | Command::Repair(_) => Role::Admin,
+        Command::Allow(_) | Command::Deny(_) | Command::Ban(_) | Command::Role(_) | Command::Permissions => Role::Admin,
+    }
+}
+
+/// The file index, the queue and API keys are shared by every chat, so commands
+/// that change them need an admin from `roles.users`, not just of the current chat
+fn check_global_admin(config: &PermissionsConfig, user_id: &str, username: Option<&str>, cmd: &Command) -> Result<(), String> {
+    let global = matches!(cmd, Command::ClearQueue | Command::Delete(_) | Command::Edit { .. } | Command::ApiKey(_) | Command::Repair(_));
+
+    if global && config.global_role(user_id, username) < Some(Role::Admin) {
+        return Err("This command affects every chat and can only be used by admins listed in roles.users".to_owned());
+    }
+
+    Ok(())
+}
+
+/// Longer bans are almost certainly a typo, `/deny` blocks for good
+const MAX_BAN_DURATION: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// A blocked user who keeps writing is reminded at most this often
+const BLOCK_NOTICE_INTERVAL: u64 = 24 * 60 * 60;
+
+/// Chat and user, mapped to the ban end they were told about and when
+type BlockNotices = HashMap<(i64, u64), (Option<u64>, u64)>;
+
+/// Users already told that they cannot use the bot
+static BLOCK_NOTICES: Lazy<Mutex<BlockNotices>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Record a notice unless the user already got one for this block. Notices for
+/// ended bans and old ones are dropped, so the map only holds current blocks.
+fn record_block_notice(notices: &mut BlockNotices, key: (i64, u64), until: Option<u64>, now: u64) -> bool {
+    notices.retain(|_, (until, noticed_at)| until.is_none_or(|until| until > now) && now.saturating_sub(*noticed_at) < BLOCK_NOTICE_INTERVAL);
+
+    // A new ban gets its own notice
+    if notices.get(&key).is_some_and(|(noticed, _)| *noticed == until) {
+        return false;
+    }
+
+    notices.insert(key, (until, now));
+
+    true
+}
+
+async fn notify_blocked_once(bot: &teloxide::Bot, chat_id: ChatId, user_id: u64, until: Option<u64>) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+    if !record_block_notice(&mut *BLOCK_NOTICES.lock().await, (chat_id.0, user_id), until, now) {
+        return;
+    }
+
+    let text = match until {
+        Some(until) => format!("Sorry, you cannot use this bot until {}.", format_timestamp(until)),
+        None => "Sorry, you do not have access to this bot. Please contact its administrator if you think this is a mistake.".to_owned(),
+    };
+
+    let _ = bot.send_message(chat_id, text).await;
+}
+
+/// How a user shows up in the audit log
+fn actor_name(from: &User) -> String {
+    match &from.username {
+        Some(username) => format!("{} (@{})", from.id, username),
+        None => from.id.to_string(),
+    }
+}
+
+/// Tell the user when their role is not enough, returns whether to go on
+async fn ensure_role(bot: &teloxide::Bot, chat_id: ChatId, role: Role, required: Role) -> bool {
+    if role >= required {
+        return true;
+    }
+
+    let _ = bot.send_message(chat_id, format!("This needs the {} role, you have {}", required, role)).await;
+
+    false
 }
 
 // Custom argument parser for `/edit <id> <new_name>`
@@ -165,7 +333,7 @@ fn split(s: String) -> Result<(String, String), ParseError> {
     Ok((id.to_string(), new_name.to_string()))
 }
 
-async fn handle_list_command(bot: Arc<teloxide::Bot>, chat_id: ChatId, page: Option<usize>) {
+async fn handle_list_command(bot: Arc<teloxide::Bot>, chat_id: ChatId, config: &Config, page: Option<usize>) {
     let mut files = list_all_files().await;
     if files.is_empty() {
         let _ = bot.send_message(chat_id, "No files found").await;
@@ -174,13 +342,12 @@ async fn handle_list_command(bot: Arc<teloxide::Bot>, chat_id: ChatId, page: Opt
     files.sort_by_key(|m| m.uploaded_at);
     let per_page: usize = 10;
     let total = files.len();
-    let total_pages = (total + per_page - 1) / per_page;
+    let total_pages = total.div_ceil(per_page);
     let p = page.unwrap_or(1).max(1).min(total_pages.max(1));
 
     let start_from_end = (p - 1) * per_page;
     let slice: Vec<_> = files.into_iter().rev().skip(start_from_end).take(per_page).collect();
-    let cfg = Config::instance().await;
-    let domain = cfg.file_domain();
+    let domain = config.file_domain();
     let mut lines = Vec::new();
     lines.push(format!("Page {}/{} ({} total)", p, total_pages.max(1), total));
     for f in slice {
@@ -193,7 +360,330 @@ async fn handle_list_command(bot: Arc<teloxide::Bot>, chat_id: ChatId, page: Opt
     let _ = bot.send_message(chat_id, lines.join("\n")).await;
 }
 
-async fn handle_command(bot: Arc<teloxide::Bot>, chat_id: ChatId, queue: FileQueueType, cmd: Command) {
+async fn handle_api_key_command(bot: Arc<teloxide::Bot>, chat_id: ChatId, args: String) {
+    const USAGE: &str = "Usage:\n/apikey create <name> [scopes] [rate_limit]\n/apikey list\n/apikey revoke <id>\n\nScopes: read, upload, admin (comma separated, default: read)";
+
+    let parts: Vec<&str> = args.split_whitespace().collect();
+
+    let text = match parts.as_slice() {
+        ["create", ..] if !chat_id.is_user() => {
+            "Create API keys in a private chat with the bot, everyone here would see the key".to_string()
+        }
+        ["create", name, rest @ ..] if rest.len() <= 2 => {
+            let scopes = match api_keys::parse_scopes(rest.first().copied().unwrap_or("read")) {
+                Ok(scopes) => scopes,
+                Err(e) => {
+                    let _ = bot.send_message(chat_id, e).await;
+                    return;
+                }
+            };
+            let rate_limit = match rest.get(1).map(|r| r.parse::<u32>()) {
+                None => DEFAULT_RATE_LIMIT,
+                Some(Ok(rate_limit)) => rate_limit,
+                Some(Err(_)) => {
+                    let _ = bot.send_message(chat_id, "rate_limit must be a number of requests per minute").await;
+                    return;
+                }
+            };
+            match api_keys::create_api_key(name, scopes, rate_limit).await {
+                Ok((key, secret)) => format!(
+                    "Created API key '{}' (id: {}, scopes: {}, {}/min)\n\n{}\n\nStore this key now, it cannot be shown again. Delete this message once saved.",
+                    key.name, key.id, key.scopes_string(), key.rate_limit, secret
+                ),
+                Err(e) => {
+                    error!("Failed to create API key: {}", e);
+                    "Failed to create API key".to_string()
+                }
+            }
+        }
+        ["list"] => {
+            let keys = api_keys::list_api_keys().await;
+            if keys.is_empty() {
+                "No API keys".to_string()
+            } else {
+                keys.iter()
+                    .map(|k| format!(
+                        "- {} ({}) [{}] {}/min{}",
+                        k.id, k.name, k.scopes_string(), k.rate_limit,
+                        if k.is_active() { "" } else { " revoked" }
+                    ))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        }
+        ["revoke", id] => match api_keys::revoke_api_key(id).await {
+            Ok(true) => format!("Revoked API key {}", id),
+            Ok(false) => format!("No active API key with id {}", id),
+            Err(e) => {
+                error!("Failed to revoke API key: {}", e);
+                "Failed to revoke API key".to_string()
+            }
+        },
+        _ => USAGE.to_string(),
+    };
+
+    let _ = bot.send_message(chat_id, text).await;
+}
+
+async fn handle_repair_command(bot: Arc<teloxide::Bot>, chat_id: ChatId, config: Arc<Config>, id: &str) {
+    if !id.is_empty() {
+        let metadata = match get_file_metadata(id).await {
+            Some(metadata) => metadata,
+            None => {
+                let _ = bot.send_message(chat_id, format!("File id not found: {}", id)).await;
+                return;
+            }
+        };
+
+        let reply = match check_and_repair(&bot, &config, &metadata).await {
+            RepairOutcome::Healthy => format!("{} is healthy, nothing to repair", id),
+            RepairOutcome::Repaired => format!("Repaired {}", id),
+            RepairOutcome::Failed(reason) => format!("Failed to repair {}: {}", id, reason),
+        };
+        let _ = bot.send_message(chat_id, reply).await;
+
+        return;
+    }
+
+    let _ = bot.send_message(chat_id, "Checking all files, this may take a while...").await;
+
+    // A bulk repair can take minutes, so it runs without blocking other commands
+    tokio::spawn(async move {
+        let report = repair_all(&bot, &config).await;
+        let _ = bot.send_message(chat_id, report.summary()).await;
+    });
+}
+
+/// `None` for a global change, `here` stands for the current chat
+fn parse_chat_scope(chat_id: ChatId, arg: Option<&str>) -> Result<Option<String>, String> {
+    match arg {
+        None => Ok(None),
+        Some("here") => Ok(Some(chat_id.to_string())),
+        Some(arg) => arg.parse::<i64>()
+            .map(|id| Some(id.to_string()))
+            .map_err(|_| format!("'{}' is not a chat id", arg)),
+    }
+}
+
+fn parse_user(arg: &str) -> Result<String, String> {
+    if arg == "*" {
+        return Ok(arg.to_owned());
+    }
+
+    if let Some(username) = arg.strip_prefix('@') {
+        if !username.is_empty() && username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Ok(arg.to_owned());
+        }
+    }
+
+    arg.parse::<u64>()
+        .map(|id| id.to_string())
+        .map_err(|_| format!("'{}' is not a user id or @username", arg))
+}
+
+/// Admins of a single chat may only edit that chat, anything wider needs an admin from `roles.users`
+fn check_edit_scope(config: &PermissionsConfig, chat_id: ChatId, user_id: &str, username: Option<&str>, scope: &Option<String>) -> Result<(), String> {
+    match scope {
+        Some(chat) if *chat == chat_id.to_string() => Ok(()),
+        _ if config.global_role(user_id, username) >= Some(Role::Admin) => Ok(()),
+        _ => Err("Only admins listed in roles.users can change other chats or every chat, use 'here' for this chat".to_owned()),
+    }
+}
+
+fn ban_until(now: u64, duration: Duration) -> Result<u64, String> {
+    if duration > MAX_BAN_DURATION {
+        return Err(format!("Bans can last at most {} days, use /deny to block for good", MAX_BAN_DURATION.as_secs() / (24 * 60 * 60)));
+    }
+
+    Ok(now.saturating_add(duration.as_secs()))
+}
+
+fn scope_name(scope: &Option<String>) -> String {
+    match scope {
+        None => "in every chat".to_owned(),
+        Some(chat_id) => format!("in chat {}", chat_id),
+    }
+}
+
+/// Edit a copy, persist it and only then swap it in, so a failed write changes nothing
+async fn update_permissions<F>(permissions: &Arc<Mutex<PermissionsConfig>>, actor: &str, action: &str, edit: F) -> Result<bool, String>
+where
+    F: FnOnce(&mut PermissionsConfig) -> bool,
+{
+    let mut current = permissions.lock().await;
+    let mut updated = current.clone();
+
+    let pruned = updated.prune_expired_bans();
+
+    if !edit(&mut updated) && !pruned {
+        return Ok(false);
+    }
+
+    save_config(&updated).await.map_err(|e| format!("Failed to save permissions: {}", e))?;
+    *current = updated;
+    drop(current);
+
+    if let Err(e) = append_audit_log(actor, action).await {
+        error!("Failed to write permissions audit log: {}", e);
+    }
+
+    Ok(true)
+}
+
+async fn handle_permissions_command(permissions: &Arc<Mutex<PermissionsConfig>>, chat_id: ChatId, from: &User, cmd: Command) -> String {
+    let actor = &actor_name(from);
+    let allowed = |scope: &Option<String>| {
+        let scope = scope.clone();
+        async move {
+            check_edit_scope(&*permissions.lock().await, chat_id, &from.id.to_string(), from.username.as_deref(), &scope)
+        }
+    };
+
+    let (user, args) = match &cmd {
+        Command::Permissions => return permissions.lock().await.summary(),
+        Command::Allow(args) | Command::Deny(args) | Command::Ban(args) | Command::Role(args) => {
+            let mut parts = args.split_whitespace();
+
+            match parts.next().map(parse_user) {
+                Some(Ok(user)) => (user, parts.map(str::to_owned).collect::<Vec<_>>()),
+                Some(Err(e)) => return e,
+                None => return "Usage: /allow, /deny, /ban or /role followed by a user id".to_owned(),
+            }
+        }
+        _ => return String::new(),
+    };
+
+    // Blocking everyone would lock out the admins too
+    if user == "*" && matches!(cmd, Command::Deny(_) | Command::Ban(_)) {
+        return "Only single users can be blocked, not '*'".to_owned();
+    }
+
+    let result = match cmd {
+        Command::Allow(_) => {
+            let scope = match parse_chat_scope(chat_id, args.first().map(String::as_str)) {
+                Ok(scope) => scope,
+                Err(e) => return e,
+            };
+            if let Err(e) = allowed(&scope).await {
+                return e;
+            }
+            let action = format!("allow {} {}", user, scope_name(&scope));
+
+            update_permissions(permissions, actor, &action, |config| {
+                let unblocked = config.undeny_user(&user, scope.as_deref());
+                // Never downgrade an admin
+                if config.assigned_role(&user, scope.as_deref()) >= Some(Role::Uploader) {
+                    return unblocked;
+                }
+                config.set_role(&user, scope.as_deref(), Role::Uploader);
+                true
+            }).await.map(|changed| if changed {
+                format!("{} can now upload {}", user, scope_name(&scope))
+            } else {
+                format!("{} already has at least the uploader role {}", user, scope_name(&scope))
+            })
+        }
+        Command::Deny(_) => {
+            let scope = match parse_chat_scope(chat_id, args.first().map(String::as_str)) {
+                Ok(scope) => scope,
+                Err(e) => return e,
+            };
+            if let Err(e) = allowed(&scope).await {
+                return e;
+            }
+            let action = format!("deny {} {}", user, scope_name(&scope));
+
+            update_permissions(permissions, actor, &action, |config| {
+                config.remove_user(&user, scope.as_deref());
+                config.deny_user(&user, scope.as_deref(), None);
+                true
+            }).await.map(|_| format!("{} is blocked {}, /allow lifts it", user, scope_name(&scope)))
+        }
+        Command::Ban(_) => {
+            let duration = match args.first().map(|d| parse_duration(d)) {
+                Some(Ok(duration)) => duration,
+                Some(Err(e)) => return e,
+                None => return "Usage: /ban <user_id|@username> <duration, e.g. 30m, 12h, 7d> [chat_id|here]".to_owned(),
+            };
+            let scope = match parse_chat_scope(chat_id, args.get(1).map(String::as_str)) {
+                Ok(scope) => scope,
+                Err(e) => return e,
+            };
+            if let Err(e) = allowed(&scope).await {
+                return e;
+            }
+            let until = match ban_until(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(), duration) {
+                Ok(until) => until,
+                Err(e) => return e,
+            };
+            let action = format!("ban {} {} until {}", user, scope_name(&scope), format_timestamp(until));
+
+            // Roles are kept, so the user is back to normal once the ban runs out
+            update_permissions(permissions, actor, &action, |config| {
+                config.deny_user(&user, scope.as_deref(), Some(until));
+                true
+            }).await.map(|_| format!("{} is banned {} until {}", user, scope_name(&scope), format_timestamp(until)))
+        }
+        Command::Role(_) => {
+            let (role, scope) = match args.first().map(|a| a.parse::<Role>()) {
+                Some(Ok(role)) => match parse_chat_scope(chat_id, args.get(1).map(String::as_str)) {
+                    Ok(scope) => (Some(role), scope),
+                    Err(e) => return e,
+                },
+                // `/role <user> [chat]` only shows the role
+                _ => match parse_chat_scope(chat_id, args.first().map(String::as_str)) {
+                    Ok(scope) => (None, scope),
+                    Err(_) => return "Usage: /role <user_id|@username> [viewer|uploader|admin] [chat_id|here]".to_owned(),
+                },
+            };
+
+            match role {
+                None => {
+                    let chat = scope.unwrap_or_else(|| chat_id.to_string());
+                    let role = permissions.lock().await.role_for(&chat, &user, None);
+
+                    return match role {
+                        Some(role) => format!("{} has the {} role in chat {}", user, role, chat),
+                        None => format!("{} has no access in chat {}", user, chat),
+                    };
+                }
+                Some(role) => {
+                    if let Err(e) = allowed(&scope).await {
+                        return e;
+                    }
+                    let action = format!("set role of {} to {} {}", user, role, scope_name(&scope));
+
+                    update_permissions(permissions, actor, &action, |config| {
+                        config.set_role(&user, scope.as_deref(), role);
+                        true
+                    }).await.map(|_| format!("{} is now {} {}", user, role, scope_name(&scope)))
+                }
+            }
+        }
+        _ => return String::new(),
+    };
+
+    result.unwrap_or_else(|e| {
+        error!("{}", e);
+        "Failed to save permissions, nothing was changed".to_owned()
+    })
+}
+
+async fn handle_command(
+    bot: Arc<teloxide::Bot>,
+    chat_id: ChatId,
+    config: &Arc<Config>,
+    queue: FileQueueType,
+    permissions: &Arc<Mutex<PermissionsConfig>>,
+    from: &User,
+    cmd: Command,
+) {
+    let allowed = check_global_admin(&*permissions.lock().await, &from.id.to_string(), from.username.as_deref(), &cmd);
+    if let Err(e) = allowed {
+        let _ = bot.send_message(chat_id, e).await;
+        return;
+    }
+
     match cmd {
         Command::Help => {
             let _ = bot.send_message(chat_id, Command::descriptions().to_string()).await;
@@ -209,11 +699,24 @@ async fn handle_command(bot: Arc<teloxide::Bot>, chat_id: ChatId, queue: FileQue
             let _ = bot.send_message(chat_id, format!("Cleared {} item(s) from queue", n)).await;
         }
         Command::List => {
-            handle_list_command(bot.clone(), chat_id, None).await;
+            handle_list_command(bot.clone(), chat_id, config, None).await;
         }
         Command::Delete(id) => {
+            let metadata = match get_file_metadata(&id).await {
+                Some(metadata) => metadata,
+                None => {
+                    let _ = bot.send_message(chat_id, format!("File id not found: {}", id)).await;
+                    return;
+                }
+            };
+            // The mapping stays while the stored message exists, otherwise reindex would restore it
+            if let Err(e) = delete_stored_file(&bot, config, &metadata).await {
+                error!("{}", e);
+                let _ = bot.send_message(chat_id, format!("{}, {} was not deleted", e, id)).await;
+                return;
+            }
             let _ = delete_file_metadata(&id).await;
-            let _ = bot.send_message(chat_id, format!("Deleted mapping for id: {}", id)).await;
+            let _ = bot.send_message(chat_id, format!("Deleted {} and its stored message", id)).await;
         }
         Command::Edit { id, new_name } => {
             // Load all, update one, and save via save_file_metadata
@@ -244,8 +747,7 @@ async fn handle_command(bot: Arc<teloxide::Bot>, chat_id: ChatId, queue: FileQue
                 let _ = bot.send_message(chat_id, "No matches found").await;
                 return;
             }
-            let cfg = Config::instance().await;
-            let domain = cfg.file_domain();
+            let domain = config.file_domain();
             let mut lines = Vec::new();
             for f in matches {
                 let url_name = f.file_name.replace(' ', "_");
@@ -256,24 +758,56 @@ async fn handle_command(bot: Arc<teloxide::Bot>, chat_id: ChatId, queue: FileQue
             }
             let _ = bot.send_message(chat_id, lines.join("\n")).await;
         }
+        Command::UploadLink(hours) => {
+            // Whoever opens a link first gets it, so it is never posted where others can see it
+            if !chat_id.is_user() {
+                let _ = bot.send_message(chat_id, "Ask for upload links in a private chat with the bot, everyone here could use them").await;
+                return;
+            }
+            let ttl = match hours.trim() {
+                "" => DEFAULT_UPLOAD_TOKEN_TTL,
+                h => match h.parse::<u64>() {
+                    Ok(h) if h > 0 => Duration::from_secs(h.saturating_mul(60 * 60)),
+                    _ => {
+                        let _ = bot.send_message(chat_id, "Usage: /uploadlink [hours]").await;
+                        return;
+                    }
+                },
+            };
+            let token = issue_upload_token(ttl).await;
+            let url = build_upload_url(&config.file_domain(), &token);
+            let _ = bot.send_message(chat_id, format!(
+                "One-time upload link (valid for {} hour(s), works in one browser):\n{}",
+                ttl.min(MAX_UPLOAD_TOKEN_TTL).as_secs() / 3600, url
+            )).await;
+        }
+        Command::ApiKey(args) => {
+            handle_api_key_command(bot.clone(), chat_id, args).await;
+        }
+        Command::Repair(id) => {
+            handle_repair_command(bot.clone(), chat_id, Arc::clone(config), id.trim()).await;
+        }
+        cmd @ (Command::Allow(_) | Command::Deny(_) | Command::Ban(_) | Command::Role(_) | Command::Permissions) => {
+            let reply = handle_permissions_command(permissions, chat_id, from, cmd).await;
+            let _ = bot.send_message(chat_id, reply).await;
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::bot::{Bot, TeloxideBot};
-    use shared::chat_config::PermissionsConfig;
-    use shared::config::Config;
-    use std::env;
+    use crate::bot::{ban_until, check_edit_scope, check_global_admin, record_block_notice, parse_chat_scope, parse_user, required_role, Bot, Command, TeloxideBot};
+    use teloxide::types::ChatId;
+    use shared::chat_config::{PermissionsConfig, Role};
+    use shared::config::{Config, SharedConfig, Sources};
+    use std::collections::HashMap;
     use std::sync::Arc;
+    use std::time::Duration;
     use tokio::sync::Mutex;
 
     #[tokio::test]
-    #[serial_test::serial]
     async fn test_teloxide_bot_new() {
-        env::set_var("BOT_TOKEN", "test_token");
-
-        let config = Arc::new(Config::new());
+        let config = SharedConfig::new(Config::from_sources(&Sources::from_pairs(&[("BOT_TOKEN", "test_token")])));
         let permissions = Arc::new(Mutex::new(PermissionsConfig::init_allow_all()));
         let queue = Arc::new(Mutex::new(Vec::new()));
 
@@ -286,7 +820,94 @@ mod tests {
 
         assert_eq!(bot.get_teloxide_bot().token(), "test_token");
         assert_eq!(bot.get_teloxide_bot().api_url().as_str(), "https://api.telegram.org/");
+    }
+
+    #[test]
+    fn test_required_role() {
+        assert_eq!(required_role(&Command::Find { query: "a".to_owned() }), Role::Viewer);
+        assert_eq!(required_role(&Command::UploadLink(String::new())), Role::Uploader);
+        assert_eq!(required_role(&Command::Edit { id: "a".to_owned(), new_name: "b".to_owned() }), Role::Admin);
+        assert_eq!(required_role(&Command::ClearQueue), Role::Admin);
+        assert_eq!(required_role(&Command::Delete("a".to_owned())), Role::Admin);
+    }
+
+    #[test]
+    fn test_index_commands_need_global_admin() {
+        let mut config = PermissionsConfig::init_closed();
+        config.set_role("1", None, Role::Admin);
+        config.set_role("2", Some("-100"), Role::Admin);
+
+        let commands = [
+            Command::Delete("a".to_owned()),
+            Command::Edit { id: "a".to_owned(), new_name: "b".to_owned() },
+            Command::ClearQueue,
+            Command::Repair(String::new()),
+            Command::ApiKey("list".to_owned()),
+        ];
+
+        for cmd in &commands {
+            assert!(check_global_admin(&config, "1", None, cmd).is_ok());
+            assert!(check_global_admin(&config, "2", None, cmd).is_err());
+        }
+
+        // Chat admins keep the commands that only touch their chat
+        assert!(check_global_admin(&config, "2", None, &Command::Permissions).is_ok());
+        assert!(check_global_admin(&config, "2", None, &Command::List).is_ok());
+    }
+
+    #[test]
+    fn test_parse_permission_arguments() {
+        assert_eq!(parse_user("123"), Ok("123".to_owned()));
+        assert_eq!(parse_user("*"), Ok("*".to_owned()));
+        assert_eq!(parse_user("@someone"), Ok("@someone".to_owned()));
+        assert!(parse_user("someone").is_err());
+        assert!(parse_user("@").is_err());
+
+        assert_eq!(parse_chat_scope(ChatId(-100), None), Ok(None));
+        assert_eq!(parse_chat_scope(ChatId(-100), Some("here")), Ok(Some("-100".to_owned())));
+        assert_eq!(parse_chat_scope(ChatId(-100), Some("-200")), Ok(Some("-200".to_owned())));
+        assert!(parse_chat_scope(ChatId(-100), Some("admin")).is_err());
+    }
 
-        env::remove_var("BOT_TOKEN")
+    #[test]
+    fn test_ban_until() {
+        assert_eq!(ban_until(100, Duration::from_secs(60)), Ok(160));
+        assert_eq!(ban_until(u64::MAX - 10, Duration::from_secs(60)), Ok(u64::MAX));
+        assert!(ban_until(100, Duration::from_secs(u64::MAX)).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_record_block_notice() {
+        let mut notices = HashMap::new();
+
+        assert!(record_block_notice(&mut notices, (-100, 1), Some(200), 100));
+        assert!(!record_block_notice(&mut notices, (-100, 1), Some(200), 150));
+        // A new ban is announced again
+        assert!(record_block_notice(&mut notices, (-100, 1), Some(300), 150));
+
+        assert!(record_block_notice(&mut notices, (-100, 2), None, 150));
+        assert!(!record_block_notice(&mut notices, (-100, 2), None, 1000));
+
+        // Ended bans are forgotten
+        record_block_notice(&mut notices, (-100, 3), None, 1000);
+        assert!(!notices.contains_key(&(-100, 1)));
+        assert_eq!(notices.len(), 2);
+    }
+
+    #[test]
+    fn test_chat_admin_cannot_edit_other_scopes() {
+        let mut config = PermissionsConfig::init_closed();
+        config.set_role("1", None, Role::Admin);
+        config.set_role("2", Some("-100"), Role::Admin);
+
+        let here = Some("-100".to_owned());
+        let other = Some("-200".to_owned());
+
+        assert!(check_edit_scope(&config, ChatId(-100), "2", None, &here).is_ok());
+        assert!(check_edit_scope(&config, ChatId(-100), "2", None, &None).is_err());
+        assert!(check_edit_scope(&config, ChatId(-100), "2", None, &other).is_err());
+
+        assert!(check_edit_scope(&config, ChatId(-100), "1", None, &None).is_ok());
+        assert!(check_edit_scope(&config, ChatId(-100), "1", None, &other).is_ok());
+    }
+}
@@ -1,4 +1,7 @@
 pub mod bot;
 pub mod queue;
 pub mod process_message;
+pub mod storage;
 
+pub mod membership;
+pub mod repair;
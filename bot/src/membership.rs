@@ -0,0 +1,83 @@
+// Resolving group rules in permissions.json through `get_chat_member`
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+use teloxide::prelude::Requester;
+use teloxide::types::{ChatId, ChatMemberKind, UserId};
+use tokio::sync::Mutex;
+
+use shared::chat_config::{GroupMembership, GroupRule, Role};
+
+/// How long a membership lookup is trusted; joining or leaving the group
+/// changes access after at most this long
+pub const GROUP_MEMBERSHIP_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Group and user id
+type MembershipKey = (i64, u64);
+
+/// Memberships with the time they were looked up
+static MEMBERSHIPS: Lazy<Mutex<HashMap<MembershipKey, (GroupMembership, Instant)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn membership_of(kind: &ChatMemberKind) -> GroupMembership {
+    if kind.is_privileged() {
+        GroupMembership::Admin
+    } else if kind.is_present() {
+        GroupMembership::Member
+    } else {
+        GroupMembership::Outside
+    }
+}
+
+async fn lookup_membership(bot: &teloxide::Bot, group: i64, user_id: u64) -> GroupMembership {
+    {
+        let mut memberships = MEMBERSHIPS.lock().await;
+        let now = Instant::now();
+        memberships.retain(|_, (_, at)| now.duration_since(*at) < GROUP_MEMBERSHIP_TTL);
+
+        if let Some((membership, _)) = memberships.get(&(group, user_id)) {
+            return *membership;
+        }
+    }
+
+    let membership = match bot.get_chat_member(ChatId(group), UserId(user_id)).await {
+        Ok(member) => membership_of(&member.kind),
+        Err(e) => {
+            // Usually the bot is not in the group; treated as outside until the TTL runs out
+            warn!("Failed to look up user {} in group {}: {}", user_id, group, e);
+            GroupMembership::Outside
+        }
+    };
+
+    debug!("User {} in group {}: {:?}", user_id, group, membership);
+
+    MEMBERSHIPS.lock().await.insert((group, user_id), (membership, Instant::now()));
+
+    membership
+}
+
+/// Highest role the user gets from `rules`, checking the most powerful rules first
+pub async fn group_role(bot: &teloxide::Bot, user_id: u64, mut rules: Vec<GroupRule>) -> Option<Role> {
+    rules.sort_by_key(|rule| std::cmp::Reverse(rule.role));
+
+    for rule in rules {
+        if rule.matches(lookup_membership(bot, rule.group, user_id).await) {
+            return Some(rule.role);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_membership_of() {
+        assert_eq!(membership_of(&ChatMemberKind::Member), GroupMembership::Member);
+        assert_eq!(membership_of(&ChatMemberKind::Left), GroupMembership::Outside);
+    }
+}
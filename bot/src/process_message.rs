@@ -24,11 +24,7 @@ fn get_url_from_message(msg: &Message) -> Option<String> {
     fn extract_first_link(text: &str) -> Option<String> {
         let link_regex = Regex::new(r"https?://\S+").unwrap();
 
-        if let Some(mat) = link_regex.find(text) {
-            Some(mat.as_str().to_string())
-        } else {
-            None
-        }
+        link_regex.find(text).map(|mat| mat.as_str().to_string())
     }
 
     if let Some(text) = msg.text() {
@@ -76,11 +72,7 @@ pub async fn process_message(
         Some((Some(animation.file.id.clone()), animation.file_name.clone(), None))
     } else if let Some(text) = msg_copy.text() {
         if text.starts_with("/url") {
-            if let Some(url) = get_url_from_message(&msg_copy) {
-                Some((None, None, Some(url)))
-            } else {
-                None
-            }
+            get_url_from_message(&msg_copy).map(|url| (None, None, Some(url)))
         } else {
             None
         }
@@ -1,9 +1,10 @@
 use crate::bot::TeloxideBot;
 use log::{debug, error, info, warn};
 use nanoid::nanoid;
-use shared::config::Config;
 use shared::file_storage::{save_file_metadata, FileMetadata};
-use shared::link_utils::build_url_path;
+use shared::html_utils::escape_html;
+use shared::link_utils::build_download_url;
+use shared::utils::human_size;
 use std::error::Error;
 use std::fmt::Display;
 use std::sync::Arc;
@@ -54,7 +55,7 @@ impl FileQueueItem {
     /// Human-readable summary for queue display
     pub fn summary(&self) -> String {
         if let Some(name) = &self.file_name {
-            return format!("{}", name);
+            return name.to_string();
         }
         if let Some(url) = &self.url {
             let short = if url.len() > 48 { format!("{}…", &url[..48]) } else { url.clone() };
@@ -92,7 +93,7 @@ pub async fn process_queue(
     file_queue: FileQueueType,
     mut rx: Receiver<()>,
 ) -> Result<(), Box<dyn Error>> {
-    Ok(while let Some(()) = rx.recv().await {
+    while let Some(()) = rx.recv().await {
         let queue_item = {
             let queue = file_queue.lock().await;
 
@@ -162,7 +163,9 @@ pub async fn process_queue(
         }
 
         info!("Removed item from queue. Remaining items in queue: {}", queue.len());
-    })
+    }
+
+    Ok(())
 }
 
 
@@ -174,7 +177,7 @@ async fn forward_file_to_storage_channel(
     info!("Forwarding file to storage channel. File ID: {}", file_id);
 
     // Get storage channel ID from config
-    let storage_channel_id = Config::instance().await.storage_channel_id()
+    let storage_channel_id = bot.config().storage_channel_id()
         .map_err(|e| format!("Storage channel not configured: {}", e))?;
 
     // Generate unique ID for this file
@@ -297,7 +300,7 @@ async fn download_and_store_file_from_url(
     info!("Downloading file from URL: {}", url);
 
     // Get storage channel ID from config
-    let storage_channel_id = Config::instance().await.storage_channel_id()
+    let storage_channel_id = bot.config().storage_channel_id()
         .map_err(|e| format!("Storage channel not configured: {}", e))?;
 
     // Download the file
@@ -310,7 +313,7 @@ async fn download_and_store_file_from_url(
         .and_then(|v| v.to_str().ok())
         .and_then(|v| v.split("filename=").nth(1))
         .map(|v| v.trim_matches('"').to_string())
-        .or_else(|| url.split('/').last().map(|name| name.to_string()))
+        .or_else(|| url.split('/').next_back().map(|name| name.to_string()))
         .filter(|name| !name.is_empty())
         .ok_or("Could not determine file name")?;
 
@@ -381,7 +384,7 @@ async fn generate_final_file_name(queue_item: &FileQueueItem, file_path_or_name:
     match name {
         Some(name) => format!("{}_{}", id, name),
         None => {
-            let file_name = file_path_or_name.split('/').last().unwrap_or("file");
+            let file_name = file_path_or_name.split('/').next_back().unwrap_or("file");
             format!("{}_{}", id, file_name)
         }
     }
@@ -429,16 +432,18 @@ async fn get_file_info(bot: Arc<TeloxideBot>, id: &String) -> Result<(String, u3
 // }
 //
 
-fn human_size(bytes: u64) -> String {
-    const KB: u64 = 1024;
-    const MB: u64 = KB * 1024;
-    const GB: u64 = MB * 1024;
-    match bytes {
-        b if b >= GB => format!("{:.2} GB", b as f64 / GB as f64),
-        b if b >= MB => format!("{:.2} MB", b as f64 / MB as f64),
-        b if b >= KB => format!("{:.2} KB", b as f64 / KB as f64),
-        _ => format!("{} bytes", bytes),
-    }
+/// Success message sent in Telegram HTML parse mode; the name and link are escaped
+/// so that names like `<b>.txt` or `a&b.pdf` neither break nor alter the markup
+fn file_link_message(file_name: &str, size_str: &str, url: &str) -> String {
+    let url = escape_html(url);
+
+    format!(
+        "✅ <b>File uploaded successfully!</b>\n\n📁 <b>File:</b> {}\n📊 <b>Size:</b> {}\n\n🔗 <b>Download Link:</b>\n<a href=\"{}\">{}</a>",
+        escape_html(file_name),
+        size_str,
+        url,
+        url
+    )
 }
 
 async fn edit_message_with_file_link(
@@ -448,23 +453,15 @@ async fn edit_message_with_file_link(
     file_name: &str,
     file_size: u32,
 ) -> Result<(), String> {
-    let file_domain = Config::instance().await.file_domain();
-    // Build full URL path using shared util (id + url-safe filename)
-    let full_path = build_url_path(unique_id, file_name);
-    // Add auto-close parameter for better UX (closes tab after download starts)
-    let full_url_with_close = format!("{}{}?close=1", file_domain, full_path);
+    let file_domain = bot.config().file_domain();
+    // Full link with url-safe filename and auto-close parameter (closes tab after download starts)
+    let full_url_with_close = build_download_url(&file_domain, unique_id, file_name);
     info!("Generated download link: {}", full_url_with_close);
     let size_str = human_size(file_size as u64);
     let edit_result = bot.get_teloxide_bot().edit_message_text(
         queue_item.message.chat.id,
         queue_item.queue_message.id,
-        format!(
-            "✅ <b>File uploaded successfully!</b>\n\n📁 <b>File:</b> {}\n📊 <b>Size:</b> {}\n\n🔗 <b>Download Link:</b>\n<a href=\"{}\">{}</a>",
-            file_name,
-            size_str,
-            full_url_with_close,
-            full_url_with_close
-        ),
+        file_link_message(file_name, &size_str, &full_url_with_close),
     )
         .parse_mode(ParseMode::Html)
         .await;
@@ -476,3 +473,17 @@ async fn edit_message_with_file_link(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_link_message_escapes_name_and_url() {
+        let message = file_link_message("<b>x</b> & \"y\".txt", "1.0 KB", "https://example.com/files/abc_a&b.txt?close=1");
+
+        assert!(message.contains("<b>File:</b> &lt;b&gt;x&lt;/b&gt; &amp; &quot;y&quot;.txt\n"));
+        assert!(message.contains("<a href=\"https://example.com/files/abc_a&amp;b.txt?close=1\">"));
+        assert!(!message.contains("<b>x</b>"));
+    }
+}
@@ -0,0 +1,269 @@
+// Re-resolving stored files whose Bot API file id stopped working or whose
+// storage channel message id was never recorded
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use teloxide::prelude::Requester;
+use teloxide::types::{ChatId, Message, MessageId};
+use teloxide::{ApiError, RequestError};
+use tokio::sync::Mutex;
+
+use shared::config::Config;
+use shared::file_storage::{list_all_files, save_file_metadata, FileMetadata};
+
+/// A record that could not be repaired is not retried by downloads for this long
+const REPAIR_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// Pause between records during a bulk repair, to stay clear of Telegram flood limits
+const BULK_REPAIR_DELAY: Duration = Duration::from_millis(300);
+
+static FAILED_REPAIRS: Lazy<Mutex<HashMap<String, Instant>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, PartialEq)]
+pub enum RepairOutcome {
+    Healthy,
+    Repaired,
+    Failed(String),
+}
+
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    pub checked: usize,
+    pub healthy: usize,
+    pub repaired: usize,
+    pub failed: Vec<(String, String)>,
+}
+
+impl RepairReport {
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Checked {} files: {} healthy, {} repaired, {} failed",
+            self.checked, self.healthy, self.repaired, self.failed.len()
+        );
+
+        for (unique_id, reason) in self.failed.iter().take(20) {
+            summary.push_str(&format!("\n- {}: {}", unique_id, reason));
+        }
+
+        if self.failed.len() > 20 {
+            summary.push_str(&format!("\n(and {} more)", self.failed.len() - 20));
+        }
+
+        summary
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    messages: Vec<SearchMessage>,
+}
+
+#[derive(Deserialize)]
+struct SearchMessage {
+    message_id: i32,
+    caption: String,
+}
+
+fn file_id_of(message: &Message) -> Option<String> {
+    if let Some(doc) = message.document() {
+        Some(doc.file.id.clone())
+    } else if let Some(photo) = message.photo() {
+        photo.last().map(|p| p.file.id.clone())
+    } else if let Some(video) = message.video() {
+        Some(video.file.id.clone())
+    } else if let Some(animation) = message.animation() {
+        Some(animation.file.id.clone())
+    } else if let Some(audio) = message.audio() {
+        Some(audio.file.id.clone())
+    } else {
+        message.voice().map(|voice| voice.file.id.clone())
+    }
+}
+
+/// Whether the stored ids still lead to the file. Network errors are reported as
+/// errors so that a Telegram outage does not rewrite every record.
+async fn is_healthy(bot: &teloxide::Bot, metadata: &FileMetadata) -> Result<bool, String> {
+    if metadata.message_id.is_none() {
+        return Ok(false);
+    }
+
+    // Uploaded through FastTelethon; only the message id is used for downloads
+    if metadata.telegram_file_id.is_empty() {
+        return Ok(true);
+    }
+
+    match bot.get_file(&metadata.telegram_file_id).await {
+        Ok(_) => Ok(true),
+        Err(RequestError::Api(ApiError::Unknown(description))) if description == "Bad Request: file is too big" => Ok(true),
+        Err(RequestError::Api(ApiError::FileIdInvalid | ApiError::WrongFileId | ApiError::WrongFileIdOrUrl)) => Ok(false),
+        Err(e) => Err(format!("Telegram error: {}", e)),
+    }
+}
+
+/// Find the storage channel message captioned with the unique id through FastTelethon
+pub(crate) async fn find_message_by_caption(config: &Config, channel_id: i64, unique_id: &str) -> Result<Option<i32>, String> {
+    let url = format!("{}/search/{}", config.fasttelethon_url(), channel_id);
+
+    let response = reqwest::Client::new()
+        .get(&url)
+        .query(&[("q", unique_id)])
+        .send()
+        .await
+        .map_err(|e| format!("FastTelethon is unreachable: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("FastTelethon search failed with status {}", response.status()));
+    }
+
+    let result: SearchResponse = response.json()
+        .await
+        .map_err(|e| format!("Invalid FastTelethon search response: {}", e))?;
+
+    Ok(result.messages.into_iter()
+        .find(|m| m.caption.trim() == unique_id)
+        .map(|m| m.message_id))
+}
+
+/// Get a fresh Bot API file id for a storage channel message by forwarding it
+/// inside the channel and deleting the copy right away
+async fn resolve_file_id(bot: &teloxide::Bot, channel_id: i64, message_id: i32, unique_id: &str) -> Result<String, String> {
+    let channel = ChatId(channel_id);
+
+    let forwarded = bot.forward_message(channel, channel, MessageId(message_id))
+        .await
+        .map_err(|e| format!("failed to read message {}: {}", message_id, e))?;
+
+    if let Err(e) = bot.delete_message(channel, forwarded.id).await {
+        warn!("Failed to delete temporary copy of message {}: {}", message_id, e);
+    }
+
+    if forwarded.caption().map(str::trim) != Some(unique_id) {
+        return Err(format!("message {} does not belong to this file", message_id));
+    }
+
+    file_id_of(&forwarded).ok_or_else(|| format!("message {} has no file", message_id))
+}
+
+/// Re-resolve a record from the storage channel and save the updated metadata
+pub async fn repair_file(bot: &teloxide::Bot, config: &Config, metadata: &FileMetadata) -> Result<FileMetadata, String> {
+    let channel_id = config.storage_channel_id()?;
+
+    let resolved = match metadata.message_id {
+        Some(message_id) => match resolve_file_id(bot, channel_id, message_id, &metadata.unique_id).await {
+            Ok(file_id) => Ok((message_id, file_id)),
+            Err(e) => {
+                warn!("Stored message of {} is unusable ({}), searching by caption", metadata.unique_id, e);
+                Err(e)
+            }
+        },
+        None => Err("no message id recorded".to_string()),
+    };
+
+    let (message_id, file_id) = match resolved {
+        Ok(resolved) => resolved,
+        Err(_) => {
+            let message_id = find_message_by_caption(config, channel_id, &metadata.unique_id).await?
+                .ok_or_else(|| "no message with this id in the storage channel".to_string())?;
+            let file_id = resolve_file_id(bot, channel_id, message_id, &metadata.unique_id).await?;
+
+            (message_id, file_id)
+        }
+    };
+
+    let repaired = FileMetadata {
+        telegram_file_id: file_id,
+        message_id: Some(message_id),
+        ..metadata.clone()
+    };
+
+    save_file_metadata(repaired.clone()).await?;
+
+    info!("Repaired {} (message {})", repaired.unique_id, message_id);
+
+    Ok(repaired)
+}
+
+/// Repair used by downloads: skipped for records that failed recently, so a file
+/// that is really gone does not cost Telegram calls on every request
+pub async fn repair_file_on_demand(bot: &teloxide::Bot, config: &Config, metadata: &FileMetadata) -> Option<FileMetadata> {
+    {
+        let mut failed = FAILED_REPAIRS.lock().await;
+        let now = Instant::now();
+        failed.retain(|_, at| now.duration_since(*at) < REPAIR_BACKOFF);
+
+        if failed.contains_key(&metadata.unique_id) {
+            return None;
+        }
+    }
+
+    match repair_file(bot, config, metadata).await {
+        Ok(repaired) => Some(repaired),
+        Err(e) => {
+            warn!("Failed to repair {}: {}", metadata.unique_id, e);
+            FAILED_REPAIRS.lock().await.insert(metadata.unique_id.clone(), Instant::now());
+
+            None
+        }
+    }
+}
+
+pub async fn check_and_repair(bot: &teloxide::Bot, config: &Config, metadata: &FileMetadata) -> RepairOutcome {
+    match is_healthy(bot, metadata).await {
+        Ok(true) => RepairOutcome::Healthy,
+        Ok(false) => match repair_file(bot, config, metadata).await {
+            Ok(_) => RepairOutcome::Repaired,
+            Err(e) => RepairOutcome::Failed(e),
+        },
+        Err(e) => RepairOutcome::Failed(e),
+    }
+}
+
+/// Check every record and repair the broken ones
+pub async fn repair_all(bot: &teloxide::Bot, config: &Config) -> RepairReport {
+    let mut files = list_all_files().await;
+    files.sort_by_key(|f| f.uploaded_at);
+
+    let mut report = RepairReport::default();
+
+    info!("Checking {} files for broken storage references", files.len());
+
+    for metadata in files {
+        report.checked += 1;
+
+        match check_and_repair(bot, config, &metadata).await {
+            RepairOutcome::Healthy => report.healthy += 1,
+            RepairOutcome::Repaired => report.repaired += 1,
+            RepairOutcome::Failed(reason) => report.failed.push((metadata.unique_id.clone(), reason)),
+        }
+
+        tokio::time::sleep(BULK_REPAIR_DELAY).await;
+    }
+
+    info!("{}", report.summary());
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_summary() {
+        let report = RepairReport {
+            checked: 3,
+            healthy: 1,
+            repaired: 1,
+            failed: vec![("abcd1234".to_owned(), "no message with this id in the storage channel".to_owned())],
+        };
+
+        assert_eq!(
+            report.summary(),
+            "Checked 3 files: 1 healthy, 1 repaired, 1 failed\n- abcd1234: no message with this id in the storage channel"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{error, info};
+use nanoid::nanoid;
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+use shared::config::Config;
+use shared::file_storage::{save_file_metadata, FileMetadata};
+use teloxide::payloads::SendDocumentSetters;
+use teloxide::prelude::Requester;
+use teloxide::types::{ChatId, InputFile, MessageId};
+use teloxide::{ApiError, RequestError};
+
+use crate::repair::find_message_by_caption;
+use tokio::fs::{self, File};
+use tokio_util::io::ReaderStream;
+
+/// Largest file the Bot API accepts through `send_document`
+pub const BOT_API_UPLOAD_LIMIT: u64 = 50 * 1024 * 1024;
+
+/// Largest file Telegram accepts at all (MTProto upload through FastTelethon)
+pub const MAX_UPLOAD_SIZE: u64 = 2000 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct FastTelethonUploadResponse {
+    message_id: i32,
+}
+
+/// Store a file from local disk in the storage channel and save its metadata.
+/// Files up to `BOT_API_UPLOAD_LIMIT` are sent with `send_document`, larger ones go through FastTelethon.
+///
+/// # Arguments
+/// * `bot` - Bot instance
+/// * `config` - Current configuration
+/// * `path` - Local file to upload
+/// * `file_name` - Name the file is stored and served under
+/// * `mime_type` - MIME type, guessed from the file name if `None`
+/// # Returns
+/// * `Result` containing the saved metadata
+/// * `String` containing an error message
+pub async fn store_local_file(
+    bot: &teloxide::Bot,
+    config: &Config,
+    path: &Path,
+    file_name: &str,
+    mime_type: Option<String>,
+) -> Result<FileMetadata, String> {
+    let storage_channel_id = config.storage_channel_id()
+        .map_err(|e| format!("Storage channel not configured: {}", e))?;
+
+    let size = fs::metadata(path).await
+        .map_err(|e| format!("Failed to read file metadata: {}", e))?
+        .len();
+
+    if size > MAX_UPLOAD_SIZE {
+        return Err(format!("File is too large ({} bytes, limit is {} bytes)", size, MAX_UPLOAD_SIZE));
+    }
+
+    let unique_id = nanoid!(8);
+
+    let mime_type = mime_type.or_else(|| mime_guess::from_path(file_name).first().map(|m| m.to_string()));
+
+    let (telegram_file_id, message_id) = if size <= BOT_API_UPLOAD_LIMIT {
+        info!("Uploading {} ({} bytes) to storage channel via Bot API", file_name, size);
+
+        let uploaded_msg = bot
+            .send_document(
+                ChatId(storage_channel_id),
+                InputFile::file(path).file_name(file_name.to_owned()),
+            )
+            .caption(&unique_id)
+            .await
+            .map_err(|e| format!("Failed to upload to storage channel: {}", e))?;
+
+        let stored_file_id = uploaded_msg.document()
+            .ok_or("No document in uploaded message")?
+            .file.id.clone();
+
+        (stored_file_id, uploaded_msg.id.0)
+    } else {
+        info!("Uploading {} ({} bytes) to storage channel via FastTelethon", file_name, size);
+
+        // Bot API file ids are useless for files this large, downloads go through FastTelethon by message id
+        let message_id = upload_via_fasttelethon(&config.fasttelethon_url(), path, file_name, mime_type.as_deref(), size, &unique_id, storage_channel_id).await?;
+
+        (String::new(), message_id)
+    };
+
+    info!("File stored in channel with message ID: {}", message_id);
+
+    let metadata = FileMetadata {
+        unique_id,
+        telegram_file_id,
+        file_name: file_name.to_owned(),
+        mime_type,
+        file_size: size as u32,
+        uploaded_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        message_id: Some(message_id),
+    };
+
+    save_file_metadata(metadata.clone()).await
+        .map_err(|e| format!("Failed to save file metadata: {}", e))?;
+
+    info!("File metadata saved successfully");
+
+    Ok(metadata)
+}
+
+/// Delete the storage channel message of a file. `reindex` restores every captioned
+/// message, so a file is only gone once its message is. Records from before message
+/// ids were kept are looked up by caption.
+pub async fn delete_stored_file(bot: &teloxide::Bot, config: &Config, metadata: &FileMetadata) -> Result<(), String> {
+    let channel_id = config.storage_channel_id()?;
+
+    let message_id = match metadata.message_id {
+        Some(message_id) => message_id,
+        None => match find_message_by_caption(config, channel_id, &metadata.unique_id).await? {
+            Some(message_id) => message_id,
+            None => return Ok(()),
+        },
+    };
+
+    match bot.delete_message(ChatId(channel_id), MessageId(message_id)).await {
+        Ok(_) | Err(RequestError::Api(ApiError::MessageToDeleteNotFound)) => {
+            info!("Deleted message {} of {} from the storage channel", message_id, metadata.unique_id);
+
+            Ok(())
+        }
+        Err(e) => Err(format!("Failed to delete message {} from the storage channel: {}", message_id, e)),
+    }
+}
+
+/// Stream a file to the FastTelethon `/upload` endpoint, returns the message id in the storage channel
+async fn upload_via_fasttelethon(
+    fasttelethon_url: &str,
+    path: &Path,
+    file_name: &str,
+    mime_type: Option<&str>,
+    size: u64,
+    caption: &str,
+    channel_id: i64,
+) -> Result<i32, String> {
+    let upload_url = format!("{}/upload", fasttelethon_url);
+
+    let file = File::open(path).await
+        .map_err(|e| format!("Failed to open file for upload: {}", e))?;
+
+    let mut part = Part::stream_with_length(reqwest::Body::wrap_stream(ReaderStream::new(file)), size)
+        .file_name(file_name.to_owned());
+
+    if let Some(mime_type) = mime_type {
+        part = part.mime_str(mime_type)
+            .map_err(|e| format!("Invalid MIME type '{}': {}", mime_type, e))?;
+    }
+
+    let form = Form::new()
+        .part("file", part)
+        .text("caption", caption.to_owned());
+
+    let response = reqwest::Client::new()
+        .post(&upload_url)
+        .query(&[("channel_id", channel_id.to_string())])
+        .multipart(form)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to connect to FastTelethon: {}", e))?;
+
+    if !response.status().is_success() {
+        error!("FastTelethon upload returned error: {}", response.status());
+
+        return Err(format!("FastTelethon upload failed with status {}", response.status()));
+    }
+
+    let body: FastTelethonUploadResponse = response.json().await
+        .map_err(|e| format!("Invalid response from FastTelethon: {}", e))?;
+
+    Ok(body.message_id)
+}
@@ -1,7 +1,15 @@
+use crate::reindex::{reindex, Prefer};
 use crate::utils::send_command;
 use log::{error, info};
+use shared::api_keys;
+use shared::backup::{self, ImportMode};
+use shared::chat_config;
+use shared::config;
+use shared::file_storage;
+use shared::utils::fifo_has_reader;
 use structopt::StructOpt;
 
+pub mod reindex;
 pub mod utils;
 
 #[derive(StructOpt)]
@@ -29,6 +37,63 @@ pub enum Command {
     UpdatePermissions,
     #[structopt(about = "Shutting down the system")]
     Shutdown,
+    #[structopt(about = "Manages API keys for the HTTP server")]
+    ApiKey(ApiKeyCommand),
+    #[structopt(about = "Checks all stored files and re-resolves broken ones from the storage channel")]
+    Repair,
+    #[structopt(about = "Rebuilds the file mappings from the storage channel through FastTelethon")]
+    Reindex {
+        #[structopt(long, help = "Only show what would change")]
+        dry_run: bool,
+        #[structopt(long, default_value = "index", help = "Who wins when a record points at a different message: index or channel")]
+        prefer: String,
+    },
+    #[structopt(about = "Inspects the server configuration")]
+    Config(ConfigCommand),
+    #[structopt(about = "Checks a permissions file and prints who can do what where")]
+    ValidatePermissions {
+        #[structopt(long, default_value = chat_config::CONFIG_PATH, help = "Permissions file to check")]
+        file: String,
+    },
+    #[structopt(about = "Writes all file mappings, permissions and API keys into a backup archive")]
+    Export {
+        #[structopt(long, short, help = "Archive path, prints to stdout when omitted")]
+        output: Option<String>,
+    },
+    #[structopt(about = "Restores file mappings, permissions and API keys from a backup archive")]
+    Import {
+        /// Path of the archive written by `export`
+        path: String,
+        #[structopt(long, default_value = "merge", help = "merge: only add what is missing, replace: make the local state match the archive")]
+        mode: String,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum ApiKeyCommand {
+    #[structopt(about = "Creates a new API key and prints it once")]
+    Create {
+        /// Human-readable name of the key
+        name: String,
+        #[structopt(long, default_value = "read", help = "Comma separated scopes: read, upload, admin")]
+        scopes: String,
+        #[structopt(long, default_value = "60", help = "Requests per minute, 0 disables the limit")]
+        rate_limit: u32,
+    },
+    #[structopt(about = "Lists all API keys")]
+    List,
+    #[structopt(about = "Revokes an API key by id")]
+    Revoke {
+        id: String,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum ConfigCommand {
+    #[structopt(about = "Prints the resolved configuration from fileslink.toml and the environment, secrets masked")]
+    Show,
+    #[structopt(about = "Makes the server re-read fileslink.toml and apply the settings that can change at runtime")]
+    Reload,
 }
 
 pub struct CommandProcessor {
@@ -54,6 +119,289 @@ impl CommandProcessor {
                     Err(_) => error!("Failed to send command 'shutdown' to {}", self.path),
                 }
             }
+            Command::Repair => {
+                match send_command(&self.path, "repair").await {
+                    Ok(_) => info!("Command 'repair' sent to {}, results are written to the server log", self.path),
+                    Err(_) => error!("Failed to send command 'repair' to {}", self.path),
+                }
+            }
+            Command::ApiKey(command) => {
+                self.process_api_key_command(command).await;
+            }
+            Command::Reindex { dry_run, prefer } => {
+                self.process_reindex_command(dry_run, &prefer).await;
+            }
+            Command::Config(ConfigCommand::Show) => {
+                // Same sources as the server: `.env`, then `fileslink.toml`
+                config::load_env();
+
+                let config = config::Config::resolve();
+                println!("{}", config.show());
+
+                if !config.problems().is_empty() {
+                    std::process::exit(1);
+                }
+            }
+            Command::Config(ConfigCommand::Reload) => {
+                match send_command(&self.path, "reload_config").await {
+                    Ok(_) => info!("Command 'reload_config' sent to {}, results are written to the server log", self.path),
+                    Err(_) => error!("Failed to send command 'reload_config' to {}", self.path),
+                }
+            }
+            Command::ValidatePermissions { file } => {
+                if !self.process_validate_permissions_command(&file).await {
+                    std::process::exit(1);
+                }
+            }
+            Command::Export { output } => {
+                self.process_export_command(output).await;
+            }
+            Command::Import { path, mode } => {
+                self.process_import_command(&path, &mode).await;
+            }
+        }
+    }
+
+    /// Prints problems and the access matrix; returns whether the file is usable
+    async fn process_validate_permissions_command(&self, file: &str) -> bool {
+        let data = match tokio::fs::read_to_string(file).await {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Failed to read {}: {}", file, e);
+
+                return false;
+            }
+        };
+
+        let validation = match chat_config::validate_config(&data) {
+            Ok(validation) => validation,
+            Err(e) => {
+                println!("{}: {}", file, e);
+
+                return false;
+            }
+        };
+
+        if !validation.migration.is_empty() {
+            println!("{}: version {}, will be upgraded to {} on load", file, validation.migration.from, validation.migration.to);
+        }
+
+        for field in &validation.unknown_fields {
+            println!("{}: unknown field '{}' is ignored", file, field);
+        }
+
+        for warning in &validation.warnings {
+            println!("{}: {}", file, warning);
+        }
+
+        println!();
+        print!("{}", validation.config.access_matrix());
+
+        let rules = validation.config.group_rules();
+        if !rules.is_empty() {
+            println!();
+            println!("Group rules, checked against Telegram at runtime:");
+            for rule in rules {
+                println!("- {}", rule);
+            }
+        }
+
+        true
+    }
+
+    async fn process_export_command(&self, output: Option<String>) {
+        if let Err(e) = file_storage::init_file_storage().await {
+            error!("Failed to load file mappings: {}", e);
+
+            return;
+        }
+
+        let archive = match backup::create_backup().await {
+            Ok(archive) => archive,
+            Err(e) => {
+                error!("Failed to create backup: {}", e);
+
+                return;
+            }
+        };
+
+        let json = match serde_json::to_string_pretty(&archive) {
+            Ok(json) => json,
+            Err(e) => {
+                error!("Failed to serialize backup: {}", e);
+
+                return;
+            }
+        };
+
+        match output {
+            Some(output) => match tokio::fs::write(&output, json).await {
+                Ok(_) => info!("Exported {} files to {}", archive.files.len(), output),
+                Err(e) => error!("Failed to write {}: {}", output, e),
+            },
+            None => println!("{}", json),
+        }
+    }
+
+    async fn process_import_command(&self, path: &str, mode: &str) {
+        let mode = match mode.parse::<ImportMode>() {
+            Ok(mode) => mode,
+            Err(e) => {
+                error!("{}", e);
+
+                return;
+            }
+        };
+
+        let archive = match backup::read_backup(path).await {
+            Ok(archive) => archive,
+            Err(e) => {
+                error!("{}", e);
+
+                return;
+            }
+        };
+
+        // A running server owns the loaded state and would overwrite files written from here
+        if fifo_has_reader(&self.path) {
+            let path = match std::fs::canonicalize(path) {
+                Ok(path) => path.display().to_string(),
+                Err(e) => {
+                    error!("Failed to resolve {}: {}", path, e);
+
+                    return;
+                }
+            };
+            let command = format!("import {} {}", if mode == ImportMode::Replace { "replace" } else { "merge" }, path);
+
+            match send_command(&self.path, &command).await {
+                Ok(_) => info!("Command '{}' sent to {}, results are written to the server log", command, self.path),
+                Err(_) => error!("Failed to send command '{}' to {}", command, self.path),
+            }
+
+            return;
+        }
+
+        if let Err(e) = file_storage::init_file_storage().await {
+            error!("Failed to load file mappings: {}", e);
+
+            return;
+        }
+
+        match backup::import_backup(archive, mode).await {
+            Ok(summary) => println!("Imported {}: {}", path, summary.describe()),
+            Err(e) => error!("Failed to import backup: {}", e),
+        }
+    }
+
+    async fn process_api_key_command(&self, command: ApiKeyCommand) {
+        if let Err(e) = api_keys::init_api_keys().await {
+            error!("Failed to load API keys: {}", e);
+
+            return;
+        }
+
+        match command {
+            ApiKeyCommand::Create { name, scopes, rate_limit } => {
+                let scopes = match api_keys::parse_scopes(&scopes) {
+                    Ok(scopes) => scopes,
+                    Err(e) => {
+                        error!("{}", e);
+
+                        return;
+                    }
+                };
+
+                match api_keys::create_api_key(&name, scopes, rate_limit).await {
+                    Ok((key, secret)) => {
+                        println!("Created API key '{}' (id: {}, scopes: {})", key.name, key.id, key.scopes_string());
+                        println!("{}", secret);
+                        println!("Store this key now, it cannot be shown again.");
+                    }
+                    Err(e) => {
+                        error!("Failed to create API key: {}", e);
+
+                        return;
+                    }
+                }
+            }
+            ApiKeyCommand::List => {
+                for key in api_keys::list_api_keys().await {
+                    let status = if key.is_active() { "active" } else { "revoked" };
+
+                    println!("{}\t{}\t{}\t{}/min\t{}", key.id, key.name, key.scopes_string(), key.rate_limit, status);
+                }
+
+                return;
+            }
+            ApiKeyCommand::Revoke { id } => {
+                match api_keys::revoke_api_key(&id).await {
+                    Ok(true) => println!("Revoked API key {}", id),
+                    Ok(false) => {
+                        error!("No active API key with id {}", id);
+
+                        return;
+                    }
+                    Err(e) => {
+                        error!("Failed to revoke API key: {}", e);
+
+                        return;
+                    }
+                }
+            }
+        }
+
+        match send_command(&self.path, "reload_api_keys").await {
+            Ok(_) => info!("Command 'reload_api_keys' sent to {}", self.path),
+            Err(_) => error!("Failed to send command 'reload_api_keys' to {}", self.path),
+        }
+    }
+
+    async fn process_reindex_command(&self, dry_run: bool, prefer: &str) {
+        let prefer = match prefer.parse::<Prefer>() {
+            Ok(prefer) => prefer,
+            Err(e) => {
+                error!("{}", e);
+
+                return;
+            }
+        };
+
+        // A running server owns the mappings and would overwrite a copy written from here
+        if !dry_run && fifo_has_reader(&self.path) {
+            let command = format!("reindex {}", if prefer == Prefer::Channel { "channel" } else { "index" });
+
+            match send_command(&self.path, &command).await {
+                Ok(_) => info!("Command '{}' sent to {}, results are written to the server log", command, self.path),
+                Err(_) => error!("Failed to send command '{}' to {}", command, self.path),
+            }
+
+            return;
+        }
+
+        shared::config::load_env();
+
+        if let Err(e) = file_storage::init_file_storage().await {
+            error!("Failed to load file mappings: {}", e);
+
+            return;
+        }
+
+        let plan = match reindex(&config::Config::resolve(), prefer, !dry_run).await {
+            Ok(plan) => plan,
+            Err(e) => {
+                error!("Failed to reindex: {}", e);
+
+                return;
+            }
+        };
+
+        for line in plan.report(prefer) {
+            println!("{}", line);
+        }
+
+        if dry_run {
+            println!("Dry run, nothing was written");
         }
     }
-}
\ No newline at end of file
+}
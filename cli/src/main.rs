@@ -102,6 +102,24 @@ mod tests {
         delete_file(path).await;
     }
 
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_cli_repair() {
+        let binding = create_rnd_file().await;
+        let path = binding.as_str();
+
+        let mut cmd = Command::cargo_bin("fileslink-cli").unwrap();
+        cmd.arg("--path").arg(path).arg("repair");
+
+        cmd.assert().success();
+
+        let content = fs::read_to_string(path).unwrap();
+
+        assert_eq!(content, "repair\n");
+
+        delete_file(path).await;
+    }
+
     #[tokio::test]
     #[serial_test::serial]
     async fn test_cli_default_path() {
@@ -0,0 +1,314 @@
+// Rebuilding the file mappings from the storage channel. Every stored message is
+// captioned with the file's unique id, so the channel doubles as a copy of the index.
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use serde::Deserialize;
+use shared::config::Config;
+use shared::file_storage::{list_all_files, save_file_metadata_batch, FileMetadata};
+
+/// A file message in the storage channel as listed by FastTelethon
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChannelFile {
+    pub message_id: i32,
+    pub caption: String,
+    pub file_name: Option<String>,
+    pub file_size: u64,
+    pub mime_type: Option<String>,
+    pub timestamp: u64,
+}
+
+#[derive(Deserialize)]
+struct MessagesPage {
+    messages: Vec<ChannelFile>,
+    next_offset_id: Option<i32>,
+}
+
+/// Which side wins when the index and the channel disagree about a file's message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Prefer {
+    Index,
+    Channel,
+}
+
+impl FromStr for Prefer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "index" => Ok(Prefer::Index),
+            "channel" => Ok(Prefer::Channel),
+            other => Err(format!("Unknown conflict preference '{}', expected 'index' or 'channel'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReindexPlan {
+    /// Messages read from the channel
+    pub scanned: usize,
+    /// Records to write
+    pub records: Vec<FileMetadata>,
+    pub added: Vec<String>,
+    /// Existing records that only gained a message id
+    pub updated: Vec<String>,
+    pub unchanged: usize,
+    /// Unique id and a description of the disagreement
+    pub conflicts: Vec<(String, String)>,
+    /// Extra messages for an id that was already seen (newer messages win)
+    pub duplicates: Vec<(String, i32)>,
+    /// Messages whose caption is not a unique id
+    pub unrecognized: usize,
+    /// Records with no message in the channel
+    pub not_in_channel: Vec<String>,
+}
+
+impl ReindexPlan {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} added, {} updated, {} unchanged, {} conflicts, {} duplicate messages, {} messages without an id caption, {} records not found in the channel",
+            self.added.len(), self.updated.len(), self.unchanged, self.conflicts.len(),
+            self.duplicates.len(), self.unrecognized, self.not_in_channel.len()
+        )
+    }
+
+    /// One line per change and a closing summary
+    pub fn report(&self, prefer: Prefer) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        for record in self.records.iter().filter(|r| self.added.contains(&r.unique_id)) {
+            lines.push(format!("+ {} {} (message {})", record.unique_id, record.file_name, record.message_id.unwrap_or_default()));
+        }
+        for unique_id in &self.updated {
+            lines.push(format!("~ {} gains its message id", unique_id));
+        }
+        for (unique_id, conflict) in &self.conflicts {
+            let kept = if prefer == Prefer::Channel { "using channel" } else { "keeping index" };
+            lines.push(format!("! {}: {} ({})", unique_id, conflict, kept));
+        }
+        for (unique_id, message_id) in &self.duplicates {
+            lines.push(format!("= {}: ignoring older duplicate message {}", unique_id, message_id));
+        }
+        for unique_id in &self.not_in_channel {
+            lines.push(format!("? {} has no message in the channel", unique_id));
+        }
+
+        lines.push(format!("Scanned {} channel messages: {}", self.scanned, self.summary()));
+
+        lines
+    }
+}
+
+/// The unique id in a storage caption. Ids are nanoids, so anything with other
+/// characters or several words is a caption from elsewhere (e.g. "📁 name.pdf").
+pub fn parse_caption(caption: &str) -> Option<&str> {
+    let caption = caption.trim();
+
+    let valid = (6..=21).contains(&caption.len())
+        && caption.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if valid { Some(caption) } else { None }
+}
+
+fn metadata_from_channel(unique_id: &str, file: &ChannelFile) -> FileMetadata {
+    FileMetadata {
+        unique_id: unique_id.to_owned(),
+        // MTProto cannot produce a Bot API file id; downloads use the message id
+        // and `repair` fills this in for files the Bot API can serve
+        telegram_file_id: String::new(),
+        file_name: file.file_name.clone().unwrap_or_else(|| format!("file_{}", file.message_id)),
+        mime_type: file.mime_type.clone(),
+        file_size: file.file_size.min(u32::MAX as u64) as u32,
+        uploaded_at: file.timestamp,
+        message_id: Some(file.message_id),
+    }
+}
+
+/// Work out the changes without touching anything. `channel` is expected newest first.
+pub fn plan_reindex(existing: &[FileMetadata], channel: &[ChannelFile], prefer: Prefer) -> ReindexPlan {
+    let existing: HashMap<&str, &FileMetadata> = existing.iter().map(|m| (m.unique_id.as_str(), m)).collect();
+    let mut seen = HashSet::new();
+    let mut plan = ReindexPlan { scanned: channel.len(), ..ReindexPlan::default() };
+
+    for file in channel {
+        let unique_id = match parse_caption(&file.caption) {
+            Some(unique_id) => unique_id,
+            None => {
+                plan.unrecognized += 1;
+                continue;
+            }
+        };
+
+        if !seen.insert(unique_id.to_owned()) {
+            plan.duplicates.push((unique_id.to_owned(), file.message_id));
+            continue;
+        }
+
+        match existing.get(unique_id) {
+            None => {
+                plan.added.push(unique_id.to_owned());
+                plan.records.push(metadata_from_channel(unique_id, file));
+            }
+            Some(current) if current.message_id == Some(file.message_id) => plan.unchanged += 1,
+            Some(current) if current.message_id.is_none() => {
+                // Name and Bot API file id stay: renames only live in the index
+                plan.updated.push(unique_id.to_owned());
+                plan.records.push(FileMetadata { message_id: Some(file.message_id), ..(*current).clone() });
+            }
+            Some(current) => {
+                plan.conflicts.push((
+                    unique_id.to_owned(),
+                    format!("index has message {}, channel has message {}", current.message_id.unwrap_or_default(), file.message_id),
+                ));
+
+                if prefer == Prefer::Channel {
+                    plan.records.push(FileMetadata {
+                        message_id: Some(file.message_id),
+                        file_size: file.file_size.min(u32::MAX as u64) as u32,
+                        mime_type: file.mime_type.clone().or_else(|| current.mime_type.clone()),
+                        ..(*current).clone()
+                    });
+                }
+            }
+        }
+    }
+
+    let mut not_in_channel: Vec<String> = existing.keys()
+        .filter(|id| !seen.contains(**id))
+        .map(|id| id.to_string())
+        .collect();
+    not_in_channel.sort();
+    plan.not_in_channel = not_in_channel;
+
+    plan
+}
+
+/// Compare the loaded file mappings with the channel and, with `write`, merge the
+/// changes into them. Only the changed records are saved, so whoever owns the
+/// mappings (normally the server) keeps everything saved in the meantime.
+pub async fn reindex(config: &Config, prefer: Prefer, write: bool) -> Result<ReindexPlan, String> {
+    let channel = fetch_channel_files(config).await?;
+    let plan = plan_reindex(&list_all_files().await, &channel, prefer);
+
+    if write && !plan.records.is_empty() {
+        save_file_metadata_batch(plan.records.clone()).await?;
+    }
+
+    Ok(plan)
+}
+
+/// Walk the whole storage channel history through FastTelethon, newest first
+pub async fn fetch_channel_files(config: &Config) -> Result<Vec<ChannelFile>, String> {
+    let channel_id = config.storage_channel_id()?;
+    let url = format!("{}/messages/{}", config.fasttelethon_url(), channel_id);
+
+    let client = reqwest::Client::new();
+    let mut files = Vec::new();
+    let mut offset_id = 0;
+
+    loop {
+        let response = client.get(&url)
+            .query(&[("offset_id", offset_id), ("limit", 100)])
+            .send()
+            .await
+            .map_err(|e| format!("FastTelethon is unreachable at {}: {}", url, e))?;
+
+        if !response.status().is_success() {
+            return Err(format!("FastTelethon returned {} for {}", response.status(), url));
+        }
+
+        let page: MessagesPage = response.json()
+            .await
+            .map_err(|e| format!("Invalid FastTelethon response: {}", e))?;
+
+        files.extend(page.messages);
+
+        match page.next_offset_id {
+            Some(next) if next != offset_id => offset_id = next,
+            _ => break,
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel_file(message_id: i32, caption: &str) -> ChannelFile {
+        ChannelFile {
+            message_id,
+            caption: caption.to_owned(),
+            file_name: Some(format!("file{}.txt", message_id)),
+            file_size: 10,
+            mime_type: Some("text/plain".to_owned()),
+            timestamp: 1_700_000_000,
+        }
+    }
+
+    fn record(unique_id: &str, message_id: Option<i32>) -> FileMetadata {
+        FileMetadata {
+            unique_id: unique_id.to_owned(),
+            telegram_file_id: "BQAC".to_owned(),
+            file_name: "renamed.txt".to_owned(),
+            mime_type: None,
+            file_size: 10,
+            uploaded_at: 1,
+            message_id,
+        }
+    }
+
+    #[test]
+    fn test_parse_caption() {
+        assert_eq!(parse_caption(" K_zO5rG8\n"), Some("K_zO5rG8"));
+        assert_eq!(parse_caption("📁 report.pdf"), None);
+        assert_eq!(parse_caption("two words"), None);
+        assert_eq!(parse_caption(""), None);
+    }
+
+    #[test]
+    fn test_plan_reindex() {
+        let existing = vec![
+            record("same0001", Some(1)),
+            record("fill0002", None),
+            record("diff0003", Some(3)),
+            record("gone0004", Some(4)),
+        ];
+        let channel = vec![
+            channel_file(9, "new00005"),
+            channel_file(8, "diff0003"),
+            channel_file(7, "📁 legacy.bin"),
+            channel_file(6, "new00005"),
+            channel_file(2, "fill0002"),
+            channel_file(1, "same0001"),
+        ];
+
+        let plan = plan_reindex(&existing, &channel, Prefer::Index);
+
+        assert_eq!(plan.added, vec!["new00005"]);
+        assert_eq!(plan.updated, vec!["fill0002"]);
+        assert_eq!(plan.unchanged, 1);
+        assert_eq!(plan.conflicts.len(), 1);
+        assert_eq!(plan.duplicates, vec![("new00005".to_owned(), 6)]);
+        assert_eq!(plan.unrecognized, 1);
+        assert_eq!(plan.not_in_channel, vec!["gone0004"]);
+
+        let added = &plan.records[0];
+        assert_eq!((added.message_id, added.file_name.as_str()), (Some(9), "file9.txt"));
+
+        let updated = &plan.records[1];
+        assert_eq!((updated.message_id, updated.file_name.as_str()), (Some(2), "renamed.txt"));
+        assert_eq!(plan.records.len(), 2);
+    }
+
+    #[test]
+    fn test_plan_reindex_prefer_channel() {
+        let plan = plan_reindex(&[record("diff0003", Some(3))], &[channel_file(8, "diff0003")], Prefer::Channel);
+
+        assert_eq!(plan.conflicts.len(), 1);
+        assert_eq!(plan.records[0].message_id, Some(8));
+        assert_eq!(plan.records[0].file_name, "renamed.txt");
+    }
+}
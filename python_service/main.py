@@ -415,11 +415,13 @@ async def backup_session():
 @app.post("/upload")
 async def upload_large_file(
     file: UploadFile = File(...),
-    channel_id: Optional[str] = None
+    channel_id: Optional[str] = None,
+    caption: Optional[str] = Form(None)
 ):
     """
     Upload a large file to Telegram via MTProto (no size limit)
     Returns message_id for later retrieval
+    The caption defaults to the file name; FilesLink passes the file's unique_id
     """
     if not auth_state["is_authorized"]:
         raise HTTPException(status_code=503, detail="Telegram client not authorized. Visit /auth to authorize.")
@@ -470,7 +472,7 @@ async def upload_large_file(
             message = await client.send_file(
                 target_channel,
                 file=media,
-                caption=f"📁 {file.filename}"
+                caption=caption or f"📁 {file.filename}"
             )
         
         # Cleanup
@@ -622,6 +624,69 @@ async def get_file_info(channel_id: str, message_id: int):
         raise HTTPException(status_code=500, detail=f"Failed to get file info: {str(e)}")
 
 
+def describe_file_message(message):
+    """JSON description of a message that carries a file"""
+    return {
+        "message_id": message.id,
+        "caption": message.message or "",
+        "file_name": message.file.name,
+        "file_size": message.file.size,
+        "mime_type": message.file.mime_type,
+        "date": message.date.isoformat(),
+        "timestamp": int(message.date.timestamp()),
+    }
+
+
+@app.get("/messages/{channel_id}")
+async def list_messages(channel_id: str, offset_id: int = 0, limit: int = 100):
+    """
+    List file messages of a channel, newest first. Pass the returned
+    `next_offset_id` as `offset_id` to get the next (older) page.
+    """
+    if not client or not await client.is_user_authorized():
+        raise HTTPException(status_code=503, detail="Telegram client not ready")
+
+    try:
+        channel_id_int = int(channel_id)
+        peer = PeerChannel(utils.resolve_id(channel_id_int)[0])
+
+        messages = await client.get_messages(peer, offset_id=offset_id, limit=min(limit, 100))
+
+        return {
+            "messages": [describe_file_message(message) for message in messages if message and message.file],
+            "next_offset_id": messages[-1].id if len(messages) > 0 else None,
+        }
+
+    except Exception as e:
+        logger.error(f"Listing messages failed: {e}", exc_info=True)
+        raise HTTPException(status_code=500, detail=f"Listing messages failed: {str(e)}")
+
+
+@app.get("/search/{channel_id}")
+async def search_messages(channel_id: str, q: str, limit: int = 20):
+    """Find file messages whose caption contains `q` (used to re-resolve lost message ids)"""
+    if not client or not await client.is_user_authorized():
+        raise HTTPException(status_code=503, detail="Telegram client not ready")
+
+    try:
+        channel_id_int = int(channel_id)
+        peer = PeerChannel(utils.resolve_id(channel_id_int)[0])
+
+        messages = await client.get_messages(peer, search=q, limit=min(limit, 100))
+
+        return {
+            "messages": [
+                describe_file_message(message)
+                for message in messages
+                if message and message.file
+            ]
+        }
+
+    except Exception as e:
+        logger.error(f"Search failed: {e}", exc_info=True)
+        raise HTTPException(status_code=500, detail=f"Search failed: {str(e)}")
+
+
 if __name__ == "__main__":
     uvicorn.run(
         "main:app",
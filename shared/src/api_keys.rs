@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{debug, info};
+use nanoid::nanoid;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tokio::fs;
+use tokio::sync::RwLock;
+
+pub const API_KEYS_PATH: &str = "config/api_keys.json";
+
+/// Prefix of every issued key, makes leaked keys easy to spot in logs and repos
+const KEY_PREFIX: &str = "flk_";
+
+/// Default number of requests per minute allowed for a key
+pub const DEFAULT_RATE_LIMIT: u32 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiKeyScope {
+    Read,
+    Upload,
+    Admin,
+}
+
+impl ApiKeyScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyScope::Read => "read",
+            ApiKeyScope::Upload => "upload",
+            ApiKeyScope::Admin => "admin",
+        }
+    }
+}
+
+impl Display for ApiKeyScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ApiKeyScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "read" => Ok(ApiKeyScope::Read),
+            "upload" => Ok(ApiKeyScope::Upload),
+            "admin" => Ok(ApiKeyScope::Admin),
+            other => Err(format!("Unknown API key scope '{}' (expected read, upload or admin)", other)),
+        }
+    }
+}
+
+/// Parse a comma separated list of scopes, e.g. "read,upload"
+pub fn parse_scopes(value: &str) -> Result<Vec<ApiKeyScope>, String> {
+    let mut scopes = Vec::new();
+
+    for part in value.split(',').filter(|p| !p.trim().is_empty()) {
+        let scope = part.parse::<ApiKeyScope>()?;
+
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+
+    if scopes.is_empty() {
+        return Err("At least one scope is required".to_owned());
+    }
+
+    Ok(scopes)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    /// SHA-256 of the secret part of the key, the key itself is never stored
+    pub key_hash: String,
+    pub scopes: Vec<ApiKeyScope>,
+    /// Requests per minute, 0 disables the limit
+    pub rate_limit: u32,
+    pub created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<u64>,
+}
+
+impl ApiKey {
+    /// Admin keys implicitly carry every other scope
+    pub fn has_scope(&self, scope: ApiKeyScope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&ApiKeyScope::Admin)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none()
+    }
+
+    pub fn scopes_string(&self) -> String {
+        self.scopes.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(",")
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ApiKeyStorageData {
+    keys: HashMap<String, ApiKey>,
+}
+
+static API_KEYS: Lazy<RwLock<ApiKeyStorageData>> = Lazy::new(|| RwLock::new(ApiKeyStorageData::default()));
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn hash_secret(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Split a key of the form "flk_<id>.<secret>" into its id and secret
+fn split_key(key: &str) -> Option<(&str, &str)> {
+    let (id, secret) = key.strip_prefix(KEY_PREFIX)?.split_once('.')?;
+
+    if id.is_empty() || secret.is_empty() {
+        return None;
+    }
+
+    Some((id, secret))
+}
+
+async fn persist(storage: &ApiKeyStorageData) -> Result<(), String> {
+    if let Some(dir) = Path::new(API_KEYS_PATH).parent() {
+        fs::create_dir_all(dir)
+            .await
+            .map_err(|e| format!("Failed to create directory for API keys: {}", e))?;
+    }
+
+    let json = serde_json::to_string_pretty(storage)
+        .map_err(|e| format!("Failed to serialize API keys: {}", e))?;
+
+    fs::write(API_KEYS_PATH, json)
+        .await
+        .map_err(|e| format!("Failed to write API keys: {}", e))?;
+
+    debug!("API keys saved to '{}'", API_KEYS_PATH);
+
+    Ok(())
+}
+
+/// Load API keys from disk, replacing the in-memory set. Also used to reload after CLI changes.
+pub async fn init_api_keys() -> Result<(), String> {
+    if Path::new(API_KEYS_PATH).exists() {
+        let content = fs::read_to_string(API_KEYS_PATH)
+            .await
+            .map_err(|e| format!("Failed to read API keys: {}", e))?;
+
+        let data: ApiKeyStorageData = serde_json::from_str(&content)
+            .map_err(|e| format!("Failed to parse API keys: {}", e))?;
+
+        let mut storage = API_KEYS.write().await;
+        *storage = data;
+
+        info!("Loaded {} API key(s)", storage.keys.len());
+    } else {
+        info!("No API keys configured");
+    }
+
+    Ok(())
+}
+
+/// Create a new key. Returns the stored record and the plain key, which is only available here.
+pub async fn create_api_key(name: &str, scopes: Vec<ApiKeyScope>, rate_limit: u32) -> Result<(ApiKey, String), String> {
+    if scopes.is_empty() {
+        return Err("At least one scope is required".to_owned());
+    }
+
+    let mut storage = API_KEYS.write().await;
+
+    let mut id = nanoid!(8);
+    while storage.keys.contains_key(&id) {
+        id = nanoid!(8);
+    }
+
+    let secret = nanoid!(32);
+
+    let key = ApiKey {
+        id: id.clone(),
+        name: name.to_owned(),
+        key_hash: hash_secret(&secret),
+        scopes,
+        rate_limit,
+        created_at: now_secs(),
+        revoked_at: None,
+    };
+
+    storage.keys.insert(id.clone(), key.clone());
+    persist(&storage).await?;
+
+    info!("Created API key '{}' ({}) with scopes {}", key.name, key.id, key.scopes_string());
+
+    Ok((key, format!("{}{}.{}", KEY_PREFIX, id, secret)))
+}
+
+/// Revoke a key by id. Returns false if no active key with that id exists.
+pub async fn revoke_api_key(id: &str) -> Result<bool, String> {
+    let mut storage = API_KEYS.write().await;
+
+    match storage.keys.get_mut(id) {
+        Some(key) if key.is_active() => {
+            key.revoked_at = Some(now_secs());
+        }
+        _ => return Ok(false),
+    }
+
+    persist(&storage).await?;
+
+    info!("Revoked API key {}", id);
+
+    Ok(true)
+}
+
+pub async fn list_api_keys() -> Vec<ApiKey> {
+    let storage = API_KEYS.read().await;
+    let mut keys: Vec<ApiKey> = storage.keys.values().cloned().collect();
+    keys.sort_by_key(|k| k.created_at);
+    keys
+}
+
+/// Look up an active key matching the presented plain key
+pub async fn verify_api_key(key: &str) -> Option<ApiKey> {
+    let (id, secret) = split_key(key.trim())?;
+
+    let storage = API_KEYS.read().await;
+    let stored = storage.keys.get(id)?;
+
+    // Constant-time, so response timing does not reveal how much of the hash matched
+    let matches: bool = stored.key_hash.as_bytes().ct_eq(hash_secret(secret).as_bytes()).into();
+
+    if stored.is_active() && matches {
+        Some(stored.clone())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scopes() {
+        let scopes = parse_scopes("read, Upload,read").unwrap();
+
+        assert_eq!(scopes, vec![ApiKeyScope::Read, ApiKeyScope::Upload]);
+        assert!(parse_scopes("").is_err());
+        assert!(parse_scopes("read,write").is_err());
+    }
+
+    #[test]
+    fn test_admin_implies_all_scopes() {
+        let key = ApiKey {
+            id: "abc".to_owned(),
+            name: "test".to_owned(),
+            key_hash: hash_secret("secret"),
+            scopes: vec![ApiKeyScope::Admin],
+            rate_limit: DEFAULT_RATE_LIMIT,
+            created_at: 0,
+            revoked_at: None,
+        };
+
+        assert!(key.has_scope(ApiKeyScope::Read));
+        assert!(key.has_scope(ApiKeyScope::Upload));
+    }
+
+    #[test]
+    fn test_split_key() {
+        assert_eq!(split_key("flk_ab_cd-12.s3cr3t"), Some(("ab_cd-12", "s3cr3t")));
+        assert_eq!(split_key("ab_cd-12.s3cr3t"), None);
+        assert_eq!(split_key("flk_.s3cr3t"), None);
+        assert_eq!(split_key("flk_abc"), None);
+    }
+
+    #[test]
+    fn test_hash_secret_is_stable_hex() {
+        let hash = hash_secret("secret");
+
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_secret("secret"));
+        assert_ne!(hash, hash_secret("other"));
+    }
+
+    #[tokio::test]
+    async fn test_verify_api_key() {
+        let key = ApiKey {
+            id: "verify01".to_owned(),
+            name: "test".to_owned(),
+            key_hash: hash_secret("s3cr3t"),
+            scopes: vec![ApiKeyScope::Read],
+            rate_limit: DEFAULT_RATE_LIMIT,
+            created_at: 0,
+            revoked_at: None,
+        };
+        API_KEYS.write().await.keys.insert(key.id.clone(), key);
+
+        assert!(verify_api_key("flk_verify01.s3cr3t").await.is_some());
+        assert!(verify_api_key("flk_verify01.s3cr3x").await.is_none());
+        assert!(verify_api_key("flk_verify01.s3cr3").await.is_none());
+        assert!(verify_api_key("flk_unknown1.s3cr3t").await.is_none());
+
+        API_KEYS.write().await.keys.get_mut("verify01").unwrap().revoked_at = Some(1);
+        assert!(verify_api_key("flk_verify01.s3cr3t").await.is_none());
+    }
+}
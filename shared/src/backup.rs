@@ -0,0 +1,425 @@
+// Versioned backup archives of the file mappings and configuration
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::fs;
+
+use crate::api_keys::API_KEYS_PATH;
+use crate::chat_config::CONFIG_PATH;
+use crate::file_storage::{list_all_files, replace_all_file_metadata, save_file_metadata_batch, FileMetadata};
+
+const ARCHIVE_FORMAT: &str = "fileslink-backup";
+
+/// Bumped whenever the archive layout changes; older archives stay importable
+pub const ARCHIVE_VERSION: u32 = 1;
+
+const SNAPSHOT_PREFIX: &str = "fileslink-backup-";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupArchive {
+    pub format: String,
+    pub version: u32,
+    pub created_at: u64,
+    pub files: Vec<FileMetadata>,
+    /// Raw contents of `config/permissions.json`
+    pub permissions: Option<Value>,
+    /// Raw contents of `config/api_keys.json` (only key hashes, never secrets)
+    pub api_keys: Option<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportMode {
+    /// Add what is missing locally, keep everything that exists
+    Merge,
+    /// Make the local state exactly the archive
+    Replace,
+}
+
+impl FromStr for ImportMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merge" => Ok(ImportMode::Merge),
+            "replace" => Ok(ImportMode::Replace),
+            other => Err(format!("Unknown import mode '{}', expected 'merge' or 'replace'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub files_added: usize,
+    pub files_kept: usize,
+    pub files_removed: usize,
+    pub permissions_written: bool,
+    pub api_keys_written: bool,
+}
+
+impl ImportSummary {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} files added, {} already present, {} removed, permissions {}, API keys {}",
+            self.files_added, self.files_kept, self.files_removed,
+            if self.permissions_written { "updated" } else { "unchanged" },
+            if self.api_keys_written { "updated" } else { "unchanged" },
+        )
+    }
+}
+
+async fn read_json(path: &str) -> Result<Option<Value>, String> {
+    if !Path::new(path).exists() {
+        return Ok(None);
+    }
+
+    let content = fs::read_to_string(path).await
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+async fn write_json(path: &str, value: &Value) -> Result<(), String> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent).await
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+
+    let json = serde_json::to_string_pretty(value)
+        .map_err(|e| format!("Failed to serialize {}: {}", path, e))?;
+
+    fs::write(path, json).await
+        .map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+/// Snapshot of the loaded file mappings and the config files on disk
+pub async fn create_backup() -> Result<BackupArchive, String> {
+    let mut files = list_all_files().await;
+    files.sort_by(|a, b| a.uploaded_at.cmp(&b.uploaded_at).then_with(|| a.unique_id.cmp(&b.unique_id)));
+
+    Ok(BackupArchive {
+        format: ARCHIVE_FORMAT.to_owned(),
+        version: ARCHIVE_VERSION,
+        created_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        files,
+        permissions: read_json(CONFIG_PATH).await?,
+        api_keys: read_json(API_KEYS_PATH).await?,
+    })
+}
+
+pub fn parse_backup(content: &str) -> Result<BackupArchive, String> {
+    let archive: BackupArchive = serde_json::from_str(content)
+        .map_err(|e| format!("Not a valid backup archive: {}", e))?;
+
+    if archive.format != ARCHIVE_FORMAT {
+        return Err(format!("Not a FilesLink backup (format '{}')", archive.format));
+    }
+
+    if archive.version > ARCHIVE_VERSION {
+        return Err(format!(
+            "Backup version {} is newer than this build supports ({})",
+            archive.version, ARCHIVE_VERSION
+        ));
+    }
+
+    Ok(archive)
+}
+
+/// Maps in a config file whose missing entries a merge import adds, with how many
+/// levels deep: 2 also fills in entries missing inside an existing chat
+struct MergeSpec {
+    maps: &'static [(&'static [&'static str], usize)],
+    /// Lists whose missing items are appended
+    lists: &'static [&'static str],
+}
+
+const PERMISSIONS_MERGE: MergeSpec = MergeSpec {
+    maps: &[
+        (&["chats"], 1),
+        (&["roles", "users"], 1),
+        (&["roles", "chats"], 2),
+        (&["deny", "users"], 1),
+        (&["deny", "chats"], 2),
+        (&["usernames"], 1),
+    ],
+    lists: &["groups"],
+};
+
+const API_KEYS_MERGE: MergeSpec = MergeSpec {
+    maps: &[(&["keys"], 1)],
+    lists: &[],
+};
+
+/// Add the entries of `incoming` that are missing in `current`
+fn merge_missing(current: &mut Value, incoming: &Value, depth: usize) {
+    let (current, incoming) = match (current.as_object_mut(), incoming.as_object()) {
+        (Some(current), Some(incoming)) => (current, incoming),
+        _ => return,
+    };
+
+    for (key, value) in incoming {
+        match current.get_mut(key) {
+            None => {
+                current.insert(key.clone(), value.clone());
+            }
+            Some(existing) if depth > 1 => merge_missing(existing, value, depth - 1),
+            Some(_) => {}
+        }
+    }
+}
+
+/// The object at `path`, created when missing; `None` if something else is in the way
+fn object_at<'a>(mut value: &'a mut Value, path: &[&str]) -> Option<&'a mut Value> {
+    for field in path {
+        value = value.as_object_mut()?
+            .entry(field.to_string())
+            .or_insert_with(|| Value::Object(Default::default()));
+    }
+
+    value.is_object().then_some(value)
+}
+
+/// Merge mode: entries and rules missing locally are added, existing ones are kept
+fn merge_config(current: Option<Value>, incoming: &Value, spec: &MergeSpec) -> Option<Value> {
+    let mut current = match current {
+        None => return Some(incoming.clone()),
+        Some(current) => current,
+    };
+    let before = current.clone();
+
+    for (path, depth) in spec.maps {
+        let source = match path.iter().try_fold(incoming, |value, field| value.get(field)) {
+            Some(source) => source,
+            None => continue,
+        };
+
+        if let Some(target) = object_at(&mut current, path) {
+            merge_missing(target, source, *depth);
+        }
+    }
+
+    for field in spec.lists {
+        let items = match incoming.get(field).and_then(Value::as_array) {
+            Some(items) => items,
+            None => continue,
+        };
+
+        let target = match current.as_object_mut() {
+            Some(current) => current.entry(field.to_string()).or_insert_with(|| Value::Array(Vec::new())),
+            None => continue,
+        };
+
+        if let Some(target) = target.as_array_mut() {
+            for item in items {
+                if !target.contains(item) {
+                    target.push(item.clone());
+                }
+            }
+        }
+    }
+
+    if current != before { Some(current) } else { None }
+}
+
+/// Read and check an archive file
+pub async fn read_backup(path: &str) -> Result<BackupArchive, String> {
+    let content = fs::read_to_string(path).await
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+    parse_backup(&content).map_err(|e| format!("{}: {}", path, e))
+}
+
+pub async fn import_backup(archive: BackupArchive, mode: ImportMode) -> Result<ImportSummary, String> {
+    let mut summary = ImportSummary::default();
+    let existing = list_all_files().await;
+
+    match mode {
+        ImportMode::Replace => {
+            summary.files_removed = existing.iter()
+                .filter(|e| !archive.files.iter().any(|f| f.unique_id == e.unique_id))
+                .count();
+            summary.files_added = archive.files.len();
+
+            replace_all_file_metadata(archive.files).await?;
+
+            if let Some(permissions) = &archive.permissions {
+                write_json(CONFIG_PATH, permissions).await?;
+                summary.permissions_written = true;
+            }
+
+            if let Some(api_keys) = &archive.api_keys {
+                write_json(API_KEYS_PATH, api_keys).await?;
+                summary.api_keys_written = true;
+            }
+        }
+        ImportMode::Merge => {
+            let (new, kept): (Vec<FileMetadata>, Vec<FileMetadata>) = archive.files.into_iter()
+                .partition(|f| !existing.iter().any(|e| e.unique_id == f.unique_id));
+
+            summary.files_added = new.len();
+            summary.files_kept = kept.len();
+
+            if !new.is_empty() {
+                save_file_metadata_batch(new).await?;
+            }
+
+            if let Some(incoming) = &archive.permissions {
+                if let Some(merged) = merge_config(read_json(CONFIG_PATH).await?, incoming, &PERMISSIONS_MERGE) {
+                    write_json(CONFIG_PATH, &merged).await?;
+                    summary.permissions_written = true;
+                }
+            }
+
+            if let Some(incoming) = &archive.api_keys {
+                if let Some(merged) = merge_config(read_json(API_KEYS_PATH).await?, incoming, &API_KEYS_MERGE) {
+                    write_json(API_KEYS_PATH, &merged).await?;
+                    summary.api_keys_written = true;
+                }
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Write a snapshot into `dir` and keep only the newest `keep` snapshots
+pub async fn write_snapshot(dir: &str, keep: usize) -> Result<PathBuf, String> {
+    let archive = create_backup().await?;
+
+    fs::create_dir_all(dir).await
+        .map_err(|e| format!("Failed to create backup directory {}: {}", dir, e))?;
+
+    let path = Path::new(dir).join(format!("{}{}.json", SNAPSHOT_PREFIX, archive.created_at));
+    let partial = path.with_extension("json.part");
+
+    let json = serde_json::to_string_pretty(&archive)
+        .map_err(|e| format!("Failed to serialize backup: {}", e))?;
+
+    fs::write(&partial, json).await
+        .map_err(|e| format!("Failed to write {}: {}", partial.display(), e))?;
+    fs::rename(&partial, &path).await
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    info!("Backup of {} files written to {}", archive.files.len(), path.display());
+
+    rotate_snapshots(dir, keep).await;
+
+    Ok(path)
+}
+
+async fn rotate_snapshots(dir: &str, keep: usize) {
+    let mut read_dir = match fs::read_dir(dir).await {
+        Ok(read_dir) => read_dir,
+        Err(e) => {
+            warn!("Failed to list backup directory {}: {}", dir, e);
+            return;
+        }
+    };
+
+    let mut snapshots = Vec::new();
+
+    while let Ok(Some(entry)) = read_dir.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        let created_at = name.strip_prefix(SNAPSHOT_PREFIX)
+            .and_then(|rest| rest.strip_suffix(".json"))
+            .and_then(|ts| ts.parse::<u64>().ok());
+
+        if let Some(created_at) = created_at {
+            snapshots.push((created_at, entry.path()));
+        }
+    }
+
+    snapshots.sort();
+
+    let excess = snapshots.len().saturating_sub(keep.max(1));
+
+    for (_, path) in snapshots.into_iter().take(excess) {
+        match fs::remove_file(&path).await {
+            Ok(_) => info!("Removed old backup {}", path.display()),
+            Err(e) => warn!("Failed to remove old backup {}: {}", path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_backup_checks_format_and_version() {
+        let archive = json!({ "format": ARCHIVE_FORMAT, "version": 1, "created_at": 0, "files": [], "permissions": null, "api_keys": null });
+        assert!(parse_backup(&archive.to_string()).is_ok());
+
+        let newer = json!({ "format": ARCHIVE_FORMAT, "version": ARCHIVE_VERSION + 1, "created_at": 0, "files": [] });
+        assert!(parse_backup(&newer.to_string()).unwrap_err().contains("newer"));
+
+        let other = json!({ "format": "something-else", "version": 1, "created_at": 0, "files": [] });
+        assert!(parse_backup(&other.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_merge_config_keeps_existing_entries() {
+        let current = json!({ "allow_all": 1, "chats": { "-100": 1 } });
+        let incoming = json!({ "allow_all": 2, "chats": { "-100": 2, "-200": 3 } });
+
+        let merged = merge_config(Some(current.clone()), &incoming, &PERMISSIONS_MERGE).unwrap();
+        assert_eq!(merged, json!({ "allow_all": 1, "chats": { "-100": 1, "-200": 3 } }));
+
+        assert_eq!(merge_config(Some(merged.clone()), &incoming, &PERMISSIONS_MERGE), None);
+        assert_eq!(merge_config(None, &incoming, &PERMISSIONS_MERGE), Some(incoming));
+
+        // Roles, blocks, group rules and pinned usernames are merged the same way
+        let current = json!({
+            "chats": {},
+            "roles": { "users": { "1": "admin" }, "chats": { "-100": { "2": "viewer" } } },
+            "deny": { "users": { "3": {} }, "chats": {} },
+            "groups": [{ "group": -1001, "role": "uploader" }],
+            "usernames": { "alice": 5 }
+        });
+        let incoming = json!({
+            "roles": { "users": { "1": "viewer", "4": "uploader" }, "chats": { "-100": { "2": "admin", "6": "admin" }, "-200": { "7": "viewer" } } },
+            "deny": { "users": { "3": { "until": 100 }, "8": {} }, "chats": { "-100": { "9": { "until": 200 } } } },
+            "groups": [{ "group": -1001, "role": "uploader" }, { "group": -1002, "members": "admins", "role": "admin" }],
+            "usernames": { "alice": 6, "bob": 10 }
+        });
+
+        let merged = merge_config(Some(current), &incoming, &PERMISSIONS_MERGE).unwrap();
+        assert_eq!(merged, json!({
+            "chats": {},
+            "roles": { "users": { "1": "admin", "4": "uploader" }, "chats": { "-100": { "2": "viewer", "6": "admin" }, "-200": { "7": "viewer" } } },
+            "deny": { "users": { "3": {}, "8": {} }, "chats": { "-100": { "9": { "until": 200 } } } },
+            "groups": [{ "group": -1001, "role": "uploader" }, { "group": -1002, "members": "admins", "role": "admin" }],
+            "usernames": { "alice": 5, "bob": 10 }
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_rotate_snapshots() {
+        let dir = std::env::temp_dir().join(format!("fileslink-backup-test-{}", nanoid::nanoid!(8)));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for ts in [100, 200, 300] {
+            std::fs::write(dir.join(format!("{}{}.json", SNAPSHOT_PREFIX, ts)), "{}").unwrap();
+        }
+        std::fs::write(dir.join("unrelated.json"), "{}").unwrap();
+
+        rotate_snapshots(dir.to_str().unwrap(), 2).await;
+
+        let mut names: Vec<String> = std::fs::read_dir(&dir).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+
+        assert_eq!(names, vec!["fileslink-backup-200.json", "fileslink-backup-300.json", "unrelated.json"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
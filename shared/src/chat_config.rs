@@ -1,13 +1,217 @@
 use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
 
-use log::{debug, error};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tokio::fs;
+use tokio::sync::Mutex;
+
+use crate::config::Config;
+use crate::migrations::{backup_before_migration, log_migration, migrate, Migration, MigrationReport};
+
+pub const CONFIG_PATH: &str = "config/permissions.json";
+
+/// Append-only record of permission changes made through the bot
+pub const AUDIT_LOG_PATH: &str = "config/permissions_audit.log";
+
+/// Current version of `permissions.json`, bump together with a new entry in `MIGRATIONS`
+pub const PERMISSIONS_VERSION: u32 = 5;
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        description: "add format version and an empty chat list when missing",
+        apply: |value| {
+            if value.get("chats").is_none() {
+                value["chats"] = Value::Object(Default::default());
+            }
+
+            Ok(())
+        },
+    },
+    Migration {
+        from: 1,
+        description: "add empty role assignments",
+        apply: |value| {
+            if value.get("roles").is_none() {
+                value["roles"] = serde_json::json!({ "users": {}, "chats": {} });
+            }
+
+            Ok(())
+        },
+    },
+    Migration {
+        from: 2,
+        description: "add empty deny list",
+        apply: |value| {
+            if value.get("deny").is_none() {
+                value["deny"] = serde_json::json!({ "users": {}, "chats": {} });
+            }
+
+            Ok(())
+        },
+    },
+    Migration {
+        from: 3,
+        description: "add empty group rules",
+        apply: |value| {
+            if value.get("groups").is_none() {
+                value["groups"] = serde_json::json!([]);
+            }
+
+            Ok(())
+        },
+    },
+    Migration {
+        from: 4,
+        description: "add empty username pins",
+        apply: |value| {
+            if value.get("usernames").is_none() {
+                value["usernames"] = serde_json::json!({});
+            }
+
+            Ok(())
+        },
+    },
+];
+
+/// What a user may do, each role includes the ones before it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// List and search files
+    Viewer,
+    /// Upload files and get upload links
+    Uploader,
+    /// Delete and rename files, manage the queue, API keys and permissions
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Role::Viewer => "viewer",
+            Role::Uploader => "uploader",
+            Role::Admin => "admin",
+        };
 
-const CONFIG_PATH: &str = "config/permissions.json";
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "viewer" => Ok(Role::Viewer),
+            "uploader" => Ok(Role::Uploader),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("Unknown role '{}', expected viewer, uploader or admin", other)),
+        }
+    }
+}
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Explicit role assignments by user id; `"*"` stands for every user
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+struct RolesConfig {
+    #[serde(default)]
+    users: HashMap<String, Role>,
+    #[serde(default)]
+    chats: HashMap<String, HashMap<String, Role>>,
+}
+
+/// Who of a Telegram group a group rule covers
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupMembers {
+    /// Everyone currently in the group, admins included
+    #[default]
+    Members,
+    /// The owner and administrators of the group
+    Admins,
+}
+
+/// A user's standing in a group as reported by Telegram
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupMembership {
+    Outside,
+    Member,
+    Admin,
+}
+
+/// Grants a role to the members or admins of a Telegram group, e.g. a team chat
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GroupRule {
+    /// Id of the group whose members are checked; the bot has to be in it
+    pub group: i64,
+    #[serde(default)]
+    pub members: GroupMembers,
+    #[serde(default = "default_group_role")]
+    pub role: Role,
+    /// Only apply in this chat, in every chat when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat: Option<String>,
+}
+
+fn default_group_role() -> Role {
+    Role::Uploader
+}
+
+impl fmt::Display for GroupRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let who = match self.members {
+            GroupMembers::Members => "members",
+            GroupMembers::Admins => "admins",
+        };
+        let scope = self.chat.as_ref().map(|chat| format!(" in chat {}", chat)).unwrap_or_default();
+
+        write!(f, "{} of group {}: {}{}", who, self.group, self.role, scope)
+    }
+}
+
+impl GroupRule {
+    pub fn matches(&self, membership: GroupMembership) -> bool {
+        match self.members {
+            GroupMembers::Members => membership != GroupMembership::Outside,
+            GroupMembers::Admins => membership == GroupMembership::Admin,
+        }
+    }
+}
+
+/// A block on a user; takes precedence over every allow list and role
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DenyEntry {
+    /// Unix time the ban ends, permanent when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+}
+
+impl DenyEntry {
+    fn is_active(&self, now: u64) -> bool {
+        self.until.is_none_or(|until| until > now)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+struct DenyConfig {
+    #[serde(default)]
+    users: HashMap<String, DenyEntry>,
+    #[serde(default)]
+    chats: HashMap<String, HashMap<String, DenyEntry>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 #[derive(PartialEq)]
 enum UsersArrayConfig {
@@ -15,7 +219,7 @@ enum UsersArrayConfig {
     IntegerUser(i64),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 #[derive(PartialEq)]
 enum UsersConfig {
@@ -24,124 +228,687 @@ enum UsersConfig {
     ArrayUsers(Vec<UsersArrayConfig>),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl UsersConfig {
+    fn ids(&self) -> Vec<String> {
+        match self {
+            UsersConfig::SingleUser(id) => vec![id.to_string()],
+            UsersConfig::StringUsers(users) => users.split(',')
+                .map(|u| u.trim().to_owned())
+                .filter(|u| !u.is_empty())
+                .collect(),
+            UsersConfig::ArrayUsers(users) => users.iter().map(|user| match user {
+                UsersArrayConfig::StringUser(id) => id.trim().to_owned(),
+                UsersArrayConfig::IntegerUser(id) => id.to_string(),
+            }).collect(),
+        }
+    }
+
+    /// The same list without `user_id`, `None` when the user is not listed by id
+    fn without(&self, user_id: &str) -> Option<UsersConfig> {
+        let ids = self.ids();
+
+        if !ids.iter().any(|id| id == user_id) {
+            return None;
+        }
+
+        let remaining = ids.into_iter()
+            .filter(|id| id != user_id)
+            .map(|id| match id.parse::<i64>() {
+                Ok(id) => UsersArrayConfig::IntegerUser(id),
+                Err(_) => UsersArrayConfig::StringUser(id),
+            })
+            .collect();
+
+        Some(UsersConfig::ArrayUsers(remaining))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PermissionsConfig {
+    version: u32,
     allow_all: UsersConfig,
     chats: HashMap<String, UsersConfig>,
+    #[serde(default)]
+    roles: RolesConfig,
+    #[serde(default)]
+    deny: DenyConfig,
+    #[serde(default)]
+    groups: Vec<GroupRule>,
+    /// Ids pinned to the `@username` entries, keyed by lowercase username
+    #[serde(default)]
+    usernames: HashMap<String, i64>,
 }
 
 impl PermissionsConfig {
     pub fn init_allow_all() -> Self {
         PermissionsConfig {
+            version: PERMISSIONS_VERSION,
             allow_all: UsersConfig::StringUsers("*".to_string()),
             chats: HashMap::new(),
+            roles: RolesConfig::default(),
+            deny: DenyConfig::default(),
+            groups: Vec::new(),
+            usernames: HashMap::new(),
+        }
+    }
+
+    /// Nobody has access until users are added
+    pub fn init_closed() -> Self {
+        PermissionsConfig {
+            allow_all: UsersConfig::ArrayUsers(Vec::new()),
+            ..Self::init_allow_all()
         }
     }
 
+    /// Whether the user may upload in the chat, taking roles and blocks into account
+    #[allow(clippy::ptr_arg)] // Signature kept for existing callers
     pub fn user_has_access(&self, chat_id: String, user_id: &String) -> bool {
-        fn process_users_config(cfg: &UsersConfig, user_id: &String) -> bool {
-            match cfg {
-                UsersConfig::SingleUser(user) if user.to_string() == user_id.to_string() => {
-                    debug!("User '{}' has access due to allow_all rule", user_id);
+        self.role_for(&chat_id, user_id, None) >= Some(Role::Uploader)
+    }
 
-                    return true;
-                }
-                UsersConfig::StringUsers(users) => {
-                    if users == "*" || users.to_owned() == user_id.to_owned() {
-                        debug!("User '{}' has access due to allow_all rule", user_id);
+    /// Whether the user is on the `allow_all` list or on the chat's list
+    fn is_listed(&self, chat_id: &str, user_id: &str, username: Option<&str>) -> bool {
+        debug!("Checking access for user '{}' in chat '{}'", user_id, chat_id);
+
+        if self.allow_all.ids().iter().any(|entry| self.entry_matches(entry, user_id, username)) {
+            debug!("User '{}' has access due to allow_all rule", user_id);
+
+            return true;
+        }
+
+        if let Some(chat) = self.chats.get(chat_id) {
+            if chat.ids().iter().any(|entry| self.entry_matches(entry, user_id, username)) {
+                debug!("User '{}' has access due to chat '{}' rule", user_id, chat_id);
 
-                        return true;
-                    }
+                return true;
+            }
+        }
+
+        false
+    }
 
-                    if users.contains(',') {
-                        let ids: Vec<&str> = users.split(',').map(|u| u.trim()).collect();
+    /// Entries are `*`, a numeric user id or `@username`. A username that has been
+    /// pinned to an id only matches that id, so a recycled username gets nothing.
+    fn entry_matches(&self, entry: &str, user_id: &str, username: Option<&str>) -> bool {
+        let entry = entry.trim();
 
-                        if ids.contains(&user_id.as_str()) {
-                            debug!("User '{}' has access due to allow_all rule", user_id);
+        if entry == "*" || entry == user_id {
+            return true;
+        }
 
-                            return true;
-                        }
-                    }
+        let wanted = match entry.strip_prefix('@') {
+            Some(wanted) => wanted.to_lowercase(),
+            None => return false,
+        };
 
-                    debug!("User '{}' does not have access due to allow_all rule", user_id);
+        match self.usernames.get(&wanted) {
+            Some(pinned) => pinned.to_string() == user_id,
+            None => username.is_some_and(|username| username.to_lowercase() == wanted),
+        }
+    }
 
-                    return false;
-                }
-                UsersConfig::ArrayUsers(users) => {
-                    let ids: Vec<String> = users.iter().map(|user| match user {
-                        UsersArrayConfig::StringUser(id) => id.trim().to_owned(),
-                        UsersArrayConfig::IntegerUser(id) => id.to_string().trim().to_owned(),
-                    }).collect();
+    fn best_role(&self, roles: &HashMap<String, Role>, user_id: &str, username: Option<&str>) -> Option<Role> {
+        roles.iter()
+            .filter(|(entry, _)| self.entry_matches(entry, user_id, username))
+            .map(|(_, role)| *role)
+            .max()
+    }
+
+    /// The active deny entry for the user in the chat; a global entry wins over a chat one
+    /// since it is at least as broad. Expired timed bans are ignored.
+    pub fn denial_for(&self, chat_id: &str, user_id: &str, username: Option<&str>) -> Option<DenyEntry> {
+        let now = now();
+
+        let global = self.deny.users.iter();
+        let chat = self.deny.chats.get(chat_id).into_iter().flatten();
+
+        global.chain(chat)
+            .filter(|(entry, _)| self.entry_matches(entry, user_id, username))
+            .map(|(_, deny)| deny)
+            .filter(|deny| deny.is_active(now))
+            // A permanent entry outlasts any timed one
+            .max_by_key(|deny| deny.until.unwrap_or(u64::MAX))
+            .cloned()
+    }
+
+    /// Highest role of the user in the chat: explicit assignments, global or for
+    /// the chat, and `uploader` for everyone on the `allow_all`/`chats` lists.
+    /// Deny entries take precedence over all of them.
+    pub fn role_for(&self, chat_id: &str, user_id: &str, username: Option<&str>) -> Option<Role> {
+        if self.denial_for(chat_id, user_id, username).is_some() {
+            return None;
+        }
+
+        let global = self.best_role(&self.roles.users, user_id, username);
+        let chat = self.roles.chats.get(chat_id).and_then(|roles| self.best_role(roles, user_id, username));
+        let legacy = if self.is_listed(chat_id, user_id, username) {
+            Some(Role::Uploader)
+        } else {
+            None
+        };
+
+        global.max(chat).max(legacy)
+    }
+
+    /// Role assigned in `roles.users`, the only one that counts outside a single chat.
+    /// A global deny entry takes it away.
+    pub fn global_role(&self, user_id: &str, username: Option<&str>) -> Option<Role> {
+        let now = now();
+
+        let denied = self.deny.users.iter()
+            .any(|(entry, deny)| deny.is_active(now) && self.entry_matches(entry, user_id, username));
+
+        if denied {
+            return None;
+        }
 
-                    if ids.contains(&user_id) {
-                        debug!("User '{}' has access due to allow_all specific users rule", user_id);
+        self.best_role(&self.roles.users, user_id, username)
+    }
+
+    /// Every `@username` used in the config, lowercased and without the `@`
+    fn referenced_usernames(&self) -> Vec<String> {
+        let lists = std::iter::once(&self.allow_all).chain(self.chats.values()).flat_map(UsersConfig::ids);
+        let roles = self.roles.users.keys().chain(self.roles.chats.values().flat_map(HashMap::keys)).cloned();
+        let deny = self.deny.users.keys().chain(self.deny.chats.values().flat_map(HashMap::keys)).cloned();
 
-                        return true;
-                    }
+        let mut usernames: Vec<String> = lists.chain(roles).chain(deny)
+            .filter_map(|entry| entry.trim().strip_prefix('@').map(str::to_lowercase))
+            .collect();
+        usernames.sort();
+        usernames.dedup();
 
-                    debug!("User '{}' does not have access due to allow_all specific users rule", user_id);
+        usernames
+    }
+
+    /// Remember the id behind a username the config refers to, the first time it
+    /// is seen. Returns whether the config changed and should be saved.
+    pub fn pin_username(&mut self, username: &str, user_id: i64) -> bool {
+        if !self.needs_pin(username) {
+            return false;
+        }
+
+        self.usernames.insert(username.to_lowercase(), user_id);
+
+        true
+    }
+
+    /// Whether the config refers to `@username` without a pinned id yet
+    pub fn needs_pin(&self, username: &str) -> bool {
+        let username = username.to_lowercase();
+
+        !self.usernames.contains_key(&username) && self.referenced_usernames().contains(&username)
+    }
+
+    /// Entries that can never match anybody, e.g. a bare name without `@`
+    pub fn lint(&self) -> Vec<String> {
+        fn check(entry: &str, place: &str, warnings: &mut Vec<String>) {
+            let entry = entry.trim();
+
+            if entry == "*" || entry.parse::<u64>().is_ok() {
+                return;
+            }
 
-                    return false;
+            match entry.strip_prefix('@') {
+                Some(name) if (5..=32).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {}
+                Some(_) => warnings.push(format!("{}: '{}' is not a valid Telegram username", place, entry)),
+                None if entry.is_empty() => {}
+                None if entry.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+                    warnings.push(format!("{}: '{}' never matches, user ids are numbers; did you mean '@{}'?", place, entry, entry))
                 }
-                _ => { false }
+                None => warnings.push(format!("{}: '{}' never matches, use a numeric user id or @username", place, entry)),
             }
         }
 
-        debug!("Checking access for user '{}' in chat '{}'", user_id, chat_id);
+        let mut warnings = Vec::new();
 
-        if process_users_config(&self.allow_all, user_id) {
-            return true;
+        for entry in self.allow_all.ids() {
+            check(&entry, "allow_all", &mut warnings);
+        }
+        for (chat_id, users) in &self.chats {
+            for entry in users.ids() {
+                check(&entry, &format!("chats.{}", chat_id), &mut warnings);
+            }
+        }
+        for entry in self.roles.users.keys() {
+            check(entry, "roles.users", &mut warnings);
+        }
+        for (chat_id, users) in &self.roles.chats {
+            for entry in users.keys() {
+                check(entry, &format!("roles.chats.{}", chat_id), &mut warnings);
+            }
+        }
+        for entry in self.deny.users.keys() {
+            check(entry, "deny.users", &mut warnings);
+        }
+        for (chat_id, users) in &self.deny.chats {
+            for entry in users.keys() {
+                check(entry, &format!("deny.chats.{}", chat_id), &mut warnings);
+            }
         }
 
-        if let Some(chat) = self.chats.get(&chat_id) {
-            return process_users_config(chat, user_id);
+        warnings.sort();
+
+        warnings
+    }
+
+    /// Group rules that apply in the chat and could raise a user above `current`.
+    /// Membership is resolved by the caller, it needs Telegram.
+    pub fn group_rules_for(&self, chat_id: &str, current: Option<Role>) -> Vec<GroupRule> {
+        self.groups.iter()
+            .filter(|rule| rule.chat.as_deref().is_none_or(|chat| chat == chat_id))
+            .filter(|rule| Some(rule.role) > current)
+            .cloned()
+            .collect()
+    }
+
+    /// Role assigned to the user by id, globally or in one chat
+    pub fn assigned_role(&self, user_id: &str, chat_id: Option<&str>) -> Option<Role> {
+        match chat_id {
+            None => self.roles.users.get(user_id).copied(),
+            Some(chat_id) => self.roles.chats.get(chat_id).and_then(|roles| roles.get(user_id)).copied(),
         }
+    }
 
-        false
+    pub fn set_role(&mut self, user_id: &str, chat_id: Option<&str>, role: Role) {
+        let roles = match chat_id {
+            None => &mut self.roles.users,
+            Some(chat_id) => self.roles.chats.entry(chat_id.to_owned()).or_default(),
+        };
+
+        roles.insert(user_id.to_owned(), role);
+    }
+
+    /// Block the user, globally or in one chat; `until` makes it a timed ban
+    pub fn deny_user(&mut self, user_id: &str, chat_id: Option<&str>, until: Option<u64>) {
+        let users = match chat_id {
+            None => &mut self.deny.users,
+            Some(chat_id) => self.deny.chats.entry(chat_id.to_owned()).or_default(),
+        };
+
+        users.insert(user_id.to_owned(), DenyEntry { until });
+    }
+
+    /// Lift a block, returns whether there was one
+    pub fn undeny_user(&mut self, user_id: &str, chat_id: Option<&str>) -> bool {
+        let users = match chat_id {
+            None => Some(&mut self.deny.users),
+            Some(chat_id) => self.deny.chats.get_mut(chat_id),
+        };
+
+        users.map(|users| users.remove(user_id).is_some()).unwrap_or(false)
+    }
+
+    /// Drop bans that have run out, returns whether anything was removed
+    pub fn prune_expired_bans(&mut self) -> bool {
+        let now = now();
+        let before = self.deny.users.len() + self.deny.chats.values().map(HashMap::len).sum::<usize>();
+
+        self.deny.users.retain(|_, entry| entry.is_active(now));
+        for users in self.deny.chats.values_mut() {
+            users.retain(|_, entry| entry.is_active(now));
+        }
+        self.deny.chats.retain(|_, users| !users.is_empty());
+
+        before != self.deny.users.len() + self.deny.chats.values().map(HashMap::len).sum::<usize>()
+    }
+
+    /// Remove the user's role and allow list entries, globally or in one chat.
+    /// Returns whether anything changed; wildcard entries are left alone.
+    pub fn remove_user(&mut self, user_id: &str, chat_id: Option<&str>) -> bool {
+        let (roles, list) = match chat_id {
+            None => (Some(&mut self.roles.users), Some(&mut self.allow_all)),
+            Some(chat_id) => (self.roles.chats.get_mut(chat_id), self.chats.get_mut(chat_id)),
+        };
+
+        let mut changed = roles.map(|roles| roles.remove(user_id).is_some()).unwrap_or(false);
+
+        if let Some(list) = list {
+            if let Some(remaining) = list.without(user_id) {
+                *list = remaining;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    /// Human-readable overview for the `/permissions` command
+    pub fn summary(&self) -> String {
+        fn describe(users: &UsersConfig) -> String {
+            let ids = users.ids();
+
+            if ids.is_empty() { "nobody".to_owned() } else { ids.join(", ") }
+        }
+
+        let mut lines = vec![format!("Uploaders in every chat: {}", describe(&self.allow_all))];
+
+        let mut chats: Vec<_> = self.chats.iter().collect();
+        chats.sort_by(|a, b| a.0.cmp(b.0));
+        for (chat_id, users) in chats {
+            lines.push(format!("Uploaders in chat {}: {}", chat_id, describe(users)));
+        }
+
+        let mut roles: Vec<String> = self.roles.users.iter()
+            .map(|(user, role)| format!("- {}: {}", user, role))
+            .collect();
+        for (chat_id, users) in &self.roles.chats {
+            roles.extend(users.iter().map(|(user, role)| format!("- {}: {} in chat {}", user, role, chat_id)));
+        }
+        roles.sort();
+
+        if roles.is_empty() {
+            lines.push("No roles assigned".to_owned());
+        } else {
+            lines.push("Roles:".to_owned());
+            lines.extend(roles);
+        }
+
+        fn describe_deny(user: &str, entry: &DenyEntry, chat_id: Option<&str>) -> String {
+            let until = match entry.until {
+                Some(until) => format!(" until {}", crate::utils::format_timestamp(until)),
+                None => String::new(),
+            };
+            let scope = chat_id.map(|chat_id| format!(" in chat {}", chat_id)).unwrap_or_default();
+
+            format!("- {}{}{}", user, scope, until)
+        }
+
+        let now = now();
+        let mut denied: Vec<String> = self.deny.users.iter()
+            .filter(|(_, entry)| entry.is_active(now))
+            .map(|(user, entry)| describe_deny(user, entry, None))
+            .collect();
+        for (chat_id, users) in &self.deny.chats {
+            denied.extend(users.iter()
+                .filter(|(_, entry)| entry.is_active(now))
+                .map(|(user, entry)| describe_deny(user, entry, Some(chat_id))));
+        }
+        denied.sort();
+
+        if !denied.is_empty() {
+            lines.push("Blocked:".to_owned());
+            lines.extend(denied);
+        }
+
+        if !self.groups.is_empty() {
+            lines.push("Group rules:".to_owned());
+        }
+        for rule in &self.groups {
+            lines.push(format!("- {}", rule));
+        }
+
+        lines.join("\n")
+    }
+
+    pub fn group_rules(&self) -> &[GroupRule] {
+        &self.groups
+    }
+
+    /// Role of every user named in the config in every chat named in it, plus
+    /// everyone else and all other chats. Group rules need Telegram and are left out.
+    pub fn access_matrix(&self) -> AccessMatrix {
+        let mut chats: Vec<String> = self.chats.keys()
+            .chain(self.roles.chats.keys())
+            .chain(self.deny.chats.keys())
+            .chain(self.groups.iter().filter_map(|rule| rule.chat.as_ref()))
+            .cloned()
+            .collect();
+        chats.sort();
+        chats.dedup();
+
+        let lists = std::iter::once(&self.allow_all).chain(self.chats.values()).flat_map(UsersConfig::ids);
+        let roles = self.roles.users.keys().chain(self.roles.chats.values().flat_map(HashMap::keys)).cloned();
+        let deny = self.deny.users.keys().chain(self.deny.chats.values().flat_map(HashMap::keys)).cloned();
+
+        let mut users: Vec<String> = lists.chain(roles).chain(deny)
+            .map(|entry| entry.trim().to_owned())
+            // Entries that can never match are reported by `lint` instead
+            .filter(|entry| entry.parse::<u64>().is_ok() || entry.starts_with('@'))
+            .collect();
+        users.sort();
+        users.dedup();
+
+        // No real chat or user has an empty or zero id, so these only match `*` entries
+        let columns: Vec<&str> = chats.iter().map(String::as_str).chain(std::iter::once("")).collect();
+
+        let mut rows: Vec<(String, Vec<Option<Role>>)> = users.into_iter().map(|user| {
+            let pinned = user.strip_prefix('@').and_then(|name| self.usernames.get(&name.to_lowercase()));
+            let (label, user_id) = match pinned {
+                Some(id) => (format!("{} ({})", user, id), id.to_string()),
+                None => (user.clone(), user),
+            };
+
+            (label, columns.iter().map(|chat| self.role_for(chat, &user_id, None)).collect())
+        }).collect();
+
+        rows.push(("everyone else".to_owned(), columns.iter().map(|chat| self.role_for(chat, "0", None)).collect()));
+
+        chats.push("other chats".to_owned());
+
+        AccessMatrix { chats, rows }
     }
 }
 
-async fn create_initial_config() -> Result<(), Box<dyn Error>> {
-    debug!("Creating initial configuration");
+/// Effective roles per user (rows) and chat (columns), see `PermissionsConfig::access_matrix`
+pub struct AccessMatrix {
+    pub chats: Vec<String>,
+    pub rows: Vec<(String, Vec<Option<Role>>)>,
+}
 
-    let initial_config = PermissionsConfig::init_allow_all();
+impl fmt::Display for AccessMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cells: Vec<Vec<String>> = self.rows.iter().map(|(_, roles)| {
+            roles.iter().map(|role| role.map(|role| role.to_string()).unwrap_or_else(|| "-".to_owned())).collect()
+        }).collect();
+
+        let first = self.rows.iter().map(|(user, _)| user.len()).chain(std::iter::once(4)).max().unwrap_or(4);
+        let widths: Vec<usize> = self.chats.iter().enumerate().map(|(i, chat)| {
+            cells.iter().map(|row| row[i].len()).chain(std::iter::once(chat.len())).max().unwrap_or(0)
+        }).collect();
+
+        let header = std::iter::once(("user", &self.chats));
+        let rows = self.rows.iter().zip(&cells).map(|((user, _), row)| (user.as_str(), row));
+
+        for (user, row) in header.chain(rows) {
+            let mut line = format!("{:<first$}", user);
+            for (cell, width) in row.iter().zip(&widths) {
+                line.push_str(&format!("  {:<width$}", cell));
+            }
 
-    save_config(&initial_config).await
+            writeln!(f, "{}", line.trim_end())?;
+        }
+
+        Ok(())
+    }
 }
 
-pub async fn load_config() -> Result<PermissionsConfig, Box<dyn Error>> {
-    let mut attempts = 0;
+/// Result of checking a permissions file without applying it
+pub struct Validation {
+    pub config: PermissionsConfig,
+    pub migration: MigrationReport,
+    pub unknown_fields: Vec<String>,
+    pub warnings: Vec<String>,
+}
 
-    let data = loop {
-        match fs::read_to_string(CONFIG_PATH).await {
-            Ok(data) => break data,
-            Err(_) => {
-                if attempts >= 2 {
-                    error!("Failed to read config after 3 attempts");
+/// `serde_json` puts the position at the end of its message; move it to the front
+fn describe_json_error(e: &serde_json::Error) -> String {
+    let message = e.to_string();
+    let suffix = format!(" at line {} column {}", e.line(), e.column());
 
-                    return Err("Failed to read config after 3 attempts".into());
-                }
+    format!("line {}, column {}: {}", e.line(), e.column(), message.strip_suffix(&suffix).unwrap_or(&message))
+}
 
-                debug!("Attempt {} to read config failed, creating initial config", attempts + 1);
+/// Fields serde would silently ignore, as dotted paths
+fn unknown_fields(raw: &Value) -> Vec<String> {
+    fn check(value: &Value, path: &str, known: &[&str], unknown: &mut Vec<String>) {
+        if let Some(object) = value.as_object() {
+            for key in object.keys().filter(|key| !known.contains(&key.as_str())) {
+                unknown.push(if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) });
+            }
+        }
+    }
 
-                create_initial_config().await.expect("Failed to create initial config");
-                attempts += 1;
+    fn entries<'a>(value: &'a Value, field: &str) -> Vec<(&'a String, &'a Value)> {
+        value.get(field).and_then(Value::as_object).map(|object| object.iter().collect()).unwrap_or_default()
+    }
+
+    let mut unknown = Vec::new();
+
+    check(raw, "", &["version", "allow_all", "chats", "roles", "deny", "groups", "usernames"], &mut unknown);
+
+    if let Some(roles) = raw.get("roles") {
+        check(roles, "roles", &["users", "chats"], &mut unknown);
+    }
+
+    if let Some(deny) = raw.get("deny") {
+        check(deny, "deny", &["users", "chats"], &mut unknown);
+
+        for (user, entry) in entries(deny, "users") {
+            check(entry, &format!("deny.users.{}", user), &["until"], &mut unknown);
+        }
+        for (chat_id, users) in entries(deny, "chats") {
+            for (user, entry) in users.as_object().into_iter().flatten() {
+                check(entry, &format!("deny.chats.{}.{}", chat_id, user), &["until"], &mut unknown);
             }
         }
+    }
+
+    for (i, rule) in raw.get("groups").and_then(Value::as_array).into_iter().flatten().enumerate() {
+        check(rule, &format!("groups[{}]", i), &["group", "members", "role", "chat"], &mut unknown);
+    }
+
+    unknown.sort();
+
+    unknown
+}
+
+/// Parse, upgrade and check a permissions file. Errors carry the line and column
+/// when the file itself is at fault.
+pub fn validate_config(data: &str) -> Result<Validation, String> {
+    let mut raw: Value = serde_json::from_str(data).map_err(|e| describe_json_error(&e))?;
+    let unknown_fields = unknown_fields(&raw);
+
+    let migration = migrate(&mut raw, MIGRATIONS, PERMISSIONS_VERSION)?;
+
+    let config: PermissionsConfig = if migration.is_empty() {
+        // Parsing the text rather than the value keeps positions in type errors
+        serde_json::from_str(data).map_err(|e| describe_json_error(&e))?
+    } else {
+        serde_json::from_value(raw).map_err(|e| format!("{} (after upgrading from version {})", e, migration.from))?
     };
 
-    let config: PermissionsConfig = match serde_json::from_str(&data) {
-        Ok(config) => config,
+    let warnings = config.lint();
+
+    Ok(Validation { config, migration, unknown_fields, warnings })
+}
+
+/// Record a permission change, both in the log and in `AUDIT_LOG_PATH`
+pub async fn append_audit_log(actor: &str, action: &str) -> Result<(), Box<dyn Error>> {
+    use tokio::io::AsyncWriteExt;
+
+    info!("Permissions changed by {}: {}", actor, action);
+
+    let entry = serde_json::json!({
+        "at": SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        "actor": actor,
+        "action": action,
+    });
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(AUDIT_LOG_PATH)
+        .await?;
+
+    file.write_all(format!("{}\n", entry).as_bytes()).await?;
+
+    Ok(())
+}
+
+/// A missing file is replaced by one that lets nobody in, unless open mode was
+/// asked for explicitly with `PERMISSIONS_OPEN_MODE`
+async fn create_initial_config(config: &Config) -> Result<PermissionsConfig, Box<dyn Error>> {
+    let initial_config = if config.permissions_open_mode() {
+        warn!("{} not found, creating one that allows everyone (PERMISSIONS_OPEN_MODE)", CONFIG_PATH);
+
+        PermissionsConfig::init_allow_all()
+    } else {
+        error!("{} not found, creating one that allows nobody; add users to it or set PERMISSIONS_OPEN_MODE=true", CONFIG_PATH);
+
+        PermissionsConfig::init_closed()
+    };
+
+    save_config(&initial_config).await?;
+
+    Ok(initial_config)
+}
+
+/// Load `CONFIG_PATH` at startup, creating it when it doesn't exist yet
+pub async fn load_config(config: &Config) -> Result<PermissionsConfig, Box<dyn Error>> {
+    if !Path::new(CONFIG_PATH).exists() {
+        return create_initial_config(config).await;
+    }
+
+    read_config().await
+}
+
+/// An unreadable or invalid file is an error, never a reason to fall back to a
+/// more permissive config. Only startup creates a missing file.
+async fn read_config() -> Result<PermissionsConfig, Box<dyn Error>> {
+    let data = match fs::read_to_string(CONFIG_PATH).await {
+        Ok(data) => data,
         Err(e) => {
-            error!("Failed to parse configuration: {}", e);
+            error!("Failed to read {}: {}", CONFIG_PATH, e);
 
-            return Err("Failed to parse configuration".into());
+            return Err(format!("Failed to read {}: {}", CONFIG_PATH, e).into());
         }
     };
 
+    let validation = match validate_config(&data) {
+        Ok(validation) => validation,
+        Err(e) => {
+            error!("Invalid {}: {}", CONFIG_PATH, e);
+
+            return Err(format!("Invalid {}: {}", CONFIG_PATH, e).into());
+        }
+    };
+
+    if !validation.migration.is_empty() {
+        let backup = backup_before_migration(CONFIG_PATH, validation.migration.from).await?;
+        save_config(&validation.config).await?;
+        log_migration(CONFIG_PATH, &validation.migration, &backup);
+    }
+
+    for field in &validation.unknown_fields {
+        warn!("{}: unknown field '{}' is ignored", CONFIG_PATH, field);
+    }
+
+    for warning in &validation.warnings {
+        warn!("{}: {}", CONFIG_PATH, warning);
+    }
+
     debug!("Successfully loaded configuration");
 
-    Ok(config)
+    Ok(validation.config)
+}
+
+/// Load the file again and swap it in as a whole; a bad or missing file keeps
+/// the current permissions. Used by `update_permissions` and the file watcher.
+pub async fn reload_config(permissions: &Mutex<PermissionsConfig>) -> bool {
+    let new_permissions = match read_config().await {
+        Ok(new_permissions) => new_permissions,
+        Err(e) => {
+            warn!("Failed to load new permissions config, using old one. Error: {:?}", e);
+
+            return false;
+        }
+    };
+
+    *permissions.lock().await = new_permissions;
+
+    info!("Permissions updated successfully");
+
+    true
 }
 
 pub async fn save_config(config: &PermissionsConfig) -> Result<(), Box<dyn Error>> {
@@ -170,8 +937,13 @@ mod tests {
     impl PermissionsConfig {
         fn init_empty() -> Self {
             PermissionsConfig {
+                version: PERMISSIONS_VERSION,
                 allow_all: UsersConfig::StringUsers("".to_string()),
                 chats: HashMap::new(),
+                roles: RolesConfig::default(),
+                deny: DenyConfig::default(),
+                groups: Vec::new(),
+                usernames: HashMap::new(),
             }
         }
     }
@@ -192,6 +964,19 @@ mod tests {
         assert!(config.chats.is_empty());
     }
 
+    #[test]
+    fn test_migrate_unversioned_config() {
+        let mut raw = serde_json::json!({ "allow_all": [123, "456"] });
+
+        let report = migrate(&mut raw, MIGRATIONS, PERMISSIONS_VERSION).unwrap();
+        assert_eq!(report.applied.len(), 5);
+
+        let config: PermissionsConfig = serde_json::from_value(raw).unwrap();
+        assert_eq!(config.version, PERMISSIONS_VERSION);
+        assert!(config.chats.is_empty());
+        assert!(config.user_has_access("any_chat".to_string(), &"456".to_string()));
+    }
+
     #[tokio::test]
     async fn test_user_has_access_allow_all() {
         let config = PermissionsConfig::init_allow_all();
@@ -252,5 +1037,167 @@ mod tests {
         assert!(config.user_has_access("chat3".to_string(), &"user1".to_string()));
         assert!(config.user_has_access("chat3".to_string(), &"123".to_string()));
         assert!(!config.user_has_access("chat3".to_string(), &"user2".to_string()));
+
+        // Blocks and roles count as well
+        config.deny_user("123", Some("chat1"), None);
+        config.set_role("user3", Some("chat2"), Role::Uploader);
+        config.set_role("user2", Some("chat3"), Role::Viewer);
+        assert!(!config.user_has_access("chat1".to_string(), &"123".to_string()));
+        assert!(config.user_has_access("chat2".to_string(), &"user3".to_string()));
+        assert!(!config.user_has_access("chat3".to_string(), &"user2".to_string()));
+    }
+
+    #[test]
+    fn test_role_for() {
+        let config: PermissionsConfig = serde_json::from_value(serde_json::json!({
+            "version": PERMISSIONS_VERSION,
+            "allow_all": [100],
+            "chats": { "chat1": [200] },
+            "roles": {
+                "users": { "1": "admin", "*": "viewer" },
+                "chats": { "chat1": { "2": "admin", "100": "viewer" } }
+            }
+        })).unwrap();
+
+        assert_eq!(config.role_for("chat1", "1", None), Some(Role::Admin));
+        assert_eq!(config.role_for("chat1", "2", None), Some(Role::Admin));
+        assert_eq!(config.role_for("chat2", "2", None), Some(Role::Viewer));
+        assert_eq!(config.role_for("chat1", "100", None), Some(Role::Uploader));
+        assert_eq!(config.role_for("chat1", "200", None), Some(Role::Uploader));
+        assert_eq!(config.role_for("chat2", "200", None), Some(Role::Viewer));
+
+        // Only `roles.users` counts outside a single chat
+        assert_eq!(config.global_role("1", None), Some(Role::Admin));
+        assert_eq!(config.global_role("2", None), Some(Role::Viewer));
+        assert_eq!(config.global_role("100", None), Some(Role::Viewer));
+
+        let legacy = PermissionsConfig::init_empty();
+        assert_eq!(legacy.role_for("chat1", "1", None), None);
+        assert_eq!(legacy.global_role("1", None), None);
+
+        assert_eq!("Admin".parse::<Role>(), Ok(Role::Admin));
+        assert!("owner".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn test_edit_permissions() {
+        let mut config = PermissionsConfig::init_empty();
+        config.allow_all = UsersConfig::StringUsers("1, 2".to_string());
+        config.chats.insert("chat1".to_string(), UsersConfig::StringUsers("*".to_string()));
+
+        config.set_role("3", Some("chat1"), Role::Admin);
+        assert_eq!(config.assigned_role("3", Some("chat1")), Some(Role::Admin));
+        assert_eq!(config.role_for("chat2", "3", None), None);
+
+        assert!(config.remove_user("1", None));
+        assert_eq!(config.role_for("chat2", "1", None), None);
+        assert_eq!(config.role_for("chat2", "2", None), Some(Role::Uploader));
+
+        assert!(config.remove_user("3", Some("chat1")));
+        assert!(!config.remove_user("3", Some("chat1")));
+        // The chat's wildcard still lets everyone in
+        assert_eq!(config.role_for("chat1", "3", None), Some(Role::Uploader));
+
+        assert_eq!(config.summary(), "Uploaders in every chat: 2\nUploaders in chat chat1: *\nNo roles assigned");
+    }
+
+    #[test]
+    fn test_deny_takes_precedence() {
+        let mut config = PermissionsConfig::init_allow_all();
+        config.set_role("1", None, Role::Admin);
+
+        config.deny_user("1", Some("chat1"), None);
+        assert_eq!(config.role_for("chat1", "1", None), None);
+        assert_eq!(config.role_for("chat2", "1", None), Some(Role::Admin));
+
+        config.deny_user("2", None, Some(now() + 60));
+        assert!(config.denial_for("chat2", "2", None).unwrap().until.is_some());
+        assert_eq!(config.role_for("chat2", "2", None), None);
+
+        // Expired bans no longer apply and are pruned
+        config.deny_user("3", None, Some(now() - 1));
+        assert_eq!(config.role_for("chat2", "3", None), Some(Role::Uploader));
+        assert!(config.prune_expired_bans());
+        assert!(!config.prune_expired_bans());
+
+        assert!(config.undeny_user("1", Some("chat1")));
+        assert_eq!(config.role_for("chat1", "1", None), Some(Role::Admin));
+    }
+
+    #[test]
+    fn test_group_rules_for() {
+        let config: PermissionsConfig = serde_json::from_value(serde_json::json!({
+            "version": PERMISSIONS_VERSION,
+            "allow_all": "",
+            "chats": {},
+            "groups": [
+                { "group": -100 },
+                { "group": -100, "members": "admins", "role": "admin" },
+                { "group": -200, "role": "viewer", "chat": "chat1" }
+            ]
+        })).unwrap();
+
+        assert_eq!(config.group_rules_for("chat1", None).len(), 3);
+        assert_eq!(config.group_rules_for("chat2", None).len(), 2);
+        assert_eq!(config.group_rules_for("chat1", Some(Role::Uploader)).len(), 1);
+
+        let rules = config.group_rules_for("chat2", None);
+        assert_eq!(rules[0].role, Role::Uploader);
+        assert!(rules[0].matches(GroupMembership::Member));
+        assert!(!rules[1].matches(GroupMembership::Member));
+        assert!(rules[1].matches(GroupMembership::Admin));
+        assert!(!rules[0].matches(GroupMembership::Outside));
+    }
+
+    #[test]
+    fn test_username_entries() {
+        let mut config: PermissionsConfig = serde_json::from_value(serde_json::json!({
+            "version": PERMISSIONS_VERSION,
+            "allow_all": ["@Alice_Team"],
+            "chats": { "chat1": "bob, 42" },
+            "roles": { "users": { "@carol_admin": "admin" } }
+        })).unwrap();
+
+        assert_eq!(config.role_for("chat2", "7", Some("alice_team")), Some(Role::Uploader));
+        assert_eq!(config.role_for("chat2", "7", Some("ALICE_TEAM")), Some(Role::Uploader));
+        assert_eq!(config.role_for("chat2", "7", Some("mallory")), None);
+        assert_eq!(config.role_for("chat2", "8", Some("Carol_Admin")), Some(Role::Admin));
+
+        assert!(config.pin_username("Alice_Team", 7));
+        assert!(!config.pin_username("alice_team", 7));
+        assert!(!config.pin_username("mallory", 9));
+
+        // Once pinned, only the pinned id matches even if someone else takes the name
+        assert_eq!(config.role_for("chat2", "9", Some("alice_team")), None);
+        assert_eq!(config.role_for("chat2", "7", None), Some(Role::Uploader));
+
+        assert_eq!(config.lint(), vec!["chats.chat1: 'bob' never matches, user ids are numbers; did you mean '@bob'?"]);
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let error = validate_config("{\n  \"version\": 5,\n  \"allow_all\": \"*\",\n  \"chats\": {},\n}").err().unwrap();
+        assert!(error.starts_with("line 5, column 1:"), "{}", error);
+
+        let error = validate_config("{\"version\": 5, \"allow_all\": \"*\", \"chats\": {}, \"roles\": {\"users\": {\"1\": \"owner\"}}}").err().unwrap();
+        assert!(error.starts_with("line 1, column"), "{}", error);
+
+        let validation = validate_config(r#"{
+            "version": 5,
+            "allow_all": [],
+            "chats": { "-100": ["1", "alice"] },
+            "roles": { "users": { "2": "admin" }, "chat": {} },
+            "deny": { "users": { "1": { "untill": 5 } } },
+            "grups": []
+        }"#).unwrap();
+
+        assert_eq!(validation.unknown_fields, vec!["deny.users.1.untill", "grups", "roles.chat"]);
+        assert_eq!(validation.warnings.len(), 1);
+
+        let matrix = validation.config.access_matrix();
+        assert_eq!(matrix.chats, vec!["-100", "other chats"]);
+        assert_eq!(matrix.rows[0], ("1".to_owned(), vec![None, None]));
+        assert_eq!(matrix.rows[1], ("2".to_owned(), vec![Some(Role::Admin), Some(Role::Admin)]));
+        assert_eq!(matrix.rows.last().unwrap().1, vec![None, None]);
     }
 }
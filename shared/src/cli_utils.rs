@@ -1,14 +1,19 @@
+use crate::api_keys;
+use crate::backup::{self, ImportMode};
 use crate::chat_config;
-use crate::config::Config;
+use crate::config::{SharedConfig, Sources};
+use crate::file_storage;
 use crate::utils::create_fifo;
 use log::{error, info, warn};
 use std::sync::Arc;
 use tokio::fs::OpenOptions;
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 
-pub async fn handle_cli(permissions: Arc<Mutex<chat_config::PermissionsConfig>>) {
-    let path = Config::instance().await.pipe_path();
+/// Reads commands from the FIFO. Commands that need the bot or the server,
+/// such as `repair`, are passed on through `forward`.
+pub async fn handle_cli(config: SharedConfig, permissions: Arc<Mutex<chat_config::PermissionsConfig>>, forward: mpsc::Sender<String>) {
+    let path = config.get().pipe_path();
 
     match create_fifo(&path).await {
         Ok(_) => info!("FIFO created at {}", path),
@@ -40,25 +45,100 @@ pub async fn handle_cli(permissions: Arc<Mutex<chat_config::PermissionsConfig>>)
             }
         } {
             if line.trim() == "update_permissions" {
-                let new_permissions = match chat_config::load_config().await {
-                    Ok(new_permissions) => new_permissions,
-                    Err(e) => {
-                        warn!("Failed to load new permissions config, using old one. Error: {:?}", e);
+                chat_config::reload_config(&permissions).await;
+            } else if line.trim() == "reload_config" {
+                reload_config(&config);
+            } else if line.trim() == "reload_api_keys" {
+                match api_keys::init_api_keys().await {
+                    Ok(_) => info!("API keys reloaded successfully"),
+                    Err(e) => warn!("Failed to reload API keys, using old ones. Error: {}", e),
+                }
+            } else if line.trim() == "reload_files" {
+                match file_storage::init_file_storage().await {
+                    Ok(_) => info!("File mappings reloaded successfully"),
+                    Err(e) => warn!("Failed to reload file mappings, using old ones. Error: {}", e),
+                }
+            } else if let Some(args) = line.trim().strip_prefix("import ") {
+                import_backup(args, &permissions).await;
+            } else if line.trim() == "shutdown" {
+                info!("Shutting down command handled");
 
-                        continue;
-                    }
-                };
+                return;
+            } else if !line.trim().is_empty() && forward.send(line.trim().to_owned()).await.is_err() {
+                warn!("No handler for command '{}'", line.trim());
+            }
+        }
+    }
+}
+
+/// `import <mode> <path>`: restore an archive into the loaded state, so nothing
+/// saved since the CLI read the files is overwritten.
+async fn import_backup(args: &str, permissions: &Arc<Mutex<chat_config::PermissionsConfig>>) {
+    let (mode, path) = match args.split_once(' ') {
+        Some((mode, path)) => (mode, path),
+        None => {
+            warn!("Usage: import <mode> <path>");
+
+            return;
+        }
+    };
 
-                let mut permissions = permissions.lock().await;
+    let mode = match mode.parse::<ImportMode>() {
+        Ok(mode) => mode,
+        Err(e) => {
+            warn!("{}", e);
 
-                *permissions = new_permissions;
+            return;
+        }
+    };
 
-                info!("Permissions updated successfully");
-            } else if line.trim() == "shutdown" {
-                info!("Shutting down command handled");
+    let summary = match backup::read_backup(path).await {
+        Ok(archive) => match backup::import_backup(archive, mode).await {
+            Ok(summary) => summary,
+            Err(e) => {
+                error!("Failed to import backup: {}", e);
 
                 return;
             }
+        },
+        Err(e) => {
+            error!("{}", e);
+
+            return;
+        }
+    };
+
+    info!("Imported {}: {}", path, summary.describe());
+
+    if summary.permissions_written {
+        chat_config::reload_config(permissions).await;
+    }
+
+    if summary.api_keys_written {
+        match api_keys::init_api_keys().await {
+            Ok(_) => info!("API keys reloaded successfully"),
+            Err(e) => warn!("Failed to reload API keys, using old ones. Error: {}", e),
+        }
+    }
+}
+
+/// Resolve the configuration again from the environment and `fileslink.toml`.
+/// Settings that need a restart are logged and keep their current value.
+pub fn reload_config(config: &SharedConfig) {
+    match config.reload(&Sources::load()) {
+        Ok(notes) => {
+            info!("Configuration reloaded");
+
+            for note in notes {
+                warn!("{}", note);
+            }
+        }
+        Err(problems) => {
+            warn!("Invalid configuration, keeping the current one:");
+
+            for problem in problems {
+                warn!("  - {}", problem);
+            }
         }
     }
-}
\ No newline at end of file
+}
@@ -1,64 +1,231 @@
+use std::collections::HashMap;
 use std::env;
+use std::fmt;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use dotenv::dotenv;
 use log::{info, warn};
-use once_cell::sync::Lazy;
-use tokio::sync::RwLock;
+use url::Url;
+
+use crate::config_file::{config_file_path, mask, read_config_file, setting, ConfigFile, Setting, Source};
 
 pub struct Config {
     bot_token: Result<String, String>,
-    server_port: i16,
+    server_port: u16,
     file_domain: String,
     telegram_api_url: String,
     fasttelethon_url: String,
     pipe_path: String,
     enable_files_route: bool,
     storage_channel_id: Result<i64, String>,
+    file_cache_dir: Option<String>,
+    file_cache_max_size: u64,
+    file_cache_max_age: u64,
+    backup_dir: Option<String>,
+    backup_interval: u64,
+    backup_keep: usize,
+    backup_to_channel: bool,
+    permissions_open_mode: bool,
+    problems: Vec<String>,
+    /// Where each setting came from, for `show`
+    origins: HashMap<&'static str, Source>,
+}
+
+/// Where settings are read from: environment variables over `fileslink.toml`.
+/// Tests build one from a list instead of touching the process environment.
+pub struct Sources {
+    env: HashMap<String, String>,
+    file: Result<Option<ConfigFile>, String>,
+}
+
+impl Sources {
+    /// The process environment and the config file as they are right now
+    pub fn load() -> Self {
+        Self {
+            env: env::vars().collect(),
+            file: read_config_file(&config_file_path()),
+        }
+    }
+
+    pub fn from_pairs(pairs: &[(&str, &str)]) -> Self {
+        Self {
+            env: pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+            file: Ok(None),
+        }
+    }
+
+    pub fn with_file(self, file: ConfigFile) -> Self {
+        Self { file: Ok(Some(file)), ..self }
+    }
+
+    /// Values are trimmed here once, so the checks and the `fetch_*` functions see the same string
+    fn lookup(&self, setting: &Setting) -> Option<(String, Source)> {
+        if let Some(value) = self.env.get(setting.env) {
+            return Some((value.trim().to_owned(), Source::Env));
+        }
+
+        let file = self.file.as_ref().ok()?.as_ref()?;
+
+        file.value(setting.key).map(|value| (value.trim().to_owned(), Source::File))
+    }
+
+    fn get(&self, var: &str) -> Option<String> {
+        match setting(var) {
+            Some(setting) => self.lookup(setting).map(|(value, _)| value),
+            None => self.env.get(var).map(|value| value.trim().to_owned()),
+        }
+    }
+
+    /// Every value that is set but unusable, naming both the key and the variable
+    fn problems(&self) -> Vec<String> {
+        let mut problems: Vec<String> = self.file.as_ref().err().cloned().into_iter().collect();
+
+        problems.extend(crate::config_file::SETTINGS.iter().filter_map(|setting| {
+            let (value, source) = self.lookup(setting)?;
+            let error = setting.check(&value).err()?;
+            let shown = if setting.secret { mask(&value) } else { value };
+
+            Some(match source {
+                Source::Env => format!("{} (environment): '{}': {}", setting.env, shown, error),
+                _ => format!("{} ({}): '{}': {}", setting.key, config_file_path(), shown, error),
+            })
+        }));
+
+        problems
+    }
+}
+
+/// The current configuration, shared by everything that needs it. Reads only
+/// clone an `Arc` under a read lock, so they never wait on each other.
+#[derive(Clone)]
+pub struct SharedConfig(Arc<RwLock<Arc<Config>>>);
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        Arc::clone(&self.0.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn set(&self, config: Config) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+    }
+
+    /// Resolve the configuration again and apply what can change at runtime.
+    /// Returns notes on settings that need a restart; an invalid configuration
+    /// changes nothing.
+    pub fn reload(&self, sources: &Sources) -> Result<Vec<String>, Vec<String>> {
+        let mut next = Config::from_sources(sources);
+
+        let problems = next.problems();
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+
+        let notes = next.keep_startup_settings(&self.get());
+        self.set(next);
+
+        Ok(notes)
+    }
 }
 
-static INSTANCE: Lazy<RwLock<Option<Arc<Config>>>> = Lazy::new(|| RwLock::new(None));
+// Not derived, so the bot token never ends up in a log line
+impl fmt::Debug for SharedConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedConfig").finish_non_exhaustive()
+    }
+}
 
 impl Config {
-    pub fn new() -> Self {
-        let bot_token = fetch_bot_token();
+    /// The validated configuration. Every problem is collected, so one report
+    /// lists everything that needs fixing.
+    pub fn new() -> Result<Self, Vec<String>> {
+        let config = Self::resolve();
+        let problems = config.problems();
+
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Every setting resolved from the environment and `fileslink.toml`, with
+    /// defaults in place of invalid values; see `problems`
+    pub fn resolve() -> Self {
+        Self::from_sources(&Sources::load())
+    }
 
-        let server_port = fetch_server_port();
-        let file_domain = fetch_file_domain();
-        let telegram_api_url = fetch_telegram_api();
-        let fasttelethon_url = fetch_fasttelethon_url();
-        let pipe_path = fetch_pipe_path();
-        let enable_files_route = fetch_enable_files_route();
-        let storage_channel_id = fetch_storage_channel_id();
+    pub fn from_sources(sources: &Sources) -> Self {
+        let origins = crate::config_file::SETTINGS.iter()
+            .map(|setting| (setting.env, sources.lookup(setting).map(|(_, source)| source).unwrap_or(Source::Default)))
+            .collect();
 
         Self {
-            bot_token,
-            server_port,
-            file_domain,
-            telegram_api_url,
-            fasttelethon_url,
-            pipe_path,
-            enable_files_route,
-            storage_channel_id,
+            bot_token: fetch_bot_token(sources),
+            server_port: fetch_server_port(sources),
+            file_domain: fetch_file_domain(sources),
+            telegram_api_url: fetch_telegram_api(sources),
+            fasttelethon_url: fetch_fasttelethon_url(sources),
+            pipe_path: fetch_pipe_path(sources),
+            enable_files_route: fetch_enable_files_route(sources),
+            storage_channel_id: fetch_storage_channel_id(sources),
+            file_cache_dir: fetch_file_cache_dir(sources),
+            file_cache_max_size: fetch_file_cache_max_size(sources),
+            file_cache_max_age: fetch_file_cache_max_age(sources),
+            backup_dir: fetch_backup_dir(sources),
+            backup_interval: fetch_backup_interval(sources),
+            backup_keep: fetch_backup_keep(sources),
+            backup_to_channel: fetch_backup_to_channel(sources),
+            permissions_open_mode: fetch_permissions_open_mode(sources),
+            problems: sources.problems(),
+            origins,
         }
     }
 
-    pub async fn instance() -> Arc<Config> {
-        let mut instance = INSTANCE.write().await;
+    /// Validate the configuration and wrap it in the handle passed to every
+    /// task; called once at startup
+    pub fn init() -> Result<SharedConfig, Vec<String>> {
+        Ok(SharedConfig::new(Config::new()?))
+    }
 
-        if instance.is_none() {
-            *instance = Some(Arc::new(Config::new()));
+    /// Settings read once at startup (the bot, the listener, the cache and the
+    /// backup schedule) keep their current value; returns a note for each one
+    /// that changed
+    fn keep_startup_settings(&mut self, current: &Config) -> Vec<String> {
+        fn keep<T: PartialEq + Clone>(next: &mut T, current: &T, env: &str, notes: &mut Vec<String>) {
+            if next != current {
+                notes.push(format!("{} changed; restart FilesLink to apply it", env));
+                *next = current.clone();
+            }
         }
 
-        instance.clone().unwrap()
+        let mut notes = Vec::new();
+
+        keep(&mut self.bot_token, &current.bot_token, "BOT_TOKEN", &mut notes);
+        keep(&mut self.server_port, &current.server_port, "SERVER_PORT", &mut notes);
+        keep(&mut self.telegram_api_url, &current.telegram_api_url, "TELEGRAM_API_URL", &mut notes);
+        keep(&mut self.pipe_path, &current.pipe_path, "FILESLINK_PIPE_PATH", &mut notes);
+        keep(&mut self.storage_channel_id, &current.storage_channel_id, "STORAGE_CHANNEL_ID", &mut notes);
+        keep(&mut self.file_cache_dir, &current.file_cache_dir, "FILE_CACHE_DIR", &mut notes);
+        keep(&mut self.file_cache_max_size, &current.file_cache_max_size, "FILE_CACHE_MAX_SIZE_MB", &mut notes);
+        keep(&mut self.file_cache_max_age, &current.file_cache_max_age, "FILE_CACHE_MAX_AGE_HOURS", &mut notes);
+        keep(&mut self.backup_dir, &current.backup_dir, "BACKUP_DIR", &mut notes);
+        keep(&mut self.backup_interval, &current.backup_interval, "BACKUP_INTERVAL_HOURS", &mut notes);
+        keep(&mut self.backup_keep, &current.backup_keep, "BACKUP_KEEP", &mut notes);
+        keep(&mut self.backup_to_channel, &current.backup_to_channel, "BACKUP_TO_CHANNEL", &mut notes);
+
+        notes
     }
 
     pub fn bot_token(&self) -> Result<String, String> {
         self.bot_token.to_owned()
     }
 
-    pub fn server_port(&self) -> i16 {
+    pub fn server_port(&self) -> u16 {
         self.server_port
     }
 
@@ -85,6 +252,121 @@ impl Config {
     pub fn storage_channel_id(&self) -> Result<i64, String> {
         self.storage_channel_id.to_owned()
     }
+
+    /// Directory of the on-disk download cache, `None` when caching is disabled
+    pub fn file_cache_dir(&self) -> Option<String> {
+        self.file_cache_dir.to_owned()
+    }
+
+    /// Maximum total size of the download cache in bytes
+    pub fn file_cache_max_size(&self) -> u64 {
+        self.file_cache_max_size
+    }
+
+    /// Maximum age of a cached file in seconds
+    pub fn file_cache_max_age(&self) -> u64 {
+        self.file_cache_max_age
+    }
+
+    /// Directory of periodic backup snapshots, `None` when backups are disabled
+    pub fn backup_dir(&self) -> Option<String> {
+        self.backup_dir.to_owned()
+    }
+
+    /// Time between backup snapshots in seconds
+    pub fn backup_interval(&self) -> u64 {
+        self.backup_interval
+    }
+
+    /// Number of snapshots kept in the backup directory
+    pub fn backup_keep(&self) -> usize {
+        self.backup_keep
+    }
+
+    /// Whether snapshots are also posted into the storage channel
+    pub fn backup_to_channel(&self) -> bool {
+        self.backup_to_channel
+    }
+
+    /// Whether a missing permissions file is replaced by one that allows everyone
+    pub fn permissions_open_mode(&self) -> bool {
+        self.permissions_open_mode
+    }
+
+    /// Everything that keeps the configuration from being usable, naming the
+    /// variable and key of each bad value
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = self.problems.clone();
+
+        if let Err(e) = &self.bot_token {
+            problems.push(e.clone());
+        }
+
+        if let Err(e) = &self.storage_channel_id {
+            problems.push(e.clone());
+        }
+
+        for (env, url) in [
+            ("TELEGRAM_API_URL", &self.telegram_api_url),
+            ("FASTTELETHON_URL", &self.fasttelethon_url),
+            ("APP_FILE_DOMAIN", &self.file_domain),
+        ] {
+            if let Err(e) = check_http_url(url) {
+                problems.push(format!("{} ({}): '{}': {}", env, setting(env).map(|s| s.key).unwrap_or_default(), url, e));
+            }
+        }
+
+        problems
+    }
+
+    /// The resolved configuration, one `key = value` line per setting with where
+    /// the value came from. Secrets are masked.
+    pub fn show(&self) -> String {
+        const MB: u64 = 1024 * 1024;
+        const HOUR: u64 = 60 * 60;
+
+        fn optional(value: &Option<String>) -> String {
+            value.clone().unwrap_or_else(|| "(disabled)".to_owned())
+        }
+
+        let resolved = [
+            ("BOT_TOKEN", self.bot_token.as_ref().map(|token| mask(token)).unwrap_or_else(|_| "(not set)".to_owned())),
+            ("STORAGE_CHANNEL_ID", self.storage_channel_id.as_ref().map(|id| id.to_string()).unwrap_or_else(|_| "(not set)".to_owned())),
+            ("TELEGRAM_API_URL", self.telegram_api_url.clone()),
+            ("FASTTELETHON_URL", self.fasttelethon_url.clone()),
+            ("SERVER_PORT", self.server_port.to_string()),
+            ("APP_FILE_DOMAIN", self.file_domain.clone()),
+            ("ENABLE_FILES_ROUTE", self.enable_files_route.to_string()),
+            ("FILESLINK_PIPE_PATH", self.pipe_path.clone()),
+            ("FILE_CACHE_DIR", optional(&self.file_cache_dir)),
+            ("FILE_CACHE_MAX_SIZE_MB", (self.file_cache_max_size / MB).to_string()),
+            ("FILE_CACHE_MAX_AGE_HOURS", (self.file_cache_max_age / HOUR).to_string()),
+            ("BACKUP_DIR", optional(&self.backup_dir)),
+            ("BACKUP_INTERVAL_HOURS", (self.backup_interval / HOUR).to_string()),
+            ("BACKUP_KEEP", self.backup_keep.to_string()),
+            ("BACKUP_TO_CHANNEL", self.backup_to_channel.to_string()),
+            ("PERMISSIONS_OPEN_MODE", self.permissions_open_mode.to_string()),
+        ];
+
+        let path = config_file_path();
+
+        let mut lines: Vec<String> = resolved.iter().filter_map(|(env, value)| {
+            let setting = setting(env)?;
+            let source = match self.origins.get(env) {
+                Some(Source::Env) => setting.env.to_owned(),
+                Some(Source::File) => path.clone(),
+                _ => "default".to_owned(),
+            };
+
+            Some(format!("{:<28} = {:<40} # {}", setting.key, value, source))
+        }).collect();
+
+        for problem in &self.problems() {
+            lines.push(format!("# problem: {}", problem.replace('\n', "\n#   ")));
+        }
+
+        lines.join("\n")
+    }
 }
 
 pub fn load_env() {
@@ -108,33 +390,44 @@ pub fn load_env() {
     load_log_level();
 }
 
-fn fetch_env_variable(var: &str) -> Option<String> {
-    env::var(var).ok()
+/// Only absolute http(s) URLs with a host are usable for the Bot API, FastTelethon and links
+pub fn check_http_url(value: &str) -> Result<(), String> {
+    let url = Url::parse(value).map_err(|e| format!("not a valid URL ({})", e))?;
+
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(format!("unsupported scheme '{}', expected http or https", url.scheme()));
+    }
+
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err("the URL has no host".to_owned());
+    }
+
+    Ok(())
 }
 
-fn fetch_bot_token() -> Result<String, String> {
-    let val = fetch_env_variable("BOT_TOKEN");
+fn fetch_bot_token(sources: &Sources) -> Result<String, String> {
+    let val = sources.get("BOT_TOKEN");
 
     match val {
-        None => Err("environment variable 'BOT_TOKEN' is not set".to_owned()),
+        None => Err("BOT_TOKEN (telegram.bot_token) is not set".to_owned()),
         Some(_) => Ok(val.unwrap())
     }
 }
 
-fn fetch_server_port() -> i16 {
-    fetch_env_variable("SERVER_PORT")
+fn fetch_server_port(sources: &Sources) -> u16 {
+    sources.get("SERVER_PORT")
         .and_then(|val| val.parse().ok())
         .unwrap_or(8080)
 }
 
 /// Fetches the domain from the environment variables.
 /// Ends the domain with a slash if it doesn't have one.
-fn fetch_file_domain() -> String {
-    let default_port = fetch_server_port();
+fn fetch_file_domain(sources: &Sources) -> String {
+    let default_port = fetch_server_port(sources);
 
     let default_url = format!("http://localhost:{default_port}/files");
 
-    let app_file_domain = fetch_env_variable("APP_FILE_DOMAIN").unwrap_or_else(|| default_url);
+    let app_file_domain = sources.get("APP_FILE_DOMAIN").unwrap_or(default_url);
 
     if app_file_domain.ends_with('/') {
         app_file_domain
@@ -143,9 +436,9 @@ fn fetch_file_domain() -> String {
     }
 }
 
-fn fetch_telegram_api() -> String {
-    let url = fetch_env_variable("TELEGRAM_API_URL").unwrap_or_else(|| {
-        println!("TELEGRAM_API_URL environment variable is not set");
+fn fetch_telegram_api(sources: &Sources) -> String {
+    let url = sources.get("TELEGRAM_API_URL").unwrap_or_else(|| {
+        info!("TELEGRAM_API_URL environment variable is not set. Defaulting to https://api.telegram.org");
         "https://api.telegram.org".to_owned()
     });
 
@@ -156,8 +449,8 @@ fn fetch_telegram_api() -> String {
     }
 }
 
-fn fetch_fasttelethon_url() -> String {
-    let url = fetch_env_variable("FASTTELETHON_URL").unwrap_or_else(|| {
+fn fetch_fasttelethon_url(sources: &Sources) -> String {
+    let url = sources.get("FASTTELETHON_URL").unwrap_or_else(|| {
         info!("FASTTELETHON_URL environment variable is not set. Defaulting to http://localhost:8001");
         "http://localhost:8001".to_owned()
     });
@@ -170,15 +463,15 @@ fn fetch_fasttelethon_url() -> String {
     }
 }
 
-fn fetch_pipe_path() -> String {
-    fetch_env_variable("FILESLINK_PIPE_PATH").unwrap_or_else(|| {
+fn fetch_pipe_path(sources: &Sources) -> String {
+    sources.get("FILESLINK_PIPE_PATH").unwrap_or_else(|| {
         info!("FILESLINK_PIPE_PATH environment variable is not set");
         "/tmp/fileslink.pipe".to_owned()
     })
 }
 
-fn fetch_enable_files_route() -> bool {
-    fetch_env_variable("ENABLE_FILES_ROUTE")
+fn fetch_enable_files_route(sources: &Sources) -> bool {
+    sources.get("ENABLE_FILES_ROUTE")
         .unwrap_or_else(|| {
             warn!("ENABLE_FILES_ROUTE environment variable is not set. Defaulting to false.");
             "false".to_owned()
@@ -187,186 +480,230 @@ fn fetch_enable_files_route() -> bool {
         .unwrap_or(false)
 }
 
-fn fetch_storage_channel_id() -> Result<i64, String> {
-    let val = fetch_env_variable("STORAGE_CHANNEL_ID");
+fn fetch_storage_channel_id(sources: &Sources) -> Result<i64, String> {
+    let val = sources.get("STORAGE_CHANNEL_ID");
 
     match val {
-        None => Err("environment variable 'STORAGE_CHANNEL_ID' is not set".to_owned()),
+        None => Err("STORAGE_CHANNEL_ID (telegram.storage_channel_id) is not set".to_owned()),
         Some(v) => v.parse::<i64>()
-            .map_err(|_| "STORAGE_CHANNEL_ID must be a valid i64 number".to_owned())
+            .map_err(|_| "STORAGE_CHANNEL_ID (telegram.storage_channel_id) must be a valid i64 number".to_owned())
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::env;
-
-    use super::*;
+fn fetch_file_cache_dir(sources: &Sources) -> Option<String> {
+    sources.get("FILE_CACHE_DIR").filter(|dir| !dir.trim().is_empty())
+}
 
-    fn set_env_variable(key: &str, value: &str) {
-        env::set_var(key, value);
-    }
+fn fetch_file_cache_max_size(sources: &Sources) -> u64 {
+    sources.get("FILE_CACHE_MAX_SIZE_MB")
+        .and_then(|val| val.parse::<u64>().ok())
+        .unwrap_or(1024)
+        .saturating_mul(1024 * 1024)
+}
 
-    fn remove_env_variable(key: &str) {
-        env::remove_var(key);
-    }
+fn fetch_file_cache_max_age(sources: &Sources) -> u64 {
+    sources.get("FILE_CACHE_MAX_AGE_HOURS")
+        .and_then(|val| val.parse::<u64>().ok())
+        .unwrap_or(168)
+        .saturating_mul(60 * 60)
+}
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_fetch_bot_token_success() {
-        set_env_variable("BOT_TOKEN", "test_token");
+fn fetch_backup_dir(sources: &Sources) -> Option<String> {
+    sources.get("BACKUP_DIR").filter(|dir| !dir.trim().is_empty())
+}
 
-        let token = fetch_bot_token();
+fn fetch_backup_interval(sources: &Sources) -> u64 {
+    sources.get("BACKUP_INTERVAL_HOURS")
+        .and_then(|val| val.parse::<u64>().ok())
+        .filter(|hours| *hours > 0)
+        .unwrap_or(24)
+        .saturating_mul(60 * 60)
+}
 
-        assert_eq!(token, Ok("test_token".to_string()));
+fn fetch_backup_keep(sources: &Sources) -> usize {
+    sources.get("BACKUP_KEEP")
+        .and_then(|val| val.parse::<usize>().ok())
+        .filter(|keep| *keep > 0)
+        .unwrap_or(7)
+}
 
-        remove_env_variable("BOT_TOKEN");
-    }
+fn fetch_backup_to_channel(sources: &Sources) -> bool {
+    sources.get("BACKUP_TO_CHANNEL")
+        .and_then(|val| val.parse().ok())
+        .unwrap_or(false)
+}
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_fetch_bot_token_failure() {
-        remove_env_variable("BOT_TOKEN");
+fn fetch_permissions_open_mode(sources: &Sources) -> bool {
+    sources.get("PERMISSIONS_OPEN_MODE")
+        .and_then(|val| val.parse().ok())
+        .unwrap_or(false)
+}
 
-        let token = fetch_bot_token();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        assert_eq!(token, Err("environment variable 'BOT_TOKEN' is not set".to_string()));
+    fn sources(pairs: &[(&str, &str)]) -> Sources {
+        Sources::from_pairs(pairs)
     }
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_fetch_server_port() {
-        set_env_variable("SERVER_PORT", "9090");
+    #[test]
+    fn test_fetch_bot_token_success() {
+        let token = fetch_bot_token(&sources(&[("BOT_TOKEN", "test_token")]));
 
-        let port = fetch_server_port();
+        assert_eq!(token, Ok("test_token".to_string()));
+    }
 
-        assert_eq!(port, 9090);
+    #[test]
+    fn test_fetch_bot_token_failure() {
+        let token = fetch_bot_token(&sources(&[]));
 
-        remove_env_variable("SERVER_PORT");
+        assert_eq!(token, Err("BOT_TOKEN (telegram.bot_token) is not set".to_string()));
     }
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_fetch_server_port_default() {
-        remove_env_variable("SERVER_PORT");
-
-        let port = fetch_server_port();
+    #[test]
+    fn test_fetch_server_port() {
+        assert_eq!(fetch_server_port(&sources(&[("SERVER_PORT", "9090")])), 9090);
+        assert_eq!(fetch_server_port(&sources(&[])), 8080);
 
-        assert_eq!(port, 8080);
+        // Validated and parsed as the same trimmed value
+        let padded = sources(&[("SERVER_PORT", "9000 ")]);
+        assert!(padded.problems().is_empty());
+        assert_eq!(fetch_server_port(&padded), 9000);
     }
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_fetch_file_domain() {
-        set_env_variable("APP_FILE_DOMAIN", "http://example.com");
-
-        let domain = fetch_file_domain();
+    #[test]
+    fn test_fetch_file_domain() {
+        let domain = fetch_file_domain(&sources(&[("APP_FILE_DOMAIN", "http://example.com")]));
 
         assert_eq!(domain, "http://example.com/");
-
-        remove_env_variable("APP_DOMAIN");
     }
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_fetch_file_url_default() {
-        remove_env_variable("APP_FILE_DOMAIN");
-
-        let domain = fetch_file_domain();
-        let port = fetch_server_port();
-
-        assert_eq!(domain, format!("http://localhost:{port}/files/"));
+    #[test]
+    fn test_fetch_file_url_default() {
+        assert_eq!(fetch_file_domain(&sources(&[])), "http://localhost:8080/files/");
+        assert_eq!(fetch_file_domain(&sources(&[("SERVER_PORT", "9090")])), "http://localhost:9090/files/");
     }
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_fetch_telegram_api() {
-        set_env_variable("TELEGRAM_API_URL", "http://api.test.com");
-
-        let api_url = fetch_telegram_api();
+    #[test]
+    fn test_fetch_telegram_api() {
+        assert_eq!(fetch_telegram_api(&sources(&[("TELEGRAM_API_URL", "http://api.test.com")])), "http://api.test.com/");
+        assert_eq!(fetch_telegram_api(&sources(&[])), "https://api.telegram.org/");
+    }
 
-        assert_eq!(api_url, "http://api.test.com/");
+    #[test]
+    fn test_fetch_pipe_path() {
+        assert_eq!(fetch_pipe_path(&sources(&[("FILESLINK_PIPE_PATH", "/custom/path.pipe")])), "/custom/path.pipe");
+        assert_eq!(fetch_pipe_path(&sources(&[])), "/tmp/fileslink.pipe");
+    }
 
-        remove_env_variable("TELEGRAM_API_URL");
+    #[test]
+    fn test_fetch_enable_files_route() {
+        assert!(fetch_enable_files_route(&sources(&[("ENABLE_FILES_ROUTE", "true")])));
+        assert!(!fetch_enable_files_route(&sources(&[("ENABLE_FILES_ROUTE", "false")])));
+        assert!(!fetch_enable_files_route(&sources(&[])));
     }
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_fetch_telegram_api_default() {
-        remove_env_variable("TELEGRAM_API_URL");
+    #[test]
+    fn test_fetch_file_cache_settings() {
+        let set = sources(&[("FILE_CACHE_DIR", "/var/cache/fileslink"), ("FILE_CACHE_MAX_SIZE_MB", "10")]);
 
-        let api_url = fetch_telegram_api();
+        assert_eq!(fetch_file_cache_dir(&set), Some("/var/cache/fileslink".to_string()));
+        assert_eq!(fetch_file_cache_max_size(&set), 10 * 1024 * 1024);
+        assert_eq!(fetch_file_cache_max_age(&set), 168 * 60 * 60);
 
-        assert_eq!(api_url, "https://api.telegram.org/");
+        assert_eq!(fetch_file_cache_dir(&sources(&[])), None);
     }
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_fetch_pipe_path() {
-        set_env_variable("FILESLINK_PIPE_PATH", "/custom/path.pipe");
+    #[test]
+    fn test_fetch_backup_settings() {
+        let set = sources(&[
+            ("BACKUP_DIR", "/var/backups/fileslink"),
+            ("BACKUP_INTERVAL_HOURS", "0"),
+            ("BACKUP_KEEP", "3"),
+            ("BACKUP_TO_CHANNEL", "true"),
+        ]);
 
-        let pipe_path = fetch_pipe_path();
+        assert_eq!(fetch_backup_dir(&set), Some("/var/backups/fileslink".to_string()));
+        assert_eq!(fetch_backup_interval(&set), 24 * 60 * 60);
+        assert_eq!(fetch_backup_keep(&set), 3);
+        assert!(fetch_backup_to_channel(&set));
 
-        assert_eq!(pipe_path, "/custom/path.pipe");
+        let unset = sources(&[]);
+        assert_eq!(fetch_backup_dir(&unset), None);
+        assert_eq!(fetch_backup_keep(&unset), 7);
+        assert!(!fetch_backup_to_channel(&unset));
 
-        remove_env_variable("FILESLINK_PIPE_PATH");
+        // Zero would be replaced by the default, so it is reported instead
+        assert_eq!(set.problems(), vec!["BACKUP_INTERVAL_HOURS (environment): '0': expected a whole number above zero"]);
+        assert_eq!(sources(&[("BACKUP_KEEP", "0")]).problems(), vec!["BACKUP_KEEP (environment): '0': expected a whole number above zero"]);
     }
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_fetch_pipe_path_default() {
-        remove_env_variable("FILESLINK_PIPE_PATH");
-
-        let pipe_path = fetch_pipe_path();
-
-        assert_eq!(pipe_path, "/tmp/fileslink.pipe");
+    #[test]
+    fn test_fetch_permissions_open_mode() {
+        assert!(!fetch_permissions_open_mode(&sources(&[])));
+        assert!(fetch_permissions_open_mode(&sources(&[("PERMISSIONS_OPEN_MODE", "true")])));
     }
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_fetch_enable_files_route_true() {
-        set_env_variable("ENABLE_FILES_ROUTE", "true");
-
-        let enable_files_route = fetch_enable_files_route();
+    #[test]
+    fn test_environment_overrides_file() {
+        let file = || ConfigFile::parse("[server]\nport = 9000\npipe_path = \"/from/file.pipe\"\n").unwrap();
+        let port = setting("SERVER_PORT").unwrap();
 
-        assert!(enable_files_route);
+        let from_file = sources(&[]).with_file(file());
+        assert_eq!(from_file.lookup(port), Some(("9000".to_owned(), Source::File)));
+        assert!(from_file.problems().is_empty());
 
-        remove_env_variable("ENABLE_FILES_ROUTE");
+        let overridden = sources(&[("SERVER_PORT", "abc")]).with_file(file());
+        assert_eq!(overridden.lookup(port), Some(("abc".to_owned(), Source::Env)));
+        assert_eq!(overridden.problems(), vec!["SERVER_PORT (environment): 'abc': expected a port number between 0 and 65535"]);
+        assert_eq!(fetch_pipe_path(&overridden), "/from/file.pipe");
     }
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_fetch_enable_files_route_false() {
-        set_env_variable("ENABLE_FILES_ROUTE", "false");
-
-        let enable_files_route = fetch_enable_files_route();
+    #[test]
+    fn test_check_http_url() {
+        assert!(check_http_url("https://api.telegram.org/").is_ok());
+        assert!(check_http_url("http://localhost:8001").is_ok());
+        assert!(check_http_url("ftp://example.com").unwrap_err().contains("scheme"));
+        assert!(check_http_url("localhost:8001").is_err());
+        assert!(check_http_url("not a url").is_err());
+    }
 
-        assert!(!enable_files_route);
+    #[test]
+    fn test_config_reports_every_problem() {
+        let config = Config::from_sources(&sources(&[
+            ("SERVER_PORT", "40000"),
+            ("STORAGE_CHANNEL_ID", "-100123"),
+            ("FASTTELETHON_URL", "ftp://fasttelethon"),
+        ]));
 
-        remove_env_variable("ENABLE_FILES_ROUTE");
-    }
+        let problems = config.problems();
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].starts_with("BOT_TOKEN"));
+        assert!(problems[1].starts_with("FASTTELETHON_URL (telegram.fasttelethon_url)"));
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_fetch_enable_files_route_default() {
-        remove_env_variable("ENABLE_FILES_ROUTE");
+        // Ports above i16::MAX used to fall back to 8080
+        assert_eq!(config.server_port(), 40000);
 
-        let enable_files_route = fetch_enable_files_route();
+        let config = Config::from_sources(&sources(&[
+            ("SERVER_PORT", "70000"),
+            ("STORAGE_CHANNEL_ID", "-100123"),
+            ("BOT_TOKEN", "test_token"),
+        ]));
 
-        assert!(!enable_files_route);
+        assert_eq!(config.problems(), vec!["SERVER_PORT (environment): '70000': expected a port number between 0 and 65535"]);
     }
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_config_new() {
-        set_env_variable("BOT_TOKEN", "test_token");
-        set_env_variable("SERVER_PORT", "9090");
-        set_env_variable("APP_FILE_DOMAIN", "http://example.com/files");
-        set_env_variable("TELEGRAM_API_URL", "http://api.test.com");
-        set_env_variable("FILESLINK_PIPE_PATH", "/custom/path.pipe");
-        set_env_variable("ENABLE_FILES_ROUTE", "true");
-
-        let config = Config::new();
+    #[test]
+    fn test_config_from_sources() {
+        let config = Config::from_sources(&sources(&[
+            ("BOT_TOKEN", "test_token"),
+            ("SERVER_PORT", "9090"),
+            ("APP_FILE_DOMAIN", "http://example.com/files"),
+            ("TELEGRAM_API_URL", "http://api.test.com"),
+            ("FILESLINK_PIPE_PATH", "/custom/path.pipe"),
+            ("ENABLE_FILES_ROUTE", "true"),
+        ]));
 
         assert_eq!(config.bot_token, Ok("test_token".to_string()));
         assert_eq!(config.server_port, 9090);
@@ -374,39 +711,36 @@ mod tests {
         assert_eq!(config.telegram_api_url, "http://api.test.com/");
         assert_eq!(config.pipe_path, "/custom/path.pipe");
         assert!(config.enable_files_route);
-
-        remove_env_variable("BOT_TOKEN");
-        remove_env_variable("SERVER_PORT");
-        remove_env_variable("APP_DOMAIN");
-        remove_env_variable("TELEGRAM_API_URL");
-        remove_env_variable("FILESLINK_PIPE_PATH");
-        remove_env_variable("ENABLE_FILES_ROUTE");
+        assert_eq!(config.origins.get("SERVER_PORT"), Some(&Source::Env));
+        assert_eq!(config.origins.get("BACKUP_DIR"), Some(&Source::Default));
     }
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_config_instance() {
-        set_env_variable("BOT_TOKEN", "test_token");
-        set_env_variable("SERVER_PORT", "9090");
-        set_env_variable("APP_FILE_DOMAIN", "http://example.com/files");
-        set_env_variable("TELEGRAM_API_URL", "http://api.test.com");
-        set_env_variable("FILESLINK_PIPE_PATH", "/custom/path.pipe");
-        set_env_variable("ENABLE_FILES_ROUTE", "true");
+    #[test]
+    fn test_shared_config_reload() {
+        let base = [("BOT_TOKEN", "test_token"), ("STORAGE_CHANNEL_ID", "-100123")];
+        let shared = SharedConfig::new(Config::from_sources(&sources(&base)));
+        let before = shared.get();
 
-        let config = Config::instance().await;
+        let notes = shared.reload(&sources(&[
+            base[0],
+            base[1],
+            ("SERVER_PORT", "9090"),
+            ("APP_FILE_DOMAIN", "https://files.example.com/files"),
+            ("ENABLE_FILES_ROUTE", "true"),
+        ])).unwrap();
 
-        assert_eq!(config.bot_token.clone().expect(""), "test_token".to_string());
-        assert_eq!(config.server_port, 9090);
-        assert_eq!(config.file_domain, "http://example.com/files/");
-        assert_eq!(config.telegram_api_url, "http://api.test.com/");
-        assert_eq!(config.pipe_path, "/custom/path.pipe");
-        assert!(config.enable_files_route);
+        assert_eq!(notes, vec!["SERVER_PORT changed; restart FilesLink to apply it"]);
+
+        let after = shared.get();
+        assert_eq!(after.server_port(), 8080);
+        assert_eq!(after.file_domain(), "https://files.example.com/files/");
+        assert!(after.enable_files_route());
+
+        // Readers holding the previous configuration keep it
+        assert!(!before.enable_files_route());
 
-        remove_env_variable("BOT_TOKEN");
-        remove_env_variable("SERVER_PORT");
-        remove_env_variable("APP_DOMAIN");
-        remove_env_variable("TELEGRAM_API_URL");
-        remove_env_variable("FILESLINK_PIPE_PATH");
-        remove_env_variable("ENABLE_FILES_ROUTE");
+        let problems = shared.reload(&sources(&[base[0], ("ENABLE_FILES_ROUTE", "false")])).unwrap_err();
+        assert!(problems[0].starts_with("STORAGE_CHANNEL_ID"), "{:?}", problems);
+        assert!(shared.get().enable_files_route());
     }
 }
@@ -0,0 +1,205 @@
+// `fileslink.toml`: the optional config file underneath the environment variables.
+// Every setting has a TOML key and an environment variable; the variable wins.
+
+use std::env;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Default location, relative to the working directory; `FILESLINK_CONFIG` overrides it
+pub const DEFAULT_CONFIG_FILE: &str = "fileslink.toml";
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(default)]
+    telegram: TelegramSection,
+    #[serde(default)]
+    server: ServerSection,
+    #[serde(default)]
+    cache: CacheSection,
+    #[serde(default)]
+    backup: BackupSection,
+    #[serde(default)]
+    permissions: PermissionsSection,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct TelegramSection {
+    bot_token: Option<String>,
+    storage_channel_id: Option<i64>,
+    api_url: Option<String>,
+    fasttelethon_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct ServerSection {
+    port: Option<u16>,
+    file_domain: Option<String>,
+    enable_files_route: Option<bool>,
+    pipe_path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct CacheSection {
+    dir: Option<String>,
+    max_size_mb: Option<u64>,
+    max_age_hours: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct BackupSection {
+    dir: Option<String>,
+    interval_hours: Option<u64>,
+    keep: Option<usize>,
+    to_channel: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct PermissionsSection {
+    open_mode: Option<bool>,
+}
+
+/// One setting: its environment variable, its key in the file and how values are checked
+pub struct Setting {
+    pub env: &'static str,
+    pub key: &'static str,
+    pub secret: bool,
+    check: fn(&str) -> Result<(), String>,
+}
+
+fn any(_: &str) -> Result<(), String> {
+    Ok(())
+}
+
+fn boolean(value: &str) -> Result<(), String> {
+    value.parse::<bool>().map(|_| ()).map_err(|_| "expected true or false".to_owned())
+}
+
+fn unsigned(value: &str) -> Result<(), String> {
+    value.parse::<u64>().map(|_| ()).map_err(|_| "expected a non-negative whole number".to_owned())
+}
+
+fn positive(value: &str) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(value) if value > 0 => Ok(()),
+        _ => Err("expected a whole number above zero".to_owned()),
+    }
+}
+
+fn port(value: &str) -> Result<(), String> {
+    value.parse::<u16>().map(|_| ()).map_err(|_| "expected a port number between 0 and 65535".to_owned())
+}
+
+pub const SETTINGS: &[Setting] = &[
+    Setting { env: "BOT_TOKEN", key: "telegram.bot_token", secret: true, check: any },
+    // Reported by `fetch_storage_channel_id`, which also catches a missing id
+    Setting { env: "STORAGE_CHANNEL_ID", key: "telegram.storage_channel_id", secret: false, check: any },
+    Setting { env: "TELEGRAM_API_URL", key: "telegram.api_url", secret: false, check: any },
+    Setting { env: "FASTTELETHON_URL", key: "telegram.fasttelethon_url", secret: false, check: any },
+    Setting { env: "SERVER_PORT", key: "server.port", secret: false, check: port },
+    Setting { env: "APP_FILE_DOMAIN", key: "server.file_domain", secret: false, check: any },
+    Setting { env: "ENABLE_FILES_ROUTE", key: "server.enable_files_route", secret: false, check: boolean },
+    Setting { env: "FILESLINK_PIPE_PATH", key: "server.pipe_path", secret: false, check: any },
+    Setting { env: "FILE_CACHE_DIR", key: "cache.dir", secret: false, check: any },
+    Setting { env: "FILE_CACHE_MAX_SIZE_MB", key: "cache.max_size_mb", secret: false, check: unsigned },
+    Setting { env: "FILE_CACHE_MAX_AGE_HOURS", key: "cache.max_age_hours", secret: false, check: unsigned },
+    Setting { env: "BACKUP_DIR", key: "backup.dir", secret: false, check: any },
+    Setting { env: "BACKUP_INTERVAL_HOURS", key: "backup.interval_hours", secret: false, check: positive },
+    Setting { env: "BACKUP_KEEP", key: "backup.keep", secret: false, check: positive },
+    Setting { env: "BACKUP_TO_CHANNEL", key: "backup.to_channel", secret: false, check: boolean },
+    Setting { env: "PERMISSIONS_OPEN_MODE", key: "permissions.open_mode", secret: false, check: boolean },
+];
+
+impl Setting {
+    pub fn check(&self, value: &str) -> Result<(), String> {
+        (self.check)(value)
+    }
+}
+
+pub fn setting(env: &str) -> Option<&'static Setting> {
+    SETTINGS.iter().find(|setting| setting.env == env)
+}
+
+/// Where a resolved value came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Env,
+    File,
+    Default,
+}
+
+impl ConfigFile {
+    /// Parse the file; errors name the line and the offending key
+    pub fn parse(data: &str) -> Result<Self, String> {
+        toml::from_str(data).map_err(|e| e.to_string().trim_end().to_owned())
+    }
+
+    /// The value of `key` ("section.field") as text, as if it came from the environment
+    pub fn value(&self, key: &str) -> Option<String> {
+        let (section, field) = key.split_once('.')?;
+        let table = toml::Value::try_from(self).ok()?;
+
+        match table.get(section)?.get(field)? {
+            toml::Value::String(value) => Some(value.clone()),
+            value => Some(value.to_string()),
+        }
+    }
+}
+
+pub fn config_file_path() -> String {
+    env::var("FILESLINK_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_FILE.to_owned())
+}
+
+/// `Ok(None)` when there is no file, which is fine: everything can come from the environment
+pub fn read_config_file(path: &str) -> Result<Option<ConfigFile>, String> {
+    if !Path::new(path).exists() {
+        return Ok(None);
+    }
+
+    let data = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+
+    ConfigFile::parse(&data).map(Some).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Hide a secret but keep enough to tell which one is configured; for a bot
+/// token that is the bot id before the colon
+pub fn mask(value: &str) -> String {
+    match value.split_once(':') {
+        Some((id, _)) => format!("{}:****", id),
+        None if value.is_empty() => String::new(),
+        None => "****".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config_file() {
+        let file = ConfigFile::parse("[server]\nport = 9000\nenable_files_route = true\n\n[telegram]\nbot_token = \"123:abc\"\n").unwrap();
+
+        assert_eq!(file.value("server.port"), Some("9000".to_owned()));
+        assert_eq!(file.value("server.enable_files_route"), Some("true".to_owned()));
+        assert_eq!(file.value("telegram.bot_token"), Some("123:abc".to_owned()));
+        assert_eq!(file.value("backup.dir"), None);
+
+        let error = ConfigFile::parse("[server]\nprot = 9000\n").unwrap_err();
+        assert!(error.contains("line 2") && error.contains("prot"), "{}", error);
+
+        let error = ConfigFile::parse("[server]\nport = 99999\n").unwrap_err();
+        assert!(error.contains("line 2"), "{}", error);
+    }
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask("123456:ABC-DEF"), "123456:****");
+        assert_eq!(mask("secret"), "****");
+    }
+}
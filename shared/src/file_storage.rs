@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
 use std::path::Path;
 use tokio::fs;
@@ -6,6 +7,8 @@ use tokio::sync::RwLock;
 use log::info;
 use once_cell::sync::Lazy;
 
+use crate::migrations::{backup_before_migration, log_migration, migrate, Migration};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileMetadata {
     pub unique_id: String,
@@ -18,28 +21,77 @@ pub struct FileMetadata {
     pub message_id: Option<i32>,  // Telegram message ID for large files
 }
 
+/// Current version of `file_mappings.json`, bump together with a new entry in `MIGRATIONS`
+pub const FILE_STORAGE_VERSION: u32 = 1;
+
 #[derive(Debug, Serialize, Deserialize)]
 struct FileStorageData {
+    version: u32,
     files: HashMap<String, FileMetadata>,
 }
 
 static FILE_STORAGE: Lazy<RwLock<FileStorageData>> = Lazy::new(|| {
     RwLock::new(FileStorageData {
+        version: FILE_STORAGE_VERSION,
         files: HashMap::new(),
     })
 });
 
 const STORAGE_FILE_PATH: &str = "file_mappings.json";
 
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        description: "add format version and store every record's unique id from its key",
+        apply: sync_unique_ids,
+    },
+];
+
+/// Early mappings could miss `unique_id` inside the record or disagree with it.
+/// The map key is authoritative, it is the id in every link handed out.
+fn sync_unique_ids(value: &mut Value) -> Result<(), String> {
+    let files = match value.get_mut("files") {
+        Some(Value::Object(files)) => files,
+        Some(_) => return Err("'files' is not an object".to_owned()),
+        None => {
+            value["files"] = Value::Object(Default::default());
+            return Ok(());
+        }
+    };
+
+    for (key, record) in files.iter_mut() {
+        match record {
+            Value::Object(record) => {
+                record.insert("unique_id".to_owned(), Value::from(key.clone()));
+            }
+            _ => return Err(format!("record '{}' is not an object", key)),
+        }
+    }
+
+    Ok(())
+}
+
 pub async fn init_file_storage() -> Result<(), String> {
     if Path::new(STORAGE_FILE_PATH).exists() {
         let content = fs::read_to_string(STORAGE_FILE_PATH)
             .await
             .map_err(|e| format!("Failed to read file mappings: {}", e))?;
-        
-        let data: FileStorageData = serde_json::from_str(&content)
+
+        let mut raw: Value = serde_json::from_str(&content)
             .map_err(|e| format!("Failed to parse file mappings: {}", e))?;
-        
+
+        let report = migrate(&mut raw, MIGRATIONS, FILE_STORAGE_VERSION)
+            .map_err(|e| format!("Failed to migrate file mappings: {}", e))?;
+
+        let data: FileStorageData = serde_json::from_value(raw)
+            .map_err(|e| format!("Failed to parse file mappings: {}", e))?;
+
+        if !report.is_empty() {
+            let backup = backup_before_migration(STORAGE_FILE_PATH, report.from).await?;
+            write_storage(&data).await?;
+            log_migration(STORAGE_FILE_PATH, &report, &backup);
+        }
+
         let mut storage = FILE_STORAGE.write().await;
         *storage = data;
         
@@ -51,18 +103,39 @@ pub async fn init_file_storage() -> Result<(), String> {
     Ok(())
 }
 
-pub async fn save_file_metadata(metadata: FileMetadata) -> Result<(), String> {
-    let mut storage = FILE_STORAGE.write().await;
-    storage.files.insert(metadata.unique_id.clone(), metadata);
-    
-    let json = serde_json::to_string_pretty(&*storage)
+async fn write_storage(storage: &FileStorageData) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(storage)
         .map_err(|e| format!("Failed to serialize file mappings: {}", e))?;
-    
+
     fs::write(STORAGE_FILE_PATH, json)
         .await
-        .map_err(|e| format!("Failed to write file mappings: {}", e))?;
+        .map_err(|e| format!("Failed to write file mappings: {}", e))
+}
+
+pub async fn save_file_metadata(metadata: FileMetadata) -> Result<(), String> {
+    let mut storage = FILE_STORAGE.write().await;
+    storage.files.insert(metadata.unique_id.clone(), metadata);
     
-    Ok(())
+    write_storage(&storage).await
+}
+
+/// Insert or replace many records with a single write of the mappings file
+pub async fn save_file_metadata_batch(records: Vec<FileMetadata>) -> Result<(), String> {
+    let mut storage = FILE_STORAGE.write().await;
+
+    for metadata in records {
+        storage.files.insert(metadata.unique_id.clone(), metadata);
+    }
+
+    write_storage(&storage).await
+}
+
+/// Replace all records, e.g. when restoring a backup
+pub async fn replace_all_file_metadata(records: Vec<FileMetadata>) -> Result<(), String> {
+    let mut storage = FILE_STORAGE.write().await;
+    storage.files = records.into_iter().map(|m| (m.unique_id.clone(), m)).collect();
+
+    write_storage(&storage).await
 }
 
 pub async fn get_file_metadata(unique_id: &str) -> Option<FileMetadata> {
@@ -79,12 +152,50 @@ pub async fn delete_file_metadata(unique_id: &str) -> Result<(), String> {
     let mut storage = FILE_STORAGE.write().await;
     storage.files.remove(unique_id);
     
-    let json = serde_json::to_string_pretty(&*storage)
-        .map_err(|e| format!("Failed to serialize file mappings: {}", e))?;
-    
-    fs::write(STORAGE_FILE_PATH, json)
-        .await
-        .map_err(|e| format!("Failed to write file mappings: {}", e))?;
-    
-    Ok(())
+    write_storage(&storage).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_migrate_unversioned_mappings() {
+        let mut raw = json!({
+            "files": {
+                "abcd1234": {
+                    "telegram_file_id": "BQAC",
+                    "file_name": "a.txt",
+                    "mime_type": null,
+                    "file_size": 3,
+                    "uploaded_at": 1
+                },
+                "efgh5678": {
+                    "unique_id": "stale",
+                    "telegram_file_id": "BQAD",
+                    "file_name": "b.txt",
+                    "mime_type": null,
+                    "file_size": 4,
+                    "uploaded_at": 2
+                }
+            }
+        });
+
+        let report = migrate(&mut raw, MIGRATIONS, FILE_STORAGE_VERSION).unwrap();
+        assert_eq!((report.from, report.to), (0, 1));
+
+        let data: FileStorageData = serde_json::from_value(raw).unwrap();
+        assert_eq!(data.version, FILE_STORAGE_VERSION);
+        assert_eq!(data.files["abcd1234"].unique_id, "abcd1234");
+        assert_eq!(data.files["abcd1234"].message_id, None);
+        assert_eq!(data.files["efgh5678"].unique_id, "efgh5678");
+        assert!(!data.files.contains_key("stale"));
+
+        let mut broken = json!({ "files": { "abcd1234": "not a record" } });
+        assert_eq!(
+            migrate(&mut broken, MIGRATIONS, FILE_STORAGE_VERSION).unwrap_err(),
+            "migration from version 0 failed: record 'abcd1234' is not an object"
+        );
+    }
 }
@@ -1,6 +1,12 @@
 pub mod chat_config;
 pub mod config;
+pub mod config_file;
 pub mod utils;
 pub mod link_utils;
+pub mod html_utils;
 pub mod cli_utils;
 pub mod file_storage;
+pub mod api_keys;
+pub mod upload_tokens;
+pub mod backup;
+pub mod migrations;
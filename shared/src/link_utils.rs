@@ -1,5 +1,17 @@
 // Utilities for building and parsing file URLs
 
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+
+/// Characters that must be encoded inside a single URL path segment
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'/').add(b'<')
+    .add(b'>').add(b'?').add(b'`').add(b'{').add(b'}');
+
+/// Percent-encode a value for use as one path segment, e.g. the result of `build_url_path`
+pub fn encode_path_segment(segment: &str) -> String {
+    utf8_percent_encode(segment, PATH_SEGMENT).to_string()
+}
+
 /// Build URL path in the format: "<unique_id>_<url_safe_filename>"
 /// - Replaces spaces in filename with underscores for URL safety
 pub fn build_url_path(unique_id: &str, file_name: &str) -> String {
@@ -7,6 +19,30 @@ pub fn build_url_path(unique_id: &str, file_name: &str) -> String {
     format!("{}_{}", unique_id, safe)
 }
 
+/// Build the public download link handed out to users.
+/// - `file_domain` is expected to end with a slash (see `Config::file_domain`)
+/// - Adds `?close=1` so the browser tab closes once the download starts
+/// - Characters like `#` and `?` in the name are percent-encoded so they cannot cut the link short
+pub fn build_download_url(file_domain: &str, unique_id: &str, file_name: &str) -> String {
+    format!("{}{}?close=1", file_domain, encode_path_segment(&build_url_path(unique_id, file_name)))
+}
+
+/// Root of the site derived from the file domain: pages like `/upload` and `/v/`
+/// live next to `/files/`, so the `files/` suffix of the domain is dropped.
+fn site_base_url(file_domain: &str) -> &str {
+    file_domain.strip_suffix("files/").unwrap_or(file_domain)
+}
+
+/// Build the browser upload link for a one-time upload token
+pub fn build_upload_url(file_domain: &str, token: &str) -> String {
+    format!("{}upload?token={}", site_base_url(file_domain), token)
+}
+
+/// Build the link to the in-browser preview page of a file
+pub fn build_preview_url(file_domain: &str, unique_id: &str, file_name: &str) -> String {
+    format!("{}v/{}", site_base_url(file_domain), encode_path_segment(&build_url_path(unique_id, file_name)))
+}
+
 /// Extract the unique id from a path of the form "<unique_id>_<filename>".
 /// Since unique_id is always 8 characters (from nanoid!(8)), we can extract it reliably.
 /// Falls back to checking for underscore at position 8 if present.
@@ -39,6 +75,36 @@ mod tests {
         assert_eq!(p, "id8_hello_world.txt");
     }
 
+    #[test]
+    fn test_build_download_url() {
+        let url = build_download_url("https://example.com/files/", "abc12345", "My File.pdf");
+        assert_eq!(url, "https://example.com/files/abc12345_My_File.pdf?close=1");
+    }
+
+    #[test]
+    fn test_build_download_url_hostile_name() {
+        let url = build_download_url("https://example.com/files/", "abc12345", "a#b?c=\"d\".txt");
+        assert_eq!(url, "https://example.com/files/abc12345_a%23b%3Fc=%22d%22.txt?close=1");
+    }
+
+    #[test]
+    fn test_build_upload_url() {
+        let url = build_upload_url("https://example.com/files/", "tok");
+        assert_eq!(url, "https://example.com/upload?token=tok");
+    }
+
+    #[test]
+    fn test_build_preview_url() {
+        let url = build_preview_url("https://example.com/files/", "abc12345", "clip one.mp4");
+        assert_eq!(url, "https://example.com/v/abc12345_clip_one.mp4");
+    }
+
+    #[test]
+    fn test_encode_path_segment() {
+        assert_eq!(encode_path_segment("abc_a#b?c/d%.txt"), "abc_a%23b%3Fc%2Fd%25.txt");
+        assert_eq!(encode_path_segment("abc_файл.txt"), "abc_%D1%84%D0%B0%D0%B9%D0%BB.txt");
+    }
+
     #[test]
     fn test_extract_id_from_path_with_filename() {
         let id = extract_id_from_path("ZvOWMhv1_report.pdf");
@@ -0,0 +1,126 @@
+// Versioned on-disk formats. Each data file carries a `version` field; files without
+// one are version 0. Migrations run on the raw JSON so old layouts never need a
+// Rust type of their own.
+
+use std::path::{Path, PathBuf};
+
+use log::info;
+use serde_json::Value;
+use tokio::fs;
+
+/// One step of a migration chain, turning version `from` into `from + 1`
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    pub apply: fn(&mut Value) -> Result<(), String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MigrationReport {
+    pub from: u32,
+    pub to: u32,
+    pub applied: Vec<&'static str>,
+}
+
+impl MigrationReport {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty()
+    }
+}
+
+pub fn version_of(value: &Value) -> u32 {
+    value.get("version").and_then(Value::as_u64).unwrap_or(0) as u32
+}
+
+/// Bring `value` up to `current` by running the chain. Files written by a newer
+/// build are refused rather than silently downgraded.
+pub fn migrate(value: &mut Value, migrations: &[Migration], current: u32) -> Result<MigrationReport, String> {
+    let from = version_of(value);
+
+    if from > current {
+        return Err(format!("format version {} is newer than this build supports ({})", from, current));
+    }
+
+    if !value.is_object() {
+        return Err("expected a JSON object".to_owned());
+    }
+
+    let mut applied = Vec::new();
+
+    for version in from..current {
+        let migration = migrations.iter()
+            .find(|m| m.from == version)
+            .ok_or_else(|| format!("no migration from version {}", version))?;
+
+        (migration.apply)(value)
+            .map_err(|e| format!("migration from version {} failed: {}", version, e))?;
+
+        value["version"] = Value::from(version + 1);
+        applied.push(migration.description);
+    }
+
+    Ok(MigrationReport { from, to: current, applied })
+}
+
+/// Copy `path` next to itself as `<path>.v<version>.bak` before it is rewritten
+pub async fn backup_before_migration(path: &str, version: u32) -> Result<PathBuf, String> {
+    let mut backup = PathBuf::from(format!("{}.v{}.bak", path, version));
+    let mut n = 1;
+
+    // Never overwrite an earlier backup
+    while backup.exists() {
+        backup = PathBuf::from(format!("{}.v{}.{}.bak", path, version, n));
+        n += 1;
+    }
+
+    fs::copy(Path::new(path), &backup).await
+        .map_err(|e| format!("Failed to back up {} to {}: {}", path, backup.display(), e))?;
+
+    Ok(backup)
+}
+
+pub fn log_migration(path: &str, report: &MigrationReport, backup: &Path) {
+    info!("Migrated {} from version {} to {} (previous file kept as {})", path, report.from, report.to, backup.display());
+
+    for step in &report.applied {
+        info!("  - {}", step);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rename_field(value: &mut Value) -> Result<(), String> {
+        let old = value.as_object_mut().unwrap().remove("old").unwrap_or(Value::Null);
+        value["new"] = old;
+        Ok(())
+    }
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration { from: 0, description: "add version", apply: |_| Ok(()) },
+        Migration { from: 1, description: "rename old to new", apply: rename_field },
+    ];
+
+    #[test]
+    fn test_migrate_runs_chain_from_file_version() {
+        let mut value = json!({ "old": 1 });
+        let report = migrate(&mut value, MIGRATIONS, 2).unwrap();
+
+        assert_eq!(report.applied, vec!["add version", "rename old to new"]);
+        assert_eq!(value, json!({ "new": 1, "version": 2 }));
+
+        let mut value = json!({ "version": 1, "old": 2 });
+        assert_eq!(migrate(&mut value, MIGRATIONS, 2).unwrap().applied, vec!["rename old to new"]);
+
+        assert!(migrate(&mut value, MIGRATIONS, 2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_refuses_newer_and_gaps() {
+        assert!(migrate(&mut json!({ "version": 3 }), MIGRATIONS, 2).unwrap_err().contains("newer"));
+        assert!(migrate(&mut json!({}), MIGRATIONS, 3).is_err());
+        assert!(migrate(&mut json!([]), MIGRATIONS, 2).is_err());
+    }
+}
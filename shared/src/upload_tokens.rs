@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+use nanoid::nanoid;
+use once_cell::sync::Lazy;
+use tokio::sync::Mutex;
+
+/// Default lifetime of an upload link issued by the bot
+pub const DEFAULT_UPLOAD_TOKEN_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Upper bound accepted for `/uploadlink <hours>`
+pub const MAX_UPLOAD_TOKEN_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Debug, Clone)]
+struct UploadToken {
+    expires_at: Instant,
+    /// Browser session bound to the token once the link has been opened
+    session_id: Option<String>,
+}
+
+/// One-time upload links, kept in memory only: a restart invalidates outstanding links
+static UPLOAD_TOKENS: Lazy<Mutex<HashMap<String, UploadToken>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn remove_expired(tokens: &mut HashMap<String, UploadToken>) {
+    let now = Instant::now();
+    tokens.retain(|_, token| token.expires_at > now);
+}
+
+/// Issue a new upload token valid for `ttl`
+pub async fn issue_upload_token(ttl: Duration) -> String {
+    let mut tokens = UPLOAD_TOKENS.lock().await;
+    remove_expired(&mut tokens);
+
+    let token = nanoid!(32);
+
+    tokens.insert(token.clone(), UploadToken {
+        expires_at: Instant::now() + ttl.min(MAX_UPLOAD_TOKEN_TTL),
+        session_id: None,
+    });
+
+    info!("Issued upload token valid for {:?}", ttl.min(MAX_UPLOAD_TOKEN_TTL));
+
+    token
+}
+
+/// Redeem a token for a browser session. A token can only be redeemed once;
+/// returns the session id and its remaining lifetime.
+pub async fn claim_upload_token(token: &str) -> Option<(String, Duration)> {
+    let mut tokens = UPLOAD_TOKENS.lock().await;
+    remove_expired(&mut tokens);
+
+    let entry = tokens.get_mut(token)?;
+
+    if entry.session_id.is_some() {
+        debug!("Upload token has already been used");
+
+        return None;
+    }
+
+    let session_id = nanoid!(32);
+    entry.session_id = Some(session_id.clone());
+
+    Some((session_id, entry.expires_at.saturating_duration_since(Instant::now())))
+}
+
+/// Check that a browser session belongs to a redeemed, unexpired token
+pub async fn verify_upload_session(session_id: &str) -> bool {
+    let mut tokens = UPLOAD_TOKENS.lock().await;
+    remove_expired(&mut tokens);
+
+    tokens.values().any(|token| token.session_id.as_deref() == Some(session_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_upload_token_is_one_time() {
+        let token = issue_upload_token(DEFAULT_UPLOAD_TOKEN_TTL).await;
+
+        let (session_id, ttl) = claim_upload_token(&token).await.expect("token should be claimable");
+
+        assert!(ttl <= DEFAULT_UPLOAD_TOKEN_TTL);
+        assert!(verify_upload_session(&session_id).await);
+        assert!(claim_upload_token(&token).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_upload_token() {
+        let token = issue_upload_token(Duration::ZERO).await;
+
+        assert!(claim_upload_token(&token).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_upload_session() {
+        assert!(!verify_upload_session("unknown").await);
+        assert!(claim_upload_token("unknown").await.is_none());
+    }
+}
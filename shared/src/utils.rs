@@ -8,6 +8,62 @@ pub fn get_file_name_from_path(path: &str) -> Option<&str> {
     Path::new(path).file_name()?.to_str()
 }
 
+pub fn human_size(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    const GB: u64 = MB * 1024;
+    match bytes {
+        b if b >= GB => format!("{:.2} GB", b as f64 / GB as f64),
+        b if b >= MB => format!("{:.2} MB", b as f64 / MB as f64),
+        b if b >= KB => format!("{:.2} KB", b as f64 / KB as f64),
+        _ => format!("{} bytes", bytes),
+    }
+}
+
+/// Format a unix timestamp as "YYYY-MM-DD HH:MM UTC"
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+
+    // Civil-from-days conversion (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, rem / 3_600, rem % 3_600 / 60)
+}
+
+/// Parse a duration like "90s", "30m", "12h", "7d" or "2w"
+pub fn parse_duration(value: &str) -> Result<std::time::Duration, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+
+    let amount: u64 = amount.parse()
+        .map_err(|_| format!("'{}' is not a duration, use e.g. 30m, 12h or 7d", value))?;
+
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("'{}' is not a duration, use e.g. 30m, 12h or 7d", value)),
+    };
+
+    if amount == 0 {
+        return Err("Duration must be greater than zero".to_owned());
+    }
+
+    Ok(std::time::Duration::from_secs(amount.saturating_mul(seconds)))
+}
+
 pub async fn get_file_size(path: &str) -> io::Result<u64> {
     let metadata = fs::metadata(path).await.expect("Failed to read file metadata");
 
@@ -60,4 +116,45 @@ pub async fn create_fifo(path: &str) -> Result<(), String> {
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Whether a running server reads the FIFO. Opening the write end without blocking
+/// only succeeds while it has a reader.
+pub fn fifo_has_reader(path: &str) -> bool {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(512), "512 bytes");
+        assert_eq!(human_size(1536), "1.50 KB");
+        assert_eq!(human_size(5 * 1024 * 1024), "5.00 MB");
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00 UTC");
+        assert_eq!(format_timestamp(1_718_454_896), "2024-06-15 12:34 UTC");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s"), Ok(std::time::Duration::from_secs(90)));
+        assert_eq!(parse_duration("12h"), Ok(std::time::Duration::from_secs(12 * 3600)));
+        assert_eq!(parse_duration("2w"), Ok(std::time::Duration::from_secs(14 * 86_400)));
+        assert!(parse_duration("10").is_err());
+        assert!(parse_duration("0d").is_err());
+        assert!(parse_duration("h").is_err());
+    }
+}
@@ -0,0 +1,264 @@
+use std::path::{Path, PathBuf};
+
+use axum::extract::{self, multipart::Field, Multipart, State};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use bot::storage::{store_local_file, MAX_UPLOAD_SIZE};
+use http::{HeaderMap, StatusCode};
+use log::{error, info, warn};
+use nanoid::nanoid;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+
+use shared::api_keys::{self, ApiKey, ApiKeyScope, DEFAULT_RATE_LIMIT};
+use shared::file_storage::{list_all_files, FileMetadata};
+use shared::link_utils::build_download_url;
+use shared::utils::get_file_name_from_path;
+
+use crate::auth::authorize;
+use crate::file_paths::file_path_stats;
+use crate::server::AppState;
+
+#[derive(Deserialize)]
+pub struct CreateKeyRequest {
+    name: String,
+    scopes: Vec<ApiKeyScope>,
+    rate_limit: Option<u32>,
+}
+
+fn key_json(key: &ApiKey) -> serde_json::Value {
+    json!({
+        "id": key.id,
+        "name": key.name,
+        "scopes": key.scopes,
+        "rate_limit": key.rate_limit,
+        "created_at": key.created_at,
+        "revoked_at": key.revoked_at,
+    })
+}
+
+pub(crate) fn json_error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+pub(crate) fn file_json(metadata: &FileMetadata, file_domain: &str) -> serde_json::Value {
+    json!({
+        "unique_id": metadata.unique_id,
+        "file_name": metadata.file_name,
+        "file_size": metadata.file_size,
+        "mime_type": metadata.mime_type,
+        "url": build_download_url(file_domain, &metadata.unique_id, &metadata.file_name),
+    })
+}
+
+/// Write a multipart field to a temporary file without buffering it in memory.
+/// Returns the path and number of bytes written; the file is removed on error.
+async fn spool_field(field: &mut Field<'_>) -> Result<(PathBuf, u64), (StatusCode, String)> {
+    let path = std::env::temp_dir().join(format!("fileslink-upload-{}", nanoid!()));
+
+    let result = async {
+        let mut file = File::create(&path).await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create temporary file: {}", e)))?;
+        let mut written: u64 = 0;
+
+        while let Some(chunk) = field.chunk().await
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read upload: {}", e)))? {
+            written += chunk.len() as u64;
+
+            if written > MAX_UPLOAD_SIZE {
+                return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("File exceeds the {} byte limit", MAX_UPLOAD_SIZE)));
+            }
+
+            file.write_all(&chunk).await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write temporary file: {}", e)))?;
+        }
+
+        file.flush().await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write temporary file: {}", e)))?;
+
+        Ok(written)
+    }.await;
+
+    match result {
+        Ok(written) => Ok((path, written)),
+        Err(e) => {
+            let _ = fs::remove_file(&path).await;
+            Err(e)
+        }
+    }
+}
+
+/// Upload one or more files (multipart field `file`) into the storage channel.
+/// Responds with the same links the bot hands out.
+pub async fn api_upload(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Response {
+    let key = match authorize(&headers, ApiKeyScope::Upload).await {
+        Ok(key) => key,
+        Err(response) => return response,
+    };
+
+    let mut stored = Vec::new();
+    let mut failed = Vec::new();
+    // Answered with when no file could be stored at all
+    let mut first_error: Option<(StatusCode, String)> = None;
+
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                let message = format!("Invalid multipart body: {}", e);
+                failed.push(json!({ "file_name": null, "error": message }));
+                first_error.get_or_insert((StatusCode::BAD_REQUEST, message));
+                break;
+            }
+        };
+
+        if field.name() != Some("file") {
+            continue;
+        }
+
+        // Only keep the last path component, clients may send full paths
+        let file_name = field.file_name()
+            .and_then(get_file_name_from_path)
+            .filter(|name| !name.is_empty())
+            .map(|name| name.to_owned())
+            .unwrap_or_else(|| format!("file_{}", nanoid!(8)));
+
+        let mime_type = field.content_type()
+            .filter(|m| *m != "application/octet-stream")
+            .map(|m| m.to_owned());
+
+        let (path, size) = match spool_field(&mut field).await {
+            Ok(spooled) => spooled,
+            Err((status, message)) => {
+                warn!("Rejected upload of {}: {}", file_name, message);
+
+                failed.push(json!({ "file_name": file_name, "error": message }));
+                first_error.get_or_insert((status, message));
+                continue;
+            }
+        };
+
+        info!("Received upload {} ({} bytes) with API key {}", file_name, size, key.id);
+
+        let result = store_local_file(&state.bot, &state.config.get(), Path::new(&path), &file_name, mime_type).await;
+
+        let _ = fs::remove_file(&path).await;
+
+        match result {
+            Ok(metadata) => stored.push(file_json(&metadata, &state.config.get().file_domain())),
+            Err(e) => {
+                error!("Failed to store upload {}: {}", file_name, e);
+
+                let message = "Failed to store file in Telegram".to_owned();
+                failed.push(json!({ "file_name": file_name, "error": message }));
+                first_error.get_or_insert((StatusCode::BAD_GATEWAY, message));
+            }
+        }
+    }
+
+    // Files that were stored keep their links even when others in the request failed
+    match (stored.is_empty(), first_error) {
+        (true, Some((status, message))) => json_error(status, &message),
+        (true, None) => json_error(StatusCode::BAD_REQUEST, "No file provided, send it in a multipart field named 'file'"),
+        (false, None) => (StatusCode::CREATED, Json(json!({ "files": stored }))).into_response(),
+        (false, Some(_)) => (StatusCode::MULTI_STATUS, Json(json!({ "files": stored, "failed": failed }))).into_response(),
+    }
+}
+
+/// JSON listing of all stored files
+pub async fn api_files_list(headers: HeaderMap) -> Response {
+    if let Err(response) = authorize(&headers, ApiKeyScope::Read).await {
+        return response;
+    }
+
+    let mut files = list_all_files().await;
+    files.sort_by_key(|f| f.uploaded_at);
+
+    Json(json!({ "files": files })).into_response()
+}
+
+/// Cache counters for monitoring
+pub async fn api_stats(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(response) = authorize(&headers, ApiKeyScope::Admin).await {
+        return response;
+    }
+
+    let file_cache = state.file_cache.as_ref().map(|cache| {
+        let (files, bytes) = cache.usage();
+        json!({ "files": files, "bytes": bytes })
+    });
+
+    Json(json!({
+        "file_paths": file_path_stats(),
+        "file_cache": file_cache,
+    })).into_response()
+}
+
+pub async fn api_keys_list(headers: HeaderMap) -> Response {
+    if let Err(response) = authorize(&headers, ApiKeyScope::Admin).await {
+        return response;
+    }
+
+    let keys: Vec<_> = api_keys::list_api_keys().await.iter().map(key_json).collect();
+
+    Json(json!({ "keys": keys })).into_response()
+}
+
+pub async fn api_keys_create(
+    headers: HeaderMap,
+    Json(request): Json<CreateKeyRequest>,
+) -> Response {
+    let admin = match authorize(&headers, ApiKeyScope::Admin).await {
+        Ok(key) => key,
+        Err(response) => return response,
+    };
+
+    let rate_limit = request.rate_limit.unwrap_or(DEFAULT_RATE_LIMIT);
+
+    match api_keys::create_api_key(&request.name, request.scopes, rate_limit).await {
+        Ok((key, secret)) => {
+            info!("API key {} created through the API by key {}", key.id, admin.id);
+
+            let mut body = key_json(&key);
+            body["key"] = json!(secret);
+
+            (StatusCode::CREATED, Json(body)).into_response()
+        }
+        Err(e) => {
+            error!("Failed to create API key: {}", e);
+
+            json_error(StatusCode::BAD_REQUEST, &e)
+        }
+    }
+}
+
+pub async fn api_keys_revoke(
+    headers: HeaderMap,
+    extract::Path(id): extract::Path<String>,
+) -> Response {
+    let admin = match authorize(&headers, ApiKeyScope::Admin).await {
+        Ok(key) => key,
+        Err(response) => return response,
+    };
+
+    match api_keys::revoke_api_key(&id).await {
+        Ok(true) => {
+            info!("API key {} revoked through the API by key {}", id, admin.id);
+
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => json_error(StatusCode::NOT_FOUND, "API key not found"),
+        Err(e) => {
+            error!("Failed to revoke API key: {}", e);
+
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, &e)
+        }
+    }
+}
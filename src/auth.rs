@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use axum::body::Body;
+use axum::response::Response;
+use http::header::{AUTHORIZATION, RETRY_AFTER, WWW_AUTHENTICATE};
+use http::{HeaderMap, StatusCode};
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+use tokio::sync::Mutex;
+
+use shared::api_keys::{verify_api_key, ApiKey, ApiKeyScope};
+
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Fixed-window request counters keyed by API key id
+static RATE_LIMITS: Lazy<Mutex<HashMap<String, (Instant, u32)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Extract the key from an `Authorization: Bearer <key>` header
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+
+    if scheme.eq_ignore_ascii_case("bearer") && !token.trim().is_empty() {
+        Some(token.trim())
+    } else {
+        None
+    }
+}
+
+/// Count a request against the key's limit. Returns the seconds to wait if the limit is exceeded.
+async fn check_rate_limit(key: &ApiKey) -> Result<(), u64> {
+    if key.rate_limit == 0 {
+        return Ok(());
+    }
+
+    let mut limits = RATE_LIMITS.lock().await;
+    let now = Instant::now();
+
+    let entry = limits.entry(key.id.clone()).or_insert((now, 0));
+
+    if now.duration_since(entry.0) >= RATE_LIMIT_WINDOW {
+        *entry = (now, 0);
+    }
+
+    if entry.1 >= key.rate_limit {
+        let retry_after = RATE_LIMIT_WINDOW.saturating_sub(now.duration_since(entry.0));
+
+        return Err(retry_after.as_secs().max(1));
+    }
+
+    entry.1 += 1;
+
+    Ok(())
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(message.to_owned()))
+        .unwrap()
+}
+
+/// Authenticate the request with a Bearer API key carrying `scope`.
+/// On failure returns the response that should be sent back to the client.
+pub async fn authorize(headers: &HeaderMap, scope: ApiKeyScope) -> Result<ApiKey, Response<Body>> {
+    let token = match bearer_token(headers) {
+        Some(token) => token,
+        None => {
+            let mut response = error_response(StatusCode::UNAUTHORIZED, "Missing API key");
+            response.headers_mut().insert(WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+
+            return Err(response);
+        }
+    };
+
+    let key = match verify_api_key(token).await {
+        Some(key) => key,
+        None => {
+            warn!("Rejected request with invalid or revoked API key");
+
+            let mut response = error_response(StatusCode::UNAUTHORIZED, "Invalid API key");
+            response.headers_mut().insert(WWW_AUTHENTICATE, "Bearer error=\"invalid_token\"".parse().unwrap());
+
+            return Err(response);
+        }
+    };
+
+    if !key.has_scope(scope) {
+        warn!("API key {} is missing the '{}' scope", key.id, scope);
+
+        return Err(error_response(StatusCode::FORBIDDEN, &format!("API key lacks the '{}' scope", scope)));
+    }
+
+    if let Err(retry_after) = check_rate_limit(&key).await {
+        warn!("API key {} exceeded its rate limit of {} requests/minute", key.id, key.rate_limit);
+
+        let mut response = error_response(StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded");
+        response.headers_mut().insert(RETRY_AFTER, retry_after.into());
+
+        return Err(response);
+    }
+
+    debug!("Request authorized with API key {} ({})", key.id, key.name);
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key(id: &str, rate_limit: u32) -> ApiKey {
+        ApiKey {
+            id: id.to_owned(),
+            name: "test".to_owned(),
+            key_hash: String::new(),
+            scopes: vec![ApiKeyScope::Read],
+            rate_limit,
+            created_at: 0,
+            revoked_at: None,
+        }
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer_token(&headers), None);
+
+        headers.insert(AUTHORIZATION, "Bearer flk_abc.def".parse().unwrap());
+        assert_eq!(bearer_token(&headers), Some("flk_abc.def"));
+
+        headers.insert(AUTHORIZATION, "Basic dXNlcjpwYXNz".parse().unwrap());
+        assert_eq!(bearer_token(&headers), None);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let key = test_key("rate-limit-test", 2);
+
+        assert!(check_rate_limit(&key).await.is_ok());
+        assert!(check_rate_limit(&key).await.is_ok());
+        assert!(check_rate_limit(&key).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_disabled() {
+        let key = test_key("unlimited-test", 0);
+
+        for _ in 0..100 {
+            assert!(check_rate_limit(&key).await.is_ok());
+        }
+    }
+}
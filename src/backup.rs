@@ -0,0 +1,67 @@
+// Periodic backup snapshots, enabled by BACKUP_DIR
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{error, info};
+use teloxide::payloads::SendDocumentSetters;
+use teloxide::prelude::Requester;
+use teloxide::types::{ChatId, InputFile};
+
+use shared::backup::write_snapshot;
+use shared::config::{Config, SharedConfig};
+
+/// Write a snapshot every `BACKUP_INTERVAL_HOURS`, starting right away.
+/// Returns immediately when backups are disabled.
+pub async fn run_backup_schedule(bot: Arc<teloxide::Bot>, config: SharedConfig) {
+    // The backup settings need a restart, so the startup values are kept
+    let config = config.get();
+
+    let dir = match config.backup_dir() {
+        Some(dir) => dir,
+        None => return,
+    };
+
+    info!("Backups enabled: every {} hours into {}, keeping {}", config.backup_interval() / 3600, dir, config.backup_keep());
+
+    let mut interval = tokio::time::interval(Duration::from_secs(config.backup_interval()));
+
+    loop {
+        interval.tick().await;
+
+        let path = match write_snapshot(&dir, config.backup_keep()).await {
+            Ok(path) => path,
+            Err(e) => {
+                error!("Backup failed: {}", e);
+                continue;
+            }
+        };
+
+        if config.backup_to_channel() {
+            post_to_channel(&bot, &config, &path).await;
+        }
+    }
+}
+
+async fn post_to_channel(bot: &teloxide::Bot, config: &Config, path: &Path) {
+    let channel_id = match config.storage_channel_id() {
+        Ok(id) => id,
+        Err(e) => {
+            error!("Cannot post backup to the storage channel: {}", e);
+            return;
+        }
+    };
+
+    let file_name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+    // The caption is not a unique id, so `reindex` skips backup messages
+    let result = bot.send_document(ChatId(channel_id), InputFile::file(path))
+        .caption(format!("#fileslink_backup {}", file_name))
+        .await;
+
+    match result {
+        Ok(_) => info!("Backup {} posted to the storage channel", file_name),
+        Err(e) => error!("Failed to post backup to the storage channel: {}", e),
+    }
+}
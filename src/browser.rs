@@ -0,0 +1,321 @@
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+
+use shared::file_storage::FileMetadata;
+use shared::html_utils::escape_html;
+use shared::link_utils::{build_download_url, build_url_path, encode_path_segment};
+use shared::utils::{format_timestamp, human_size};
+
+const DEFAULT_PER_PAGE: usize = 50;
+const MAX_PER_PAGE: usize = 200;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct BrowserQuery {
+    q: Option<String>,
+    sort: Option<String>,
+    order: Option<String>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortKey {
+    Name,
+    Size,
+    Date,
+}
+
+impl SortKey {
+    fn parse(value: Option<&str>) -> Self {
+        match value {
+            Some("name") => SortKey::Name,
+            Some("size") => SortKey::Size,
+            _ => SortKey::Date,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Date => "date",
+        }
+    }
+}
+
+/// Resolved browser parameters, used both for filtering and for building links
+struct View {
+    query: String,
+    sort: SortKey,
+    descending: bool,
+    page: usize,
+    per_page: usize,
+}
+
+impl View {
+    fn from_query(query: &BrowserQuery) -> Self {
+        let sort = SortKey::parse(query.sort.as_deref());
+
+        // Newest first and A→Z are the natural defaults
+        let descending = match query.order.as_deref() {
+            Some("asc") => false,
+            Some("desc") => true,
+            _ => sort != SortKey::Name,
+        };
+
+        View {
+            query: query.q.clone().unwrap_or_default().trim().to_owned(),
+            sort,
+            descending,
+            page: query.page.unwrap_or(1).max(1),
+            per_page: query.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE),
+        }
+    }
+
+    fn href(&self, sort: SortKey, descending: bool, page: usize) -> String {
+        let mut href = format!(
+            "/files?sort={}&order={}&page={}",
+            sort.as_str(),
+            if descending { "desc" } else { "asc" },
+            page
+        );
+
+        if self.per_page != DEFAULT_PER_PAGE {
+            href.push_str(&format!("&per_page={}", self.per_page));
+        }
+
+        if !self.query.is_empty() {
+            href.push_str(&format!("&q={}", utf8_percent_encode(&self.query, NON_ALPHANUMERIC)));
+        }
+
+        escape_html(&href)
+    }
+
+    /// Header link: clicking the active column flips the order
+    fn sort_header(&self, sort: SortKey, label: &str) -> String {
+        let (descending, arrow) = if self.sort == sort {
+            (!self.descending, if self.descending { " ▼" } else { " ▲" })
+        } else {
+            (sort != SortKey::Name, "")
+        };
+
+        format!("<a href=\"{}\">{}{}</a>", self.href(sort, descending, 1), label, arrow)
+    }
+}
+
+fn mime_icon(mime_type: Option<&str>) -> &'static str {
+    match mime_type.unwrap_or("") {
+        m if m.starts_with("image/") => "🖼️",
+        m if m.starts_with("video/") => "🎬",
+        m if m.starts_with("audio/") => "🎵",
+        "application/pdf" => "📕",
+        m if m.starts_with("text/") || m == "application/json" => "📝",
+        m if ["zip", "compressed", "tar", "rar", "7z"].iter().any(|k| m.contains(k)) => "📦",
+        _ => "📄",
+    }
+}
+
+/// Filter, sort and paginate files. Returns the page and the total number of matches.
+fn select_files(mut files: Vec<FileMetadata>, view: &View) -> (Vec<FileMetadata>, usize) {
+    if !view.query.is_empty() {
+        let needle = view.query.to_lowercase();
+        files.retain(|f| f.file_name.to_lowercase().contains(&needle) || f.unique_id == view.query);
+    }
+
+    // Ties are broken by id, the files come in hash map order and pages must not shift
+    match view.sort {
+        SortKey::Name => files.sort_by_cached_key(|f| (f.file_name.to_lowercase(), f.unique_id.clone())),
+        SortKey::Size => files.sort_by(|a, b| a.file_size.cmp(&b.file_size).then_with(|| a.unique_id.cmp(&b.unique_id))),
+        SortKey::Date => files.sort_by(|a, b| a.uploaded_at.cmp(&b.uploaded_at).then_with(|| a.unique_id.cmp(&b.unique_id))),
+    }
+
+    if view.descending {
+        files.reverse();
+    }
+
+    let total = files.len();
+    let page = files.into_iter()
+        .skip((view.page - 1) * view.per_page)
+        .take(view.per_page)
+        .collect();
+
+    (page, total)
+}
+
+/// Render the `/files` browser page
+pub fn render_file_browser(files: Vec<FileMetadata>, query: &BrowserQuery, file_domain: &str) -> String {
+    let view = View::from_query(query);
+    let (page_files, total) = select_files(files, &view);
+    let total_pages = total.div_ceil(view.per_page).max(1);
+
+    let mut html = String::from(BROWSER_HEAD);
+
+    html.push_str(&format!(
+        "<form method=\"get\" action=\"/files\">\
+        <input type=\"search\" name=\"q\" value=\"{}\" placeholder=\"Search by name or id\">\
+        <input type=\"hidden\" name=\"sort\" value=\"{}\">\
+        <input type=\"hidden\" name=\"order\" value=\"{}\">\
+        <button type=\"submit\">Search</button></form>",
+        escape_html(&view.query),
+        view.sort.as_str(),
+        if view.descending { "desc" } else { "asc" },
+    ));
+
+    if page_files.is_empty() {
+        html.push_str(if view.query.is_empty() { "<p>No files uploaded yet.</p>" } else { "<p>No files match your search.</p>" });
+    } else {
+        html.push_str(&format!(
+            "<table><thead><tr><th></th><th>{}</th><th>{}</th><th>{}</th><th></th></tr></thead><tbody>",
+            view.sort_header(SortKey::Name, "Name"),
+            view.sort_header(SortKey::Size, "Size"),
+            view.sort_header(SortKey::Date, "Uploaded"),
+        ));
+
+        for file in &page_files {
+            let path = build_url_path(&file.unique_id, &file.file_name);
+
+            html.push_str(&format!(
+                "<tr><td>{}</td><td><a href=\"/files/{}\">{}</a></td><td>{}</td><td>{}</td>\
+                <td><button type=\"button\" data-url=\"{}\">Copy link</button></td></tr>",
+                mime_icon(file.mime_type.as_deref()),
+                escape_html(&encode_path_segment(&path)),
+                escape_html(&file.file_name),
+                human_size(file.file_size as u64),
+                format_timestamp(file.uploaded_at),
+                escape_html(&build_download_url(file_domain, &file.unique_id, &file.file_name)),
+            ));
+        }
+
+        html.push_str("</tbody></table>");
+    }
+
+    html.push_str("<nav>");
+
+    if view.page > 1 {
+        html.push_str(&format!("<a href=\"{}\">← Previous</a> ", view.href(view.sort, view.descending, view.page - 1)));
+    }
+
+    html.push_str(&format!("Page {} of {} ({} file{})", view.page.min(total_pages), total_pages, total, if total == 1 { "" } else { "s" }));
+
+    if view.page < total_pages {
+        html.push_str(&format!(" <a href=\"{}\">Next →</a>", view.href(view.sort, view.descending, view.page + 1)));
+    }
+
+    html.push_str("</nav>");
+    html.push_str(BROWSER_TAIL);
+
+    html
+}
+
+const BROWSER_HEAD: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <title>Files in storage</title>
+    <style>
+        body { font-family: Arial, sans-serif; max-width: 960px; margin: 40px auto; padding: 0 16px; }
+        form { margin-bottom: 16px; }
+        input[type=search] { width: 60%; padding: 6px; }
+        table { width: 100%; border-collapse: collapse; }
+        th, td { text-align: left; padding: 6px 8px; border-bottom: 1px solid #eee; }
+        td:nth-child(2) { word-break: break-all; }
+        th a { color: inherit; text-decoration: none; }
+        nav { margin-top: 16px; }
+    </style>
+</head>
+<body>
+    <h1>Files in storage</h1>
+"#;
+
+const BROWSER_TAIL: &str = r#"
+    <script>
+        document.querySelectorAll('button[data-url]').forEach(function (button) {
+            button.onclick = function () {
+                navigator.clipboard.writeText(button.dataset.url).then(function () {
+                    button.textContent = 'Copied';
+                    setTimeout(function () { button.textContent = 'Copy link'; }, 1500);
+                });
+            };
+        });
+    </script>
+</body>
+</html>"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(id: &str, name: &str, size: u32, uploaded_at: u64) -> FileMetadata {
+        FileMetadata {
+            unique_id: id.to_owned(),
+            telegram_file_id: String::new(),
+            file_name: name.to_owned(),
+            mime_type: None,
+            file_size: size,
+            uploaded_at,
+            message_id: None,
+        }
+    }
+
+    fn files() -> Vec<FileMetadata> {
+        vec![
+            file("aaaaaaaa", "beta.txt", 300, 2),
+            file("bbbbbbbb", "Alpha.pdf", 100, 3),
+            file("cccccccc", "gamma.zip", 200, 1),
+        ]
+    }
+
+    fn names(files: &[FileMetadata]) -> Vec<&str> {
+        files.iter().map(|f| f.file_name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_default_sort_is_newest_first() {
+        let view = View::from_query(&BrowserQuery::default());
+        let (page, total) = select_files(files(), &view);
+
+        assert_eq!(total, 3);
+        assert_eq!(names(&page), vec!["Alpha.pdf", "beta.txt", "gamma.zip"]);
+    }
+
+    #[test]
+    fn test_sort_by_size_ascending() {
+        let query = BrowserQuery { sort: Some("size".into()), order: Some("asc".into()), ..Default::default() };
+        let (page, _) = select_files(files(), &View::from_query(&query));
+
+        assert_eq!(names(&page), vec!["Alpha.pdf", "gamma.zip", "beta.txt"]);
+    }
+
+    #[test]
+    fn test_ties_keep_their_order() {
+        let query = BrowserQuery { sort: Some("size".into()), order: Some("asc".into()), ..Default::default() };
+        let view = View::from_query(&query);
+        let same = |ids: [&str; 3]| ids.map(|id| file(id, "same.txt", 100, 1)).to_vec();
+
+        let (a, _) = select_files(same(["cccccccc", "aaaaaaaa", "bbbbbbbb"]), &view);
+        let (b, _) = select_files(same(["bbbbbbbb", "cccccccc", "aaaaaaaa"]), &view);
+
+        let ids = |files: &[FileMetadata]| files.iter().map(|f| f.unique_id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&a), vec!["aaaaaaaa", "bbbbbbbb", "cccccccc"]);
+        assert_eq!(ids(&a), ids(&b));
+    }
+
+    #[test]
+    fn test_search_and_pagination() {
+        let query = BrowserQuery { q: Some("A".into()), sort: Some("name".into()), per_page: Some(1), page: Some(2), ..Default::default() };
+        let (page, total) = select_files(files(), &View::from_query(&query));
+
+        assert_eq!(total, 3);
+        assert_eq!(names(&page), vec!["beta.txt"]);
+    }
+
+    #[test]
+    fn test_render_escapes_file_names() {
+        let files = vec![file("aaaaaaaa", "<script>alert(1)</script>\".txt", 1, 0)];
+        let html = render_file_browser(files, &BrowserQuery::default(), "https://example.com/files/");
+
+        assert!(!html.contains("<script>alert(1)"));
+        assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;&quot;.txt"));
+    }
+}
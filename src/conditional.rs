@@ -0,0 +1,132 @@
+// Cache validators and conditional requests for stored files (RFC 9110 section 13)
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use http::header::{IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE};
+use http::HeaderMap;
+use sha2::{Digest, Sha256};
+
+use shared::file_storage::FileMetadata;
+
+/// Stored files never change, so clients and proxies may keep them for a day
+/// and revalidate cheaply with the validators afterwards
+pub const PUBLIC_CACHE_CONTROL: &str = "public, max-age=86400";
+
+/// Validators sent with every file response
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: SystemTime,
+}
+
+impl Validators {
+    /// Strong ETag from the unique id and the Telegram file id, which together
+    /// identify the stored bytes. `Last-Modified` is the upload time.
+    pub fn for_file(metadata: &FileMetadata) -> Self {
+        let digest = Sha256::digest(metadata.telegram_file_id.as_bytes());
+        let hash: String = digest.iter().take(8).map(|b| format!("{:02x}", b)).collect();
+
+        Validators {
+            etag: format!("\"{}-{}\"", metadata.unique_id, hash),
+            last_modified: UNIX_EPOCH + Duration::from_secs(metadata.uploaded_at),
+        }
+    }
+
+    pub fn last_modified_header(&self) -> String {
+        httpdate::fmt_http_date(self.last_modified)
+    }
+
+    /// True when the client's cached copy is still current and a 304 should be sent.
+    /// `If-None-Match` takes precedence; `If-Modified-Since` is only used without it.
+    pub fn is_not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(value) = headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+            return value.split(',').map(str::trim).any(|tag| {
+                // Weak comparison, as required for If-None-Match
+                tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == self.etag
+            });
+        }
+
+        match headers.get(IF_MODIFIED_SINCE).and_then(|v| v.to_str().ok()) {
+            Some(value) => match httpdate::parse_http_date(value) {
+                Ok(since) => self.last_modified <= since,
+                Err(_) => false,
+            },
+            None => false,
+        }
+    }
+
+    /// True when a `Range` header may be honored: either there is no `If-Range`,
+    /// or it still matches the stored representation (strong comparison)
+    pub fn if_range_matches(&self, headers: &HeaderMap) -> bool {
+        let value = match headers.get(IF_RANGE).and_then(|v| v.to_str().ok()) {
+            Some(value) => value.trim(),
+            None => return true,
+        };
+
+        if value.starts_with('"') {
+            return value == self.etag;
+        }
+
+        match httpdate::parse_http_date(value) {
+            Ok(date) => date == self.last_modified,
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn validators() -> Validators {
+        Validators::for_file(&FileMetadata {
+            unique_id: "abcd1234".to_owned(),
+            telegram_file_id: "BQACAgIAAxkBAAIB".to_owned(),
+            file_name: "a.txt".to_owned(),
+            mime_type: None,
+            file_size: 1,
+            uploaded_at: 1_700_000_000,
+            message_id: None,
+        })
+    }
+
+    fn headers(name: http::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_validators() {
+        let v = validators();
+
+        assert!(v.etag.starts_with("\"abcd1234-") && v.etag.ends_with('"'));
+        assert_eq!(v.etag.len(), 2 + 8 + 1 + 16);
+        assert_eq!(v.last_modified_header(), "Tue, 14 Nov 2023 22:13:20 GMT");
+    }
+
+    #[test]
+    fn test_is_not_modified() {
+        let v = validators();
+
+        assert!(!v.is_not_modified(&HeaderMap::new()));
+        assert!(v.is_not_modified(&headers(IF_NONE_MATCH, &format!("\"other\", W/{}", v.etag))));
+        assert!(v.is_not_modified(&headers(IF_NONE_MATCH, "*")));
+        assert!(!v.is_not_modified(&headers(IF_NONE_MATCH, "\"other\"")));
+        assert!(v.is_not_modified(&headers(IF_MODIFIED_SINCE, "Tue, 14 Nov 2023 22:13:20 GMT")));
+        assert!(!v.is_not_modified(&headers(IF_MODIFIED_SINCE, "Tue, 14 Nov 2023 22:13:19 GMT")));
+        assert!(!v.is_not_modified(&headers(IF_MODIFIED_SINCE, "garbage")));
+    }
+
+    #[test]
+    fn test_if_range_matches() {
+        let v = validators();
+
+        assert!(v.if_range_matches(&HeaderMap::new()));
+        assert!(v.if_range_matches(&headers(IF_RANGE, &v.etag)));
+        assert!(!v.if_range_matches(&headers(IF_RANGE, &format!("W/{}", v.etag))));
+        assert!(!v.if_range_matches(&headers(IF_RANGE, "\"other\"")));
+        assert!(v.if_range_matches(&headers(IF_RANGE, "Tue, 14 Nov 2023 22:13:20 GMT")));
+        assert!(!v.if_range_matches(&headers(IF_RANGE, "Tue, 14 Nov 2023 22:13:21 GMT")));
+    }
+}
@@ -0,0 +1,78 @@
+// Building `Content-Disposition` headers for stored file names (RFC 6266, RFC 8187)
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// Everything outside `attr-char` from RFC 8187 is percent-encoded
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!').remove(b'#').remove(b'$').remove(b'&').remove(b'+').remove(b'-')
+    .remove(b'.').remove(b'^').remove(b'_').remove(b'`').remove(b'|').remove(b'~');
+
+/// ASCII-only stand-in for clients that ignore `filename*`.
+/// Quotes, backslashes, control and non-ASCII characters become underscores.
+fn ascii_fallback(file_name: &str) -> String {
+    let fallback: String = file_name.chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+
+    if fallback.trim_matches(|c| c == '_' || c == '.' || c == ' ').is_empty() {
+        "download".to_string()
+    } else {
+        fallback
+    }
+}
+
+/// Build a header value such as `attachment; filename="a.txt"`.
+/// Names that do not survive the ASCII fallback unchanged also get a UTF-8
+/// `filename*` parameter, which modern browsers prefer.
+pub fn content_disposition(disposition_type: &str, file_name: &str) -> String {
+    let fallback = ascii_fallback(file_name);
+
+    if fallback == file_name {
+        return format!("{}; filename=\"{}\"", disposition_type, fallback);
+    }
+
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition_type,
+        fallback,
+        utf8_percent_encode(file_name, ATTR_CHAR)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    #[test]
+    fn test_plain_name() {
+        assert_eq!(content_disposition("attachment", "report 2024.pdf"), "attachment; filename=\"report 2024.pdf\"");
+    }
+
+    #[test]
+    fn test_unicode_name() {
+        assert_eq!(
+            content_disposition("inline", "отчёт.pdf"),
+            "inline; filename=\"_____.pdf\"; filename*=UTF-8''%D0%BE%D1%82%D1%87%D1%91%D1%82.pdf"
+        );
+    }
+
+    #[test]
+    fn test_hostile_names_are_valid_header_values() {
+        for name in ["a\"; filename=evil.exe", "line\r\nSet-Cookie: x=1", "back\\slash", "<script>.html", "\"\"", "🙂"] {
+            let value = content_disposition("attachment", name);
+
+            assert!(HeaderValue::from_str(&value).is_ok(), "invalid header for {:?}", name);
+            assert_eq!(value.matches('"').count(), 2, "unbalanced quotes for {:?}", name);
+        }
+
+        assert_eq!(
+            content_disposition("attachment", "a\"; filename=evil.exe"),
+            "attachment; filename=\"a_; filename=evil.exe\"; filename*=UTF-8''a%22%3B%20filename%3Devil.exe"
+        );
+        assert_eq!(content_disposition("attachment", "🙂"), "attachment; filename=\"download\"; filename*=UTF-8''%F0%9F%99%82");
+    }
+}
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use nanoid::nanoid;
+use once_cell::sync::Lazy;
+use tokio::sync::Mutex;
+
+/// How long the auto-close page has to start its download
+const DOWNLOAD_TOKEN_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// One-time download tokens mapped to the file's unique id and expiry
+static DOWNLOAD_TOKENS: Lazy<Mutex<HashMap<String, (String, Instant)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub async fn issue_download_token(unique_id: &str) -> String {
+    let mut tokens = DOWNLOAD_TOKENS.lock().await;
+
+    let now = Instant::now();
+    tokens.retain(|_, (_, expires_at)| *expires_at > now);
+
+    let token = nanoid!(24);
+    tokens.insert(token.clone(), (unique_id.to_owned(), now + DOWNLOAD_TOKEN_TTL));
+
+    token
+}
+
+/// Consume a token, returning the unique id it was issued for
+pub async fn redeem_download_token(token: &str) -> Option<String> {
+    let mut tokens = DOWNLOAD_TOKENS.lock().await;
+
+    match tokens.remove(token) {
+        Some((unique_id, expires_at)) if expires_at > Instant::now() => Some(unique_id),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_download_token_is_one_time() {
+        let token = issue_download_token("abcd1234").await;
+
+        assert_eq!(redeem_download_token(&token).await.as_deref(), Some("abcd1234"));
+        assert_eq!(redeem_download_token(&token).await, None);
+    }
+}
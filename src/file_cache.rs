@@ -0,0 +1,432 @@
+// On-disk LRU cache of downloaded files, keyed by unique id.
+// A file is written to `<id>.part` while the first download streams through
+// and renamed to `<id>` once complete, so readers only ever see whole files.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use log::{debug, info, warn};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, watch};
+
+/// Stream of file bytes coming from Telegram or FastTelethon
+pub type ByteStream = BoxStream<'static, Result<Bytes, String>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FillStatus {
+    Running,
+    Done,
+    Failed,
+}
+
+struct CacheEntry {
+    size: u64,
+    created: SystemTime,
+    last_access: SystemTime,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, CacheEntry>,
+    filling: HashMap<String, watch::Receiver<FillStatus>>,
+}
+
+pub struct FileCache {
+    dir: PathBuf,
+    max_size: u64,
+    max_age: Duration,
+    state: Mutex<CacheState>,
+}
+
+/// A complete file in the cache
+pub struct CachedFile {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+pub enum Lookup {
+    Hit(CachedFile),
+    /// Another request is already fetching this file
+    Pending(PendingFill),
+    /// Not cached; the caller fetches the file and hands the stream to the `Filler`
+    Miss(Filler),
+}
+
+pub struct PendingFill {
+    cache: Arc<FileCache>,
+    id: String,
+    status: watch::Receiver<FillStatus>,
+}
+
+impl PendingFill {
+    /// Wait for the running fill. Returns `None` when it failed.
+    pub async fn wait(mut self) -> Option<CachedFile> {
+        let _ = self.status.wait_for(|status| *status != FillStatus::Running).await;
+
+        self.cache.get_entry(&self.id)
+    }
+}
+
+/// Exclusive right to fill the cache for one id. Dropping it without calling
+/// `spawn` (e.g. when the upstream could not be opened) fails the waiters.
+pub struct Filler {
+    cache: Arc<FileCache>,
+    id: String,
+    status: watch::Sender<FillStatus>,
+}
+
+impl Filler {
+    /// Write `upstream` to the cache in the background. Chunks are also sent to
+    /// `forward` so the request that caused the miss is served while the file is
+    /// being written; a client that goes away does not stop the fill. A stream
+    /// that ends before `expected_size` bytes is not cached.
+    pub fn spawn(self, upstream: ByteStream, expected_size: u64, forward: Option<mpsc::Sender<io::Result<Bytes>>>) -> PendingFill {
+        let pending = PendingFill {
+            cache: self.cache.clone(),
+            id: self.id.clone(),
+            status: self.status.subscribe(),
+        };
+
+        tokio::spawn(self.run(upstream, expected_size, forward));
+
+        pending
+    }
+
+    async fn run(self, upstream: ByteStream, expected_size: u64, forward: Option<mpsc::Sender<io::Result<Bytes>>>) {
+        let part_path = self.cache.dir.join(format!("{}.part", self.id));
+
+        match write_part(&part_path, upstream, forward).await {
+            Ok(size) if size != expected_size => {
+                warn!("Not caching {}: got {} bytes, expected {}", self.id, size, expected_size);
+                let _ = tokio::fs::remove_file(&part_path).await;
+            }
+            Ok(size) if size <= self.cache.max_size => {
+                if let Err(e) = tokio::fs::rename(&part_path, self.cache.dir.join(&self.id)).await {
+                    warn!("Failed to finish cache file for {}: {}", self.id, e);
+                    let _ = tokio::fs::remove_file(&part_path).await;
+                    return;
+                }
+
+                self.cache.insert(&self.id, size);
+                let _ = self.status.send(FillStatus::Done);
+
+                debug!("Cached {} ({} bytes)", self.id, size);
+            }
+            Ok(size) => {
+                debug!("Not caching {}: {} bytes exceed the cache size", self.id, size);
+                let _ = tokio::fs::remove_file(&part_path).await;
+            }
+            Err(e) => {
+                warn!("Failed to fill cache for {}: {}", self.id, e);
+                let _ = tokio::fs::remove_file(&part_path).await;
+            }
+        }
+    }
+}
+
+impl Drop for Filler {
+    fn drop(&mut self) {
+        self.cache.state.lock().unwrap().filling.remove(&self.id);
+
+        if *self.status.borrow() == FillStatus::Running {
+            let _ = self.status.send(FillStatus::Failed);
+        }
+    }
+}
+
+async fn write_part(
+    path: &PathBuf,
+    mut upstream: ByteStream,
+    mut forward: Option<mpsc::Sender<io::Result<Bytes>>>,
+) -> Result<u64, String> {
+    let mut file = tokio::fs::File::create(path).await.map_err(|e| e.to_string())?;
+    let mut size = 0u64;
+
+    while let Some(chunk) = upstream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                if let Some(tx) = &forward {
+                    let _ = tx.send(Err(io::Error::other(e.clone()))).await;
+                }
+
+                return Err(e);
+            }
+        };
+
+        file.write_all(&chunk).await.map_err(|e| e.to_string())?;
+        size += chunk.len() as u64;
+
+        if let Some(tx) = &forward {
+            if tx.send(Ok(chunk)).await.is_err() {
+                debug!("Client went away, continuing to fill the cache");
+                forward = None;
+            }
+        }
+    }
+
+    file.flush().await.map_err(|e| e.to_string())?;
+
+    Ok(size)
+}
+
+impl FileCache {
+    /// Open the cache directory, picking up complete files from a previous run
+    /// and removing interrupted ones
+    pub async fn open(dir: impl Into<PathBuf>, max_size: u64, max_age: Duration) -> io::Result<Arc<Self>> {
+        let dir = dir.into();
+        tokio::fs::create_dir_all(&dir).await?;
+
+        let mut state = CacheState::default();
+        let mut read_dir = tokio::fs::read_dir(&dir).await?;
+
+        while let Some(entry) = read_dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let metadata = entry.metadata().await?;
+
+            if !metadata.is_file() {
+                continue;
+            }
+
+            if name.ends_with(".part") {
+                let _ = tokio::fs::remove_file(entry.path()).await;
+                continue;
+            }
+
+            let modified = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+            state.entries.insert(name, CacheEntry { size: metadata.len(), created: modified, last_access: modified });
+        }
+
+        let cache = Arc::new(FileCache { dir, max_size, max_age, state: Mutex::new(state) });
+        let (count, size) = cache.usage();
+
+        info!("File cache at {} holds {} files ({} bytes)", cache.dir.display(), count, size);
+        cache.evict(None);
+
+        Ok(cache)
+    }
+
+    /// Whether a file of this size can be cached at all
+    pub fn accepts(&self, size: u64) -> bool {
+        size <= self.max_size
+    }
+
+    pub fn lookup(self: &Arc<Self>, id: &str) -> Lookup {
+        if let Some(file) = self.get_entry(id) {
+            return Lookup::Hit(file);
+        }
+
+        let mut state = self.state.lock().unwrap();
+
+        // Re-checked under the lock: a fill may have finished in between
+        if state.entries.contains_key(id) {
+            drop(state);
+
+            return match self.get_entry(id) {
+                Some(file) => Lookup::Hit(file),
+                None => self.lookup(id),
+            };
+        }
+
+        if let Some(status) = state.filling.get(id) {
+            return Lookup::Pending(PendingFill { cache: self.clone(), id: id.to_owned(), status: status.clone() });
+        }
+
+        let (tx, rx) = watch::channel(FillStatus::Running);
+        state.filling.insert(id.to_owned(), rx);
+
+        Lookup::Miss(Filler { cache: self.clone(), id: id.to_owned(), status: tx })
+    }
+
+    /// Number of cached files and their total size
+    pub fn usage(&self) -> (usize, u64) {
+        let state = self.state.lock().unwrap();
+
+        (state.entries.len(), state.entries.values().map(|e| e.size).sum())
+    }
+
+    fn get_entry(&self, id: &str) -> Option<CachedFile> {
+        let mut state = self.state.lock().unwrap();
+        let now = SystemTime::now();
+
+        let entry = state.entries.get_mut(id)?;
+
+        if now.duration_since(entry.created).unwrap_or_default() <= self.max_age {
+            entry.last_access = now;
+
+            return Some(CachedFile { path: self.dir.join(id), size: entry.size });
+        }
+
+        debug!("Cached file {} expired", id);
+        state.entries.remove(id);
+        let _ = std::fs::remove_file(self.dir.join(id));
+
+        None
+    }
+
+    fn insert(&self, id: &str, size: u64) {
+        let now = SystemTime::now();
+
+        self.state.lock().unwrap().entries.insert(id.to_owned(), CacheEntry { size, created: now, last_access: now });
+        self.evict(Some(id));
+    }
+
+    /// Remove least recently used files until the cache fits its size limit
+    fn evict(&self, keep: Option<&str>) {
+        let mut state = self.state.lock().unwrap();
+        let mut total: u64 = state.entries.values().map(|e| e.size).sum();
+
+        if total <= self.max_size {
+            return;
+        }
+
+        let mut candidates: Vec<(String, SystemTime, u64)> = state.entries.iter()
+            .filter(|(id, _)| Some(id.as_str()) != keep)
+            .map(|(id, e)| (id.clone(), e.last_access, e.size))
+            .collect();
+        candidates.sort_by_key(|(_, last_access, _)| *last_access);
+
+        for (id, _, size) in candidates {
+            if total <= self.max_size {
+                break;
+            }
+
+            debug!("Evicting {} from the file cache", id);
+            state.entries.remove(&id);
+            let _ = std::fs::remove_file(self.dir.join(&id));
+            total -= size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+
+    async fn temp_cache(max_size: u64, max_age: Duration) -> Arc<FileCache> {
+        let dir = std::env::temp_dir().join(format!("fileslink-cache-test-{}", nanoid::nanoid!(8)));
+
+        FileCache::open(dir, max_size, max_age).await.unwrap()
+    }
+
+    fn upstream(chunks: Vec<&'static [u8]>) -> ByteStream {
+        stream::iter(chunks.into_iter().map(|c| Ok(Bytes::from_static(c)))).boxed()
+    }
+
+    async fn fill(cache: &Arc<FileCache>, id: &str, chunks: Vec<&'static [u8]>) -> Option<CachedFile> {
+        match cache.lookup(id) {
+            Lookup::Miss(filler) => {
+                let size = chunks.iter().map(|c| c.len() as u64).sum();
+                filler.spawn(upstream(chunks), size, None).wait().await
+            }
+            _ => panic!("expected a miss for {}", id),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fill_then_hit() {
+        let cache = temp_cache(1024, Duration::from_secs(60)).await;
+        let (tx, mut rx) = mpsc::channel(4);
+
+        let pending = match cache.lookup("abcd1234") {
+            Lookup::Miss(filler) => filler.spawn(upstream(vec![b"hello ", b"world"]), 11, Some(tx)),
+            _ => panic!("expected a miss"),
+        };
+
+        let mut forwarded = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            forwarded.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(forwarded, b"hello world");
+
+        let file = pending.wait().await.unwrap();
+        assert_eq!(file.size, 11);
+        assert_eq!(std::fs::read(&file.path).unwrap(), b"hello world");
+        assert!(matches!(cache.lookup("abcd1234"), Lookup::Hit(_)));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_share_one_fill() {
+        let cache = temp_cache(1024, Duration::from_secs(60)).await;
+        let (tx, rx) = mpsc::channel(1);
+
+        let filler = match cache.lookup("abcd1234") {
+            Lookup::Miss(filler) => filler,
+            _ => panic!("expected a miss"),
+        };
+
+        let pending = match cache.lookup("abcd1234") {
+            Lookup::Pending(pending) => pending,
+            _ => panic!("expected the second lookup to wait"),
+        };
+
+        drop(rx);
+        filler.spawn(upstream(vec![b"data"]), 4, Some(tx));
+
+        assert_eq!(pending.wait().await.unwrap().size, 4);
+    }
+
+    #[tokio::test]
+    async fn test_failed_fill_is_not_cached() {
+        let cache = temp_cache(1024, Duration::from_secs(60)).await;
+
+        let failing: ByteStream = stream::iter(vec![Ok(Bytes::from_static(b"part")), Err("boom".to_string())]).boxed();
+        let (waiter, result) = match cache.lookup("abcd1234") {
+            Lookup::Miss(filler) => {
+                let waiter = match cache.lookup("abcd1234") {
+                    Lookup::Pending(pending) => pending,
+                    _ => panic!("expected a pending fill"),
+                };
+
+                (waiter, filler.spawn(failing, 8, None).wait().await)
+            }
+            _ => panic!("expected a miss"),
+        };
+
+        assert!(result.is_none());
+        assert!(waiter.wait().await.is_none());
+        assert!(!cache.dir.join("abcd1234.part").exists());
+        assert!(matches!(cache.lookup("abcd1234"), Lookup::Miss(_)));
+    }
+
+    #[tokio::test]
+    async fn test_short_fill_is_not_cached() {
+        let cache = temp_cache(1024, Duration::from_secs(60)).await;
+
+        let result = match cache.lookup("abcd1234") {
+            Lookup::Miss(filler) => filler.spawn(upstream(vec![b"part"]), 8, None).wait().await,
+            _ => panic!("expected a miss"),
+        };
+
+        assert!(result.is_none());
+        assert!(!cache.dir.join("abcd1234.part").exists());
+        assert!(matches!(cache.lookup("abcd1234"), Lookup::Miss(_)));
+    }
+
+    #[tokio::test]
+    async fn test_lru_eviction_and_expiry() {
+        let cache = temp_cache(10, Duration::from_secs(60)).await;
+
+        fill(&cache, "first", vec![b"12345"]).await.unwrap();
+        fill(&cache, "second", vec![b"12345"]).await.unwrap();
+        assert!(matches!(cache.lookup("first"), Lookup::Hit(_)));
+
+        // "second" is now the least recently used file
+        fill(&cache, "third", vec![b"12345"]).await.unwrap();
+        assert_eq!(cache.usage(), (2, 10));
+        assert!(matches!(cache.lookup("first"), Lookup::Hit(_)));
+        assert!(!cache.dir.join("second").exists());
+
+        let expiring = temp_cache(10, Duration::ZERO).await;
+        fill(&expiring, "old", vec![b"1"]).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(matches!(expiring.lookup("old"), Lookup::Miss(_)));
+    }
+}
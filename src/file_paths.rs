@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+/// Telegram keeps download paths valid for at least an hour; stay a bit below that
+const FILE_PATH_TTL: Duration = Duration::from_secs(55 * 60);
+
+/// Result of a Bot API `get_file` call
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilePath {
+    pub path: String,
+    pub size: u32,
+}
+
+/// Download paths keyed by Telegram file id, with their expiry
+static FILE_PATHS: Lazy<Mutex<HashMap<String, (FilePath, Instant)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Serialize)]
+pub struct FilePathStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+/// Look up a still valid download path, counting the hit or miss
+pub fn cached_file_path(telegram_file_id: &str) -> Option<FilePath> {
+    let mut paths = FILE_PATHS.lock().unwrap();
+
+    let found = match paths.get(telegram_file_id) {
+        Some((path, expires_at)) if *expires_at > Instant::now() => Some(path.clone()),
+        Some(_) => {
+            paths.remove(telegram_file_id);
+            None
+        }
+        None => None,
+    };
+
+    match found {
+        Some(_) => HITS.fetch_add(1, Ordering::Relaxed),
+        None => MISSES.fetch_add(1, Ordering::Relaxed),
+    };
+
+    found
+}
+
+pub fn remember_file_path(telegram_file_id: &str, path: FilePath) {
+    let mut paths = FILE_PATHS.lock().unwrap();
+
+    let now = Instant::now();
+    paths.retain(|_, (_, expires_at)| *expires_at > now);
+    paths.insert(telegram_file_id.to_owned(), (path, now + FILE_PATH_TTL));
+}
+
+/// Drop a path after a failed download, so the next request asks Telegram again
+pub fn forget_file_path(telegram_file_id: &str) {
+    FILE_PATHS.lock().unwrap().remove(telegram_file_id);
+}
+
+pub fn file_path_stats() -> FilePathStats {
+    FilePathStats {
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+        entries: FILE_PATHS.lock().unwrap().len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_path_cache() {
+        let path = FilePath { path: "documents/file_1.pdf".to_owned(), size: 42 };
+        let before = file_path_stats();
+
+        assert_eq!(cached_file_path("test-file-id"), None);
+        remember_file_path("test-file-id", path.clone());
+        assert_eq!(cached_file_path("test-file-id"), Some(path));
+
+        forget_file_path("test-file-id");
+        assert_eq!(cached_file_path("test-file-id"), None);
+
+        let after = file_path_stats();
+        assert!(after.hits > before.hits);
+        assert!(after.misses >= before.misses + 2);
+    }
+}
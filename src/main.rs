@@ -4,14 +4,31 @@ use std::sync::Arc;
 
 use bot::bot::{Bot as BotTrait, TeloxideBot};
 use bot::queue::FileQueueType;
+use cli::reindex::{reindex, Prefer};
 use cli::utils::send_command;
-use log::{error, info};
+use log::{error, info, warn};
+use teloxide::prelude::Requester;
+use teloxide::types::ChatId;
 use tokio::net::TcpListener;
 use tokio::signal;
 use tokio::spawn;
 use tokio::sync::{mpsc, Mutex};
 
+mod api;
+mod auth;
+mod backup;
+mod browser;
+mod conditional;
+mod disposition;
+mod download_tokens;
+mod file_cache;
+mod file_paths;
+mod preview;
+mod range;
 mod server;
+mod upload;
+mod upstream;
+mod watcher;
 use shared::chat_config;
 use shared::config;
 
@@ -23,6 +40,20 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     info!("Starting up...");
 
+    let shared_config = match config::Config::init() {
+        Ok(shared_config) => shared_config,
+        Err(problems) => {
+            error!("Invalid configuration, fix the following and restart:");
+            for problem in &problems {
+                error!("  - {}", problem);
+            }
+
+            return Err(format!("Invalid configuration ({} problems)", problems.len()).into());
+        }
+    };
+
+    let app_config = shared_config.get();
+
     // Initialize file storage
     if let Err(e) = shared::file_storage::init_file_storage().await {
         error!("Failed to initialize file storage: {}", e);
@@ -30,17 +61,27 @@ async fn main() -> Result<(), Box<dyn Error>> {
     }
     info!("File storage initialized");
 
-    let server_port = config::Config::instance().await.server_port();
+    if let Err(e) = shared::api_keys::init_api_keys().await {
+        error!("Failed to load API keys: {}", e);
+        return Err("Failed to load API keys".into());
+    }
+
+    let server_port = app_config.server_port();
     info!("Server port: {}", server_port);
 
-    let raw_permissions = chat_config::load_config()
-        .await.expect("Failed to load config");
+    let raw_permissions = match chat_config::load_config(&app_config).await {
+        Ok(permissions) => permissions,
+        Err(e) => {
+            error!("Failed to load permissions: {}", e);
+            return Err("Failed to load permissions".into());
+        }
+    };
 
     let permissions = Arc::new(Mutex::new(raw_permissions));
 
     let file_queue: FileQueueType = Arc::new(Mutex::new(Vec::new()));
 
-    let bot = match TeloxideBot::new(config::Config::instance().await, permissions.clone(), file_queue.clone()) {
+    let bot = match TeloxideBot::new(shared_config.clone(), permissions.clone(), file_queue.clone()) {
         Ok(bot) => bot,
         Err(e) => {
             error!("Failed to create bot: {}", e);
@@ -49,6 +90,18 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
     };
 
+    // Validated by `Config::init`
+    let storage_channel_id = app_config.storage_channel_id()?;
+
+    // A wrong token or a channel the bot is not in would otherwise only show on the first upload
+    if let Err(e) = bot.get_teloxide_bot().get_chat(ChatId(storage_channel_id)).await {
+        error!("Invalid configuration, fix the following and restart:");
+        error!("  - STORAGE_CHANNEL_ID (telegram.storage_channel_id): channel {} can't be reached by the bot: {}", storage_channel_id, e);
+
+        return Err("Storage channel not reachable".into());
+    }
+    info!("Storage channel {} is reachable", storage_channel_id);
+
     let bot_clone = Arc::new(bot);
 
     let (tx, rx) = mpsc::channel(100);
@@ -76,10 +129,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let server_task = {
         let bot_for_server = Arc::clone(&bot_clone);
-        
+        let shared_config = shared_config.clone();
+
         spawn(async move {
             let bot_teloxide = bot_for_server.get_teloxide_bot();
-            let app = server::create_app(bot_teloxide).await;
+            let app = server::create_app(bot_teloxide, shared_config).await;
 
             let addr: String = format!("0.0.0.0:{}", server_port);
             let listener = TcpListener::bind(&addr).await
@@ -98,31 +152,97 @@ async fn main() -> Result<(), Box<dyn Error>> {
     };
 
     let ctrl_c_task = {
+        let pipe_path = app_config.pipe_path();
+
         spawn(async move {
             signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
 
             info!("Received Ctrl+C, shutting down...");
 
-            match send_command(&config::Config::instance().await.pipe_path(), "shutdown").await {
+            match send_command(&pipe_path, "shutdown").await {
                 Ok(_) => info!("Command 'shutdown' sent"),
                 Err(_) => error!("Failed to send shutdown command")
             };
         })
     };
 
+    let (cli_tx, mut cli_rx) = mpsc::channel::<String>(16);
+
     let update_cli_task = {
         let permissions = Arc::clone(&permissions);
+        let shared_config = shared_config.clone();
 
         spawn(async move {
-            shared::cli_utils::handle_cli(permissions).await;
+            shared::cli_utils::handle_cli(shared_config, permissions, cli_tx).await;
         })
     };
 
+    // FIFO commands that need the bot
+    let cli_commands_task = {
+        let bot = bot_clone.get_teloxide_bot();
+        let shared_config = shared_config.clone();
+
+        spawn(async move {
+            while let Some(command) = cli_rx.recv().await {
+                match command.as_str() {
+                    "repair" => {
+                        info!("Repair requested from CLI");
+                        bot::repair::repair_all(&bot, &shared_config.get()).await;
+                    }
+                    command if command.starts_with("reindex ") => {
+                        let prefer = match command["reindex ".len()..].parse::<Prefer>() {
+                            Ok(prefer) => prefer,
+                            Err(e) => {
+                                warn!("{}", e);
+                                continue;
+                            }
+                        };
+
+                        info!("Reindex requested from CLI");
+                        match reindex(&shared_config.get(), prefer, true).await {
+                            Ok(plan) => plan.report(prefer).iter().for_each(|line| info!("{}", line)),
+                            Err(e) => error!("Failed to reindex: {}", e),
+                        }
+                    }
+                    other => warn!("Unknown CLI command: {}", other),
+                }
+            }
+        })
+    };
+
+    let backup_task = {
+        let bot = bot_clone.get_teloxide_bot();
+        let shared_config = shared_config.clone();
+
+        spawn(async move {
+            backup::run_backup_schedule(bot, shared_config).await;
+            // Backups disabled; keep the task pending so select! does not end
+            std::future::pending::<()>().await;
+        })
+    };
+
+    let watcher_task = {
+        let permissions = Arc::clone(&permissions);
+        let shared_config = shared_config.clone();
+
+        spawn(async move {
+            watcher::run_config_watcher(shared_config, permissions).await;
+            // Watching unavailable; the FIFO still works, so keep the task pending
+            std::future::pending::<()>().await;
+        })
+    };
+
+    let upload_sweeper_task = spawn(upload::run_stale_upload_sweeper());
+
     tokio::select! {
         _ = bot_task => {},
         _ = queue_processor_task => {},
         _ = server_task => {},
         _ = update_cli_task => {},
+        _ = cli_commands_task => {},
+        _ = backup_task => {},
+        _ = watcher_task => {},
+        _ = upload_sweeper_task => {},
         _ = ctrl_c_task => {},
     }
 
@@ -0,0 +1,254 @@
+use axum::extract::{self, State};
+use axum::response::{Html, IntoResponse, Response};
+use http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use http::StatusCode;
+use log::{debug, info, warn};
+
+use shared::file_storage::{get_file_metadata, FileMetadata};
+use shared::html_utils::escape_html;
+use shared::link_utils::{build_preview_url, build_url_path, encode_path_segment, extract_id_from_path};
+use shared::utils::human_size;
+
+use crate::conditional::PUBLIC_CACHE_CONTROL;
+use crate::server::{not_found_handler, AppState};
+
+/// Text files above this size are not previewed, only offered for download
+const TEXT_PREVIEW_LIMIT: u32 = 1024 * 1024;
+
+/// highlight.js 10.1.1 (BSD-3-Clause), served by us so preview pages load no third-party script
+const HIGHLIGHT_JS: &str = include_str!("assets/highlight.min.js");
+
+pub const HIGHLIGHT_JS_PATH: &str = "/assets/highlight.min.js";
+
+/// Inline viewer used for a file on the preview page
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Viewer {
+    Image,
+    Video,
+    Audio,
+    Pdf,
+    Text,
+    None,
+}
+
+/// Pick a viewer from the stored MIME type, falling back to the file extension.
+/// Types that can run script in the browser (HTML, SVG) never get a viewer.
+pub fn viewer_for(metadata: &FileMetadata) -> Viewer {
+    let mime_type = metadata.mime_type.clone()
+        .filter(|m| m != "application/octet-stream")
+        .or_else(|| mime_guess::from_path(&metadata.file_name).first().map(|m| m.to_string()))
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let essence = mime_type.split(';').next().unwrap_or("").trim();
+
+    match essence {
+        "image/svg+xml" | "text/html" | "application/xhtml+xml" => Viewer::None,
+        m if m.starts_with("image/") => Viewer::Image,
+        m if m.starts_with("video/") => Viewer::Video,
+        m if m.starts_with("audio/") => Viewer::Audio,
+        "application/pdf" => Viewer::Pdf,
+        "application/json" | "application/xml" | "application/javascript" | "application/x-sh"
+        | "application/toml" | "application/x-yaml" | "application/sql" => Viewer::Text,
+        m if m.starts_with("text/") => Viewer::Text,
+        _ => Viewer::None,
+    }
+}
+
+/// Bundled highlight.js for text previews
+pub async fn highlight_js() -> Response {
+    (
+        [(CONTENT_TYPE, "application/javascript; charset=utf-8"), (CACHE_CONTROL, PUBLIC_CACHE_CONTROL)],
+        HIGHLIGHT_JS,
+    ).into_response()
+}
+
+fn og_type(viewer: Viewer) -> &'static str {
+    match viewer {
+        Viewer::Video => "video.other",
+        Viewer::Audio => "music.song",
+        _ => "website",
+    }
+}
+
+/// Preview page with an inline viewer, download buttons and Open Graph tags
+pub async fn preview_page(State(state): State<AppState>, extract::Path(id): extract::Path<String>) -> Response {
+    let unique_id = extract_id_from_path(&id);
+
+    let metadata = match get_file_metadata(unique_id).await {
+        Some(m) => m,
+        None => {
+            warn!("Preview requested for unknown file: {}", unique_id);
+
+            return (StatusCode::NOT_FOUND, not_found_handler().await).into_response();
+        }
+    };
+
+    info!("Preview page for {}", metadata.file_name);
+
+    let file_domain = state.config.get().file_domain();
+    Html(render_preview(&metadata, &file_domain)).into_response()
+}
+
+fn render_preview(metadata: &FileMetadata, file_domain: &str) -> String {
+    let viewer = viewer_for(metadata);
+    let mime_type = metadata.mime_type.clone().unwrap_or_else(|| "application/octet-stream".to_string());
+
+    debug!("Using {:?} viewer for {} ({})", viewer, metadata.file_name, mime_type);
+
+    let path = encode_path_segment(&build_url_path(&metadata.unique_id, &metadata.file_name));
+    let inline_url = format!("/files/{}?inline=1", path);
+    let absolute_inline_url = format!("{}{}?inline=1", file_domain, path);
+    let download_url = format!("/files/{}?dl=1", path);
+    let preview_url = build_preview_url(file_domain, &metadata.unique_id, &metadata.file_name);
+
+    let name = escape_html(&metadata.file_name);
+    let description = escape_html(&format!("{} · {}", human_size(metadata.file_size as u64), mime_type));
+
+    let mut og = format!(
+        "<meta property=\"og:site_name\" content=\"FilesLink\">\
+        <meta property=\"og:title\" content=\"{}\">\
+        <meta property=\"og:description\" content=\"{}\">\
+        <meta property=\"og:type\" content=\"{}\">\
+        <meta property=\"og:url\" content=\"{}\">",
+        name, description, og_type(viewer), escape_html(&preview_url)
+    );
+
+    match viewer {
+        Viewer::Image => og.push_str(&format!(
+            "<meta property=\"og:image\" content=\"{0}\">\
+            <meta name=\"twitter:card\" content=\"summary_large_image\">\
+            <meta name=\"twitter:image\" content=\"{0}\">",
+            escape_html(&absolute_inline_url)
+        )),
+        Viewer::Video => og.push_str(&format!(
+            "<meta property=\"og:video\" content=\"{}\"><meta property=\"og:video:type\" content=\"{}\">",
+            escape_html(&absolute_inline_url), escape_html(&mime_type)
+        )),
+        Viewer::Audio => og.push_str(&format!(
+            "<meta property=\"og:audio\" content=\"{}\"><meta property=\"og:audio:type\" content=\"{}\">",
+            escape_html(&absolute_inline_url), escape_html(&mime_type)
+        )),
+        _ => {}
+    }
+
+    let src = escape_html(&inline_url);
+
+    let body = match viewer {
+        Viewer::Image => format!("<img src=\"{}\" alt=\"{}\">", src, name),
+        Viewer::Video => format!("<video controls preload=\"metadata\" src=\"{}\"></video>", src),
+        Viewer::Audio => format!("<audio controls preload=\"metadata\" src=\"{}\"></audio>", src),
+        Viewer::Pdf => format!("<iframe src=\"{}\" title=\"{}\"></iframe>", src, name),
+        Viewer::Text if metadata.file_size <= TEXT_PREVIEW_LIMIT => {
+            // The text is fetched and inserted with textContent, so it is never parsed as HTML
+            let language = metadata.file_name.rsplit_once('.')
+                .map(|(_, ext)| ext.to_lowercase())
+                .filter(|ext| ext.chars().all(|c| c.is_ascii_alphanumeric()))
+                .unwrap_or_default();
+
+            format!(
+                "<pre><code id=\"text\" class=\"language-{}\">Loading…</code></pre>\
+                <script src=\"{}\"></script>\
+                <script>\
+                fetch(\"{}\").then(r => r.text()).then(t => {{\
+                    const el = document.getElementById('text');\
+                    el.textContent = t;\
+                    if (window.hljs) {{ if (!hljs.getLanguage(el.className.slice(9))) el.className = ''; hljs.highlightBlock(el); }}\
+                }});\
+                </script>",
+                language, HIGHLIGHT_JS_PATH, src
+            )
+        }
+        Viewer::Text => "<p>This file is too large to preview.</p>".to_string(),
+        Viewer::None => "<p>No preview is available for this file type.</p>".to_string(),
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <title>{name}</title>
+    {og}
+    <style>
+        body {{ font-family: Arial, sans-serif; max-width: 1024px; margin: 24px auto; padding: 0 16px; }}
+        h1 {{ font-size: 1.3em; word-break: break-all; }}
+        img, video {{ max-width: 100%; max-height: 80vh; display: block; margin: 0 auto; }}
+        audio {{ width: 100%; }}
+        iframe {{ width: 100%; height: 80vh; border: 1px solid #ddd; }}
+        pre {{ max-height: 80vh; overflow: auto; background: #f6f8fa; padding: 12px; }}
+        .hljs-comment, .hljs-quote {{ color: #6a737d; }}
+        .hljs-keyword, .hljs-selector-tag, .hljs-type {{ color: #d73a49; }}
+        .hljs-string, .hljs-regexp, .hljs-addition {{ color: #032f62; }}
+        .hljs-number, .hljs-literal, .hljs-built_in, .hljs-attr, .hljs-attribute {{ color: #005cc5; }}
+        .hljs-title, .hljs-section, .hljs-name {{ color: #6f42c1; }}
+        .hljs-deletion {{ color: #b31d28; }}
+        .actions {{ margin: 16px 0; }}
+        .actions a {{ display: inline-block; padding: 8px 16px; background: #3498db; color: #fff; border-radius: 4px; text-decoration: none; margin-right: 8px; }}
+    </style>
+</head>
+<body>
+    <h1>{name}</h1>
+    <p>{description}</p>
+    {body}
+    <div class="actions">
+        <a href="{download}">Download</a>
+        <a href="{open}">Open</a>
+    </div>
+</body>
+</html>"#,
+        name = name,
+        og = og,
+        description = description,
+        body = body,
+        download = escape_html(&download_url),
+        open = src,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, mime_type: Option<&str>) -> FileMetadata {
+        FileMetadata {
+            unique_id: "abcd1234".to_owned(),
+            telegram_file_id: String::new(),
+            file_name: name.to_owned(),
+            mime_type: mime_type.map(|m| m.to_owned()),
+            file_size: 10,
+            uploaded_at: 0,
+            message_id: None,
+        }
+    }
+
+    #[test]
+    fn test_viewer_for() {
+        assert_eq!(viewer_for(&file("a.png", Some("image/png"))), Viewer::Image);
+        assert_eq!(viewer_for(&file("a.mp4", Some("video/mp4"))), Viewer::Video);
+        assert_eq!(viewer_for(&file("a.mp3", None)), Viewer::Audio);
+        assert_eq!(viewer_for(&file("a.pdf", Some("application/octet-stream"))), Viewer::Pdf);
+        assert_eq!(viewer_for(&file("main.rs", None)), Viewer::Text);
+        assert_eq!(viewer_for(&file("a.svg", Some("image/svg+xml"))), Viewer::None);
+        assert_eq!(viewer_for(&file("a.html", Some("text/html; charset=utf-8"))), Viewer::None);
+        assert_eq!(viewer_for(&file("a.zip", Some("application/zip"))), Viewer::None);
+    }
+
+    #[test]
+    fn test_render_preview_open_graph() {
+        let html = render_preview(&file("cat \"1\".png", Some("image/png")), "https://example.com/files/");
+
+        assert!(html.contains("<meta property=\"og:title\" content=\"cat &quot;1&quot;.png\">"));
+        assert!(html.contains("<meta property=\"og:image\" content=\"https://example.com/files/abcd1234_cat_%221%22.png?inline=1\">"));
+        assert!(html.contains("<img src=\"/files/abcd1234_cat_%221%22.png?inline=1\""));
+    }
+
+    #[test]
+    fn test_render_preview_uses_bundled_highlighter() {
+        let html = render_preview(&file("main.rs", None), "https://example.com/files/");
+
+        assert!(html.contains("<script src=\"/assets/highlight.min.js\"></script>"));
+        assert!(!html.contains("https://cdnjs"));
+    }
+}
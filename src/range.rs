@@ -0,0 +1,84 @@
+// Parsing of single `Range: bytes=...` request headers (RFC 9110 section 14)
+
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    /// No usable range, serve the whole representation
+    Full,
+    /// Inclusive byte range
+    Partial { start: u64, end: u64 },
+    /// The range lies outside the representation, answer with 416
+    Unsatisfiable,
+}
+
+/// Parse a Range header against a representation of `len` bytes.
+/// Multiple ranges and malformed headers are ignored, which the RFC allows.
+pub fn parse_range(header: Option<&str>, len: u64) -> ByteRange {
+    let spec = match header.and_then(|h| h.trim().strip_prefix("bytes=")) {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Full,
+    };
+
+    let (start, end) = match spec.split_once('-') {
+        Some(parts) => parts,
+        None => return ByteRange::Full,
+    };
+
+    if start.is_empty() {
+        // Suffix range: the last N bytes
+        return match end.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if len == 0 => ByteRange::Unsatisfiable,
+            Ok(n) => ByteRange::Partial { start: len.saturating_sub(n), end: len - 1 },
+            Err(_) => ByteRange::Full,
+        };
+    }
+
+    let start = match start.parse::<u64>() {
+        Ok(start) => start,
+        Err(_) => return ByteRange::Full,
+    };
+
+    let end = if end.is_empty() {
+        len.saturating_sub(1)
+    } else {
+        match end.parse::<u64>() {
+            Ok(end) if end >= start => end.min(len.saturating_sub(1)),
+            _ => return ByteRange::Full,
+        }
+    };
+
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+
+    ByteRange::Partial { start, end }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range(None, 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=0-9"), 100), ByteRange::Partial { start: 0, end: 9 });
+        assert_eq!(parse_range(Some("bytes=90-"), 100), ByteRange::Partial { start: 90, end: 99 });
+        assert_eq!(parse_range(Some("bytes=-10"), 100), ByteRange::Partial { start: 90, end: 99 });
+        assert_eq!(parse_range(Some("bytes=50-500"), 100), ByteRange::Partial { start: 50, end: 99 });
+    }
+
+    #[test]
+    fn test_parse_range_unsatisfiable() {
+        assert_eq!(parse_range(Some("bytes=100-"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-0"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=0-"), 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn test_parse_range_ignored() {
+        assert_eq!(parse_range(Some("bytes=0-1,5-6"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("items=0-1"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=9-1"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=abc"), 100), ByteRange::Full);
+    }
+}
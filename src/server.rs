@@ -1,162 +1,254 @@
 use std::convert::Infallible;
 use std::collections::HashMap;
+use std::io::SeekFrom;
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::response::IntoResponse;
 use axum::{
     body::Body,
-    extract::{self, State, Query},
+    extract::{self, DefaultBodyLimit, State, Query},
     response::{Html, Response},
-    routing::{get, Router},
+    routing::{delete, get, post, Router},
 };
-use http::{header::CONTENT_TYPE, StatusCode};
+use http::header::{ACCEPT_RANGES, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, LAST_MODIFIED, RANGE};
+use http::{HeaderMap, HeaderValue, StatusCode};
+use bot::repair::repair_file_on_demand;
+use futures_util::{future, StreamExt};
 use log::{debug, error, info, warn};
-use teloxide::net::Download;
-use teloxide::prelude::Requester;
-use base64::Engine;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::io::ReaderStream;
 
+use shared::api_keys::ApiKeyScope;
 use shared::file_storage::{get_file_metadata, list_all_files, FileMetadata};
-use crate::config::Config;
-use shared::link_utils::extract_id_from_path;
+use crate::api::{api_files_list, api_keys_create, api_keys_list, api_keys_revoke, api_stats, api_upload};
+use crate::auth::authorize;
+use crate::browser::{render_file_browser, BrowserQuery};
+use crate::conditional::{Validators, PUBLIC_CACHE_CONTROL};
+use crate::config::SharedConfig;
+use crate::disposition::content_disposition;
+use crate::file_cache::{ByteStream, CachedFile, FileCache, Lookup};
+use crate::preview::{highlight_js, preview_page, viewer_for, Viewer, HIGHLIGHT_JS_PATH};
+use crate::range::{parse_range, ByteRange};
+use crate::upstream::{open_upstream, FetchError};
+use crate::upload::{complete_upload, create_upload, upload_chunk, upload_page, upload_status, UPLOAD_CHUNK_SIZE};
+use crate::download_tokens::{issue_download_token, redeem_download_token};
+use shared::html_utils::escape_html;
+use shared::link_utils::{build_url_path, encode_path_segment, extract_id_from_path};
 
 #[derive(Clone)]
 pub struct AppState {
     pub bot: Arc<teloxide::Bot>,
+    /// Read on every request, so reloaded settings apply without a restart
+    pub config: SharedConfig,
+    /// Optional on-disk cache of downloaded files, enabled by `FILE_CACHE_DIR`
+    pub file_cache: Option<Arc<FileCache>>,
 }
 
-pub async fn create_app(bot: Arc<teloxide::Bot>) -> Router {
-    let enable_files_route = Config::instance().await.enable_files_route();
+pub async fn create_app(bot: Arc<teloxide::Bot>, shared_config: SharedConfig) -> Router {
+    let config = shared_config.get();
 
-    let state = AppState { bot };
+    let file_cache = match config.file_cache_dir() {
+        Some(dir) => {
+            let max_age = Duration::from_secs(config.file_cache_max_age());
 
-    let mut router = Router::new()
+            match FileCache::open(&dir, config.file_cache_max_size(), max_age).await {
+                Ok(cache) => Some(cache),
+                Err(e) => {
+                    error!("Failed to open file cache in {}, serving without it: {}", dir, e);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    let state = AppState { bot, config: shared_config, file_cache };
+
+    Router::new()
         .route("/", get(root))
+        .route("/files", get(files_list))
         .route("/files/:id", get(files_id))
-        .with_state(state);
+        .route("/d/:token", get(download_with_token))
+        .route("/v/:id", get(preview_page))
+        .route(HIGHLIGHT_JS_PATH, get(highlight_js))
+        .route("/api/v1/files", get(api_files_list))
+        // Uploads are size-checked while streaming, so the default 2 MB body limit does not apply
+        .route("/api/v1/upload", post(api_upload).layer(DefaultBodyLimit::disable()))
+        .route("/api/v1/keys", get(api_keys_list).post(api_keys_create))
+        .route("/api/v1/keys/:id", delete(api_keys_revoke))
+        .route("/api/v1/stats", get(api_stats))
+        .route("/upload", get(upload_page))
+        .route("/upload/sessions", post(create_upload))
+        .route("/upload/sessions/:id", get(upload_status)
+            .put(upload_chunk)
+            .layer(DefaultBodyLimit::max(UPLOAD_CHUNK_SIZE)))
+        .route("/upload/sessions/:id/complete", post(complete_upload))
+        .with_state(state)
+        .fallback(not_found_handler)
+}
+
+/// Stream a file straight from storage, honoring a single-range `Range` header.
+/// A range only forwards the requested bytes and stops reading after them.
+fn serve_stream(
+    upstream: ByteStream,
+    len: u64,
+    content_type: &str,
+    content_disposition: &str,
+    range: Option<&str>,
+    validators: &Validators,
+) -> Response<Body> {
+    let builder = file_response(content_type, content_disposition, validators);
+
+    match parse_range(range, len) {
+        ByteRange::Full => builder
+            .status(StatusCode::OK)
+            .body(Body::from_stream(upstream))
+            .unwrap(),
+        ByteRange::Partial { start, end } => {
+            debug!("Serving bytes {}-{} of {}", start, end, len);
 
-    if enable_files_route {
-        router = router.route("/files", get(files_list));
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len))
+                .header(CONTENT_LENGTH, end - start + 1)
+                .body(Body::from_stream(slice_stream(upstream, start, end)))
+                .unwrap()
+        }
+        ByteRange::Unsatisfiable => range_not_satisfiable(len),
     }
+}
+
+/// The bytes `start..=end` of a stream; the rest of it is never read
+fn slice_stream(upstream: ByteStream, start: u64, end: u64) -> ByteStream {
+    upstream
+        .scan(0u64, move |offset, chunk| {
+            if *offset > end {
+                return future::ready(None);
+            }
 
-    router.fallback(not_found_handler)
+            let chunk = chunk.map(|chunk| {
+                let chunk_start = *offset;
+                let chunk_len = chunk.len() as u64;
+                *offset += chunk_len;
+
+                let from = start.saturating_sub(chunk_start).min(chunk_len) as usize;
+                let to = (end + 1).saturating_sub(chunk_start).min(chunk_len) as usize;
+                chunk.slice(from..to)
+            });
+
+            future::ready(Some(chunk))
+        })
+        .filter(|chunk| future::ready(!matches!(chunk, Ok(bytes) if bytes.is_empty())))
+        .boxed()
 }
 
-/// Proxy large file download to FastTelethon service
-async fn proxy_to_fasttelethon(metadata: &FileMetadata) -> Result<Response<Body>, Infallible> {
-    let config = Config::instance().await;
-    let fasttelethon_url = config.fasttelethon_url();
-    
-    // Get storage channel ID from config
-    let channel_id = match config.storage_channel_id() {
-        Ok(id) => id.to_string(),
-        Err(_) => {
-            error!("STORAGE_CHANNEL_ID not configured");
-            return Ok(Response::builder()
-                .status(StatusCode::SERVICE_UNAVAILABLE)
-                .body(Body::from("Large file download service not configured"))
-                .unwrap());
-        }
+/// Serve a complete file from the download cache, honoring a single-range `Range` header
+async fn serve_cached_file(
+    file: &CachedFile,
+    content_type: &str,
+    content_disposition: &str,
+    range: Option<&str>,
+    validators: &Validators,
+) -> Response<Body> {
+    let (status, start, end) = match parse_range(range, file.size) {
+        ByteRange::Full => (StatusCode::OK, 0, file.size.saturating_sub(1)),
+        ByteRange::Partial { start, end } => (StatusCode::PARTIAL_CONTENT, start, end),
+        ByteRange::Unsatisfiable => return range_not_satisfiable(file.size),
     };
-    
-    // Get message_id from metadata (needed for FastTelethon MTProto download)
-    let message_id = match metadata.message_id {
-        Some(id) => id,
-        None => {
-            // Fall back to Bot API for files without message_id (uploaded before FastTelethon integration)
-            warn!("File {} has no message_id, cannot use FastTelethon", metadata.unique_id);
-            return Ok(Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .body(Body::from("File was uploaded before FastTelethon integration and cannot be downloaded via MTProto. Please re-upload the file."))
-                .unwrap());
+
+    let mut handle = match tokio::fs::File::open(&file.path).await {
+        Ok(handle) => handle,
+        Err(e) => {
+            error!("Failed to open cached file {}: {}", file.path.display(), e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("Failed to read file from cache"))
+                .unwrap();
         }
     };
-    
-    // Build FastTelethon download URL
-    let download_url = format!("{}/download/{}/{}", fasttelethon_url, channel_id, message_id);
-    
-    info!("Proxying large file download to FastTelethon: {}", download_url);
-    
-    // Make HTTP request to FastTelethon service
-    match reqwest::get(&download_url).await {
-        Ok(response) => {
-            if response.status().is_success() {
-                // Get headers from FastTelethon response (clone to avoid borrow issues)
-                let content_type = response.headers()
-                    .get("content-type")
-                    .and_then(|v| v.to_str().ok())
-                    .map(|s| s.to_string())
-                    .unwrap_or_else(|| "application/octet-stream".to_string());
-                
-                let content_disposition = response.headers()
-                    .get("content-disposition")
-                    .and_then(|v| v.to_str().ok())
-                    .map(|s| s.to_string())
-                    .unwrap_or_else(|| format!("attachment; filename=\"{}\"", metadata.file_name));
-                
-                // Stream the response body
-                let bytes = response.bytes().await.unwrap_or_default();
-                
-                info!("Successfully proxied {} bytes from FastTelethon", bytes.len());
-                
-                Ok(Response::builder()
-                    .status(StatusCode::OK)
-                    .header(CONTENT_TYPE, content_type)
-                    .header("Content-Disposition", content_disposition)
-                    .header("X-Content-Type-Options", "nosniff")
-                    .body(bytes.into())
-                    .unwrap())
-            } else {
-                error!("FastTelethon returned error: {}", response.status());
-                Ok(Response::builder()
-                    .status(StatusCode::BAD_GATEWAY)
-                    .body(Body::from("Failed to retrieve file from download service"))
-                    .unwrap())
-            }
-        }
-        Err(e) => {
-            error!("Failed to connect to FastTelethon: {:?}", e);
-            Ok(Response::builder()
-                .status(StatusCode::SERVICE_UNAVAILABLE)
-                .body(Body::from("Download service temporarily unavailable"))
-                .unwrap())
+
+    if start > 0 {
+        if let Err(e) = handle.seek(SeekFrom::Start(start)).await {
+            error!("Failed to seek in cached file {}: {}", file.path.display(), e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("Failed to read file from cache"))
+                .unwrap();
         }
     }
+
+    let len = if file.size == 0 { 0 } else { end - start + 1 };
+    let mut builder = file_response(content_type, content_disposition, validators)
+        .status(status)
+        .header(CONTENT_LENGTH, len);
+
+    if status == StatusCode::PARTIAL_CONTENT {
+        debug!("Serving cached bytes {}-{} of {}", start, end, file.size);
+        builder = builder.header(CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, file.size));
+    }
+
+    builder
+        .body(Body::from_stream(ReaderStream::new(handle.take(len))))
+        .unwrap()
 }
 
-/// Lists all files from the file storage metadata
-async fn files_list() -> Result<Response<Body>, Infallible> {
-    info!("Files list accessed");
+/// Headers shared by every file response
+fn file_response(content_type: &str, content_disposition: &str, validators: &Validators) -> http::response::Builder {
+    // A stored type that is not a valid header must not break every download of the file
+    let content_type = HeaderValue::from_str(content_type)
+        .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream"));
 
-    let files = list_all_files().await;
+    Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .header(CONTENT_DISPOSITION, content_disposition)
+        .header(ACCEPT_RANGES, "bytes")
+        .header(ETAG, &validators.etag)
+        .header(LAST_MODIFIED, validators.last_modified_header())
+        .header(CACHE_CONTROL, PUBLIC_CACHE_CONTROL)
+        .header("X-Content-Type-Options", "nosniff")
+}
 
-    if files.is_empty() {
-        return Ok(Response::builder()
-            .status(StatusCode::OK)
-            .header(CONTENT_TYPE, "text/html")
-            .body(Body::from("<h1>Files in storage</h1><p>No files uploaded yet.</p>"))
-            .unwrap());
-    }
+fn range_not_satisfiable(len: u64) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+        .header(CONTENT_RANGE, format!("bytes */{}", len))
+        .body(Body::empty())
+        .unwrap()
+}
 
-    let mut html = String::from("<h1>Files in storage</h1><ul>");
+/// Searchable, paginated browser over the file storage metadata
+async fn files_list(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<BrowserQuery>,
+) -> Result<Response<Body>, Infallible> {
+    info!("Files list accessed");
+
+    let config = state.config.get();
 
-    for file in files {
-        html.push_str(&format!(
-            "<li><a href=\"/files/{}\">{}</a> ({} bytes)</li>",
-            file.unique_id, file.file_name, file.file_size
-        ));
+    // When set, `/files` is public; otherwise it requires an API key with the read scope
+    if !config.enable_files_route() {
+        if let Err(response) = authorize(&headers, ApiKeyScope::Read).await {
+            return Ok(response);
+        }
     }
 
-    html.push_str("</ul>");
+    let files = list_all_files().await;
+    let file_domain = config.file_domain();
 
     Ok(Response::builder()
         .status(StatusCode::OK)
-        .header(CONTENT_TYPE, "text/html")
-        .body(Body::from(html))
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(render_file_browser(files, &query, &file_domain)))
         .unwrap())
 }
 
 async fn files_id(
     State(state): State<AppState>,
+    headers: HeaderMap,
     extract::Path(id): extract::Path<String>,
     Query(params): Query<HashMap<String, String>>,
 ) -> Result<Response<Body>, Infallible> {
@@ -183,68 +275,202 @@ async fn files_id(
 
     info!("Found file: {} (Telegram ID: {})", metadata.file_name, metadata.telegram_file_id);
 
-    // Try to get file from Telegram, but if it's too big, proxy to FastTelethon
-    let file_info = match state.bot.get_file(&metadata.telegram_file_id).await {
-        Ok(info) => Some(info),
-        Err(e) => {
-            let error_msg = format!("{:?}", e);
-            if error_msg.contains("file is too big") || error_msg.contains("Bad Request") {
-                warn!("File too large for bot API, proxying to FastTelethon: {}", metadata.file_name);
-                
-                // Proxy to FastTelethon service for large files
-                return proxy_to_fasttelethon(&metadata).await;
-            } else {
-                error!("Failed to get file info from Telegram: {:?}", e);
-                return Ok(Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(Body::from("Failed to retrieve file from storage"))
-                    .unwrap());
-            }
-        }
+    // ?close=1 answers with a small page that starts a download of a one-time URL and closes itself.
+    // The file itself is never fetched here.
+    if params.contains_key("close") {
+        let token = issue_download_token(&metadata.unique_id).await;
+
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(CACHE_CONTROL, "no-store")
+            .body(Body::from(auto_close_page(&metadata, &token)))
+            .unwrap());
+    }
+
+    Ok(serve_file(&state, &metadata, &params, &headers).await)
+}
+
+/// One-time download URL used by the `?close=1` page
+async fn download_with_token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    extract::Path(token): extract::Path<String>,
+) -> Result<Response<Body>, Infallible> {
+    let metadata = match redeem_download_token(&token).await {
+        Some(unique_id) => get_file_metadata(&unique_id).await,
+        None => None,
     };
 
-    // Download file from Telegram if possible
-    let mut file_bytes = Vec::new();
-    if let Some(file_info) = file_info {
-        match state.bot.download_file(&file_info.path, &mut file_bytes).await {
-            Ok(_) => {
-                info!("Successfully downloaded {} bytes from Telegram", file_bytes.len());
-            }
-            Err(e) => {
-                error!("Failed to download file from Telegram: {:?}", e);
-                return Ok(Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(Body::from("Failed to download file from storage"))
-                    .unwrap());
-            }
+    let metadata = match metadata {
+        Some(m) => m,
+        None => {
+            warn!("Invalid or already used download token");
+            let body = not_found_handler().await;
+            return Ok((
+                StatusCode::NOT_FOUND,
+                [(CONTENT_TYPE, "text/html")],
+                body,
+            ).into_response());
         }
+    };
+
+    // The one-time URL must not be replayed from a shared cache
+    let mut response = serve_file(&state, &metadata, &HashMap::new(), &headers).await;
+    response.headers_mut().insert(CACHE_CONTROL, http::HeaderValue::from_static("no-store"));
+
+    Ok(response)
+}
+
+/// Fetch a stored file and build the download response
+async fn serve_file(
+    state: &AppState,
+    metadata: &FileMetadata,
+    params: &HashMap<String, String>,
+    headers: &HeaderMap,
+) -> Response<Body> {
+    // Stored files never change, so a matching validator is answered without fetching anything
+    let validators = Validators::for_file(metadata);
+
+    if validators.is_not_modified(headers) {
+        debug!("Not modified: {}", metadata.file_name);
+
+        return Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(ETAG, &validators.etag)
+            .header(LAST_MODIFIED, validators.last_modified_header())
+            .header(CACHE_CONTROL, PUBLIC_CACHE_CONTROL)
+            .body(Body::empty())
+            .unwrap();
     }
 
-    // Check if auto-close is requested via ?close=1
-    let auto_close = params.get("close").is_some();
-    
     // Determine content type, allow force download via ?dl=1
-    let force_download = params.get("dl").is_some();
+    let force_download = params.contains_key("dl");
+
+    // ?inline=1 is used by the preview page; only types with a safe viewer are shown inline
+    let viewer = viewer_for(metadata);
+    let inline = params.contains_key("inline") && !force_download && viewer != Viewer::None;
+
     let content_type = if force_download {
         "application/octet-stream".to_string()
+    } else if inline && viewer == Viewer::Text {
+        // Never let the browser render user-supplied markup
+        "text/plain; charset=utf-8".to_string()
     } else {
         metadata.mime_type
+            .clone()
             .unwrap_or_else(|| "application/octet-stream".to_string())
     };
 
-    // Use original filename from metadata, force download as attachment
-    let content_disposition = format!("attachment; filename=\"{}\"", metadata.file_name);
+    // Use original filename from metadata, download as attachment unless shown inline
+    let disposition_type = if inline { "inline" } else { "attachment" };
+    let content_disposition = content_disposition(disposition_type, &metadata.file_name);
+
+    // A stale If-Range means the client's partial copy is unusable, so send the whole file
+    let range = headers.get(RANGE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|_| validators.if_range_matches(headers));
+
+    if let Some(cache) = state.file_cache.as_ref().filter(|c| c.accepts(metadata.file_size as u64)) {
+        return serve_through_cache(state, cache, metadata, &content_type, &content_disposition, range, &validators).await;
+    }
+
+    let upstream = match open_upstream_or_repair(state, metadata).await {
+        Ok(upstream) => upstream,
+        Err(e) => return e.into_response(),
+    };
+
+    info!("Serving file: {} ({} bytes) with content type: {}",
+          metadata.file_name, metadata.file_size, content_type);
+
+    serve_stream(upstream, metadata.file_size as u64, &content_type, &content_disposition, range, &validators)
+}
+
+/// Serve a file through the download cache. On a miss the file is fetched once:
+/// a plain request streams it while it is written to disk, Range requests and
+/// concurrent requests for the same file wait for the complete copy.
+async fn serve_through_cache(
+    state: &AppState,
+    cache: &Arc<FileCache>,
+    metadata: &FileMetadata,
+    content_type: &str,
+    content_disposition: &str,
+    range: Option<&str>,
+    validators: &Validators,
+) -> Response<Body> {
+    let cached = match cache.lookup(&metadata.unique_id) {
+        Lookup::Hit(file) => {
+            debug!("Serving {} from the file cache", metadata.unique_id);
+            Some(file)
+        }
+        Lookup::Pending(pending) => {
+            debug!("Waiting for running download of {}", metadata.unique_id);
+            pending.wait().await
+        }
+        Lookup::Miss(filler) => {
+            let upstream = match open_upstream_or_repair(state, metadata).await {
+                Ok(upstream) => upstream,
+                Err(e) => return e.into_response(),
+            };
+
+            if range.is_none() {
+                info!("Streaming {} while caching it", metadata.file_name);
+
+                let (tx, rx) = mpsc::channel(16);
+                filler.spawn(upstream, metadata.file_size as u64, Some(tx));
+
+                return file_response(content_type, content_disposition, validators)
+                    .status(StatusCode::OK)
+                    .body(Body::from_stream(ReceiverStream::new(rx)))
+                    .unwrap();
+            }
+
+            filler.spawn(upstream, metadata.file_size as u64, None).wait().await
+        }
+    };
+
+    match cached {
+        Some(file) => serve_cached_file(&file, content_type, content_disposition, range, validators).await,
+        None => FetchError::Upstream.into_response(),
+    }
+}
 
-    info!("Serving file: {} ({} bytes) with content type: {}", 
-          metadata.file_name, file_bytes.len(), content_type);
+/// Open the download, repairing the record once when Telegram no longer knows
+/// the stored file id or the record has no storage message id
+async fn open_upstream_or_repair(state: &AppState, metadata: &FileMetadata) -> Result<ByteStream, FetchError> {
+    let config = state.config.get();
+
+    let error = match open_upstream(&state.bot, &config, metadata).await {
+        Err(FetchError::Missing) => FetchError::Missing,
+        Err(FetchError::TooLarge) if metadata.message_id.is_none() => FetchError::TooLarge,
+        result => return result,
+    };
+
+    info!("Stored references of {} are broken, trying to repair", metadata.unique_id);
+
+    match repair_file_on_demand(&state.bot, &config, metadata).await {
+        Some(repaired) => open_upstream(&state.bot, &config, &repaired).await,
+        None => Err(error),
+    }
+}
 
-    // If auto-close requested, return HTML with auto-download and close script
-    if auto_close {
-        let html = format!(
-            r#"<!DOCTYPE html>
+/// Page that starts the download through a redirect to a one-time URL, then closes the tab.
+/// Without JavaScript the redirect still happens, only the tab stays open.
+fn auto_close_page(metadata: &FileMetadata, token: &str) -> String {
+    let name = escape_html(&metadata.file_name);
+    let download_url = format!("/d/{}", token);
+    let fallback_url = format!(
+        "/files/{}?dl=1",
+        encode_path_segment(&build_url_path(&metadata.unique_id, &metadata.file_name))
+    );
+
+    format!(
+        r#"<!DOCTYPE html>
 <html>
 <head>
-    <title>Downloading {}</title>
+    <meta charset="utf-8">
+    <title>Downloading {name}</title>
+    <meta http-equiv="refresh" content="0;url={download}">
     <style>
         body {{ font-family: Arial, sans-serif; text-align: center; padding: 50px; }}
         .loader {{ border: 5px solid #f3f3f3; border-top: 5px solid #3498db; 
@@ -254,56 +480,22 @@ async fn files_id(
     </style>
 </head>
 <body>
-    <h2>Downloading {}</h2>
+    <h2>Downloading {name}</h2>
     <div class="loader"></div>
     <p>Your download will begin shortly...</p>
-    <p><small>This window will close automatically.</small></p>
+    <p><small>This window will close automatically. If the download does not start, <a href="{fallback}">click here</a>.</small></p>
     <script>
-        // Create blob from base64 data and trigger download
-        const base64Data = "{}";
-        const byteCharacters = atob(base64Data);
-        const byteNumbers = new Array(byteCharacters.length);
-        for (let i = 0; i < byteCharacters.length; i++) {{
-            byteNumbers[i] = byteCharacters.charCodeAt(i);
-        }}
-        const byteArray = new Uint8Array(byteNumbers);
-        const blob = new Blob([byteArray], {{ type: '{}' }});
-        const url = window.URL.createObjectURL(blob);
-        const a = document.createElement('a');
-        a.href = url;
-        a.download = "{}";
-        document.body.appendChild(a);
-        a.click();
-        window.URL.revokeObjectURL(url);
-        
-        // Close window after 2 seconds
+        // Close window after the download has started
         setTimeout(function() {{
             window.close();
-        }}, 2000);
+        }}, 3000);
     </script>
 </body>
 </html>"#,
-            metadata.file_name,
-            metadata.file_name,
-            base64::engine::general_purpose::STANDARD.encode(&file_bytes),
-            content_type,
-            metadata.file_name
-        );
-        
-        return Ok(Response::builder()
-            .status(StatusCode::OK)
-            .header(CONTENT_TYPE, "text/html; charset=utf-8")
-            .body(html.into())
-            .unwrap());
-    }
-
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .header(CONTENT_TYPE, content_type)
-        .header("Content-Disposition", content_disposition)
-        .header("X-Content-Type-Options", "nosniff")
-        .body(file_bytes.into())
-        .unwrap())
+        name = name,
+        download = escape_html(&download_url),
+        fallback = escape_html(&fallback_url),
+    )
 }
 
 async fn root() -> Html<&'static str> {
@@ -315,10 +507,74 @@ async fn root() -> Html<&'static str> {
     ")
 }
 
-async fn not_found_handler() -> Html<&'static str> {
+pub(crate) async fn not_found_handler() -> Html<&'static str> {
     Html("\
     <h1>404 Not Found</h1>\
     <p>The page you are looking for does not exist.</p>\
     <a href=\"/\">Go back to the homepage</a>\
     ")
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auto_close_page_escapes_file_name() {
+        let metadata = FileMetadata {
+            unique_id: "abcd1234".to_owned(),
+            telegram_file_id: String::new(),
+            file_name: "</title><script>alert(1)</script>\"#?.txt".to_owned(),
+            mime_type: None,
+            file_size: 1,
+            uploaded_at: 0,
+            message_id: None,
+        };
+        let html = auto_close_page(&metadata, "tok");
+
+        assert!(!html.contains("<script>alert(1)"));
+        assert!(html.contains("Downloading &lt;/title&gt;&lt;script&gt;alert(1)&lt;/script&gt;&quot;#?.txt"));
+        assert!(html.contains("href=\"/files/abcd1234_%3C%2Ftitle%3E%3Cscript%3Ealert(1)%3C%2Fscript%3E%22%23%3F.txt?dl=1\""));
+    }
+
+    #[test]
+    fn test_file_response_with_invalid_content_type() {
+        let metadata = FileMetadata {
+            unique_id: "abcd1234".to_owned(),
+            telegram_file_id: String::new(),
+            file_name: "a.txt".to_owned(),
+            mime_type: Some("text/plain\n".to_owned()),
+            file_size: 1,
+            uploaded_at: 0,
+            message_id: None,
+        };
+        let validators = Validators::for_file(&metadata);
+
+        let response = file_response("text/plain\n", "attachment", &validators).body(Body::empty()).unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/octet-stream");
+    }
+
+    #[tokio::test]
+    async fn test_slice_stream() {
+        let chunks = || -> ByteStream {
+            futures_util::stream::iter(["abcd", "efgh", "ijkl"].map(|c| Ok(bytes::Bytes::from(c)))).boxed()
+        };
+        let collect = |stream: ByteStream| async move {
+            let chunks: Vec<_> = stream.map(|c| c.unwrap()).collect().await;
+            String::from_utf8(chunks.concat()).unwrap()
+        };
+
+        assert_eq!(collect(slice_stream(chunks(), 0, 11)).await, "abcdefghijkl");
+        assert_eq!(collect(slice_stream(chunks(), 2, 5)).await, "cdef");
+        assert_eq!(collect(slice_stream(chunks(), 4, 7)).await, "efgh");
+        assert_eq!(collect(slice_stream(chunks(), 11, 11)).await, "l");
+
+        // Nothing after the range is read
+        let failing: ByteStream = futures_util::stream::iter(vec![
+            Ok(bytes::Bytes::from("abcd")),
+            Ok(bytes::Bytes::from("efgh")),
+            Err("not reached".to_owned()),
+        ]).boxed();
+        assert_eq!(collect(slice_stream(failing, 1, 2)).await, "bc");
+    }
+}
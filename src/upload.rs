@@ -0,0 +1,571 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use axum::body::{Body, Bytes};
+use axum::extract::{self, Query, State};
+use axum::response::{Html, IntoResponse, Response};
+use axum::Json;
+use bot::storage::{store_local_file, MAX_UPLOAD_SIZE};
+use http::header::{COOKIE, LOCATION, SET_COOKIE};
+use http::{HeaderMap, HeaderValue, StatusCode};
+use log::{error, info, warn};
+use nanoid::nanoid;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use shared::api_keys::ApiKeyScope;
+use shared::upload_tokens::{claim_upload_token, verify_upload_session};
+use shared::utils::get_file_name_from_path;
+
+use crate::api::{file_json, json_error};
+use crate::auth::authorize;
+use crate::server::AppState;
+
+/// Size of the chunks the upload page sends, also the body limit of the chunk route
+pub const UPLOAD_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Unfinished uploads are discarded after this long without progress
+const STALE_UPLOAD_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often abandoned uploads are looked for
+const STALE_UPLOAD_SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Declared size of all unfinished uploads together, bounds the temporary disk space they use
+const MAX_PENDING_UPLOAD_SIZE: u64 = 4 * MAX_UPLOAD_SIZE;
+
+/// Unfinished uploads one API key or upload link may have at a time
+const MAX_UPLOADS_PER_OWNER: usize = 4;
+
+const SESSION_COOKIE: &str = "fileslink_upload";
+
+struct UploadSession {
+    /// API key id or browser session that started the upload
+    owner: String,
+    file_name: String,
+    mime_type: Option<String>,
+    file_size: u64,
+    received: u64,
+    path: PathBuf,
+    /// Set while a chunk is being written, so chunks can't interleave
+    busy: bool,
+    updated_at: Instant,
+}
+
+static UPLOADS: Lazy<Mutex<HashMap<String, UploadSession>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Deserialize)]
+pub struct CreateUploadRequest {
+    file_name: String,
+    file_size: u64,
+    mime_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ChunkQuery {
+    offset: u64,
+}
+
+fn session_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers.get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// Authorize an upload either with an API key (upload scope) or a redeemed upload link.
+/// Returns an owner string that ties upload sessions to whoever created them.
+async fn authorize_upload(headers: &HeaderMap) -> Result<String, Response> {
+    if headers.contains_key(http::header::AUTHORIZATION) {
+        return authorize(headers, ApiKeyScope::Upload).await
+            .map(|key| format!("key:{}", key.id));
+    }
+
+    match session_cookie(headers) {
+        Some(session_id) if verify_upload_session(session_id).await => Ok(format!("session:{}", session_id)),
+        _ => Err(json_error(StatusCode::UNAUTHORIZED, "Upload link expired or missing, request a new one with /uploadlink")),
+    }
+}
+
+async fn remove_stale_uploads(uploads: &mut HashMap<String, UploadSession>) {
+    let stale: Vec<String> = uploads.iter()
+        .filter(|(_, u)| !u.busy && u.updated_at.elapsed() > STALE_UPLOAD_AGE)
+        .map(|(id, _)| id.clone())
+        .collect();
+
+    for id in stale {
+        if let Some(upload) = uploads.remove(&id) {
+            warn!("Discarding stale upload {} ({})", id, upload.file_name);
+
+            let _ = fs::remove_file(&upload.path).await;
+        }
+    }
+}
+
+/// Discard abandoned uploads periodically, so their temporary files don't wait for the next upload
+pub async fn run_stale_upload_sweeper() {
+    let mut interval = tokio::time::interval(STALE_UPLOAD_SWEEP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        remove_stale_uploads(&mut *UPLOADS.lock().await).await;
+    }
+}
+
+/// The client's MIME type is served back as `Content-Type`, so it has to be a valid one.
+/// Missing and generic types are left to be guessed from the file name.
+fn parse_mime_type(mime_type: Option<String>) -> Result<Option<String>, String> {
+    let mime_type = match mime_type {
+        Some(m) if !m.is_empty() && m != "application/octet-stream" => m,
+        _ => return Ok(None),
+    };
+
+    if mime_type.parse::<mime::Mime>().is_err() || HeaderValue::from_str(&mime_type).is_err() {
+        return Err(format!("'{}' is not a valid MIME type", mime_type.escape_debug()));
+    }
+
+    Ok(Some(mime_type))
+}
+
+/// Why a new upload of `file_size` bytes can't start next to the unfinished ones
+fn check_upload_quota(uploads: &HashMap<String, UploadSession>, owner: &str, file_size: u64) -> Result<(), (StatusCode, String)> {
+    if file_size > MAX_UPLOAD_SIZE {
+        return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("File exceeds the {} byte limit", MAX_UPLOAD_SIZE)));
+    }
+
+    if uploads.values().filter(|u| u.owner == owner).count() >= MAX_UPLOADS_PER_OWNER {
+        return Err((StatusCode::TOO_MANY_REQUESTS, format!("At most {} unfinished uploads at a time, complete one first", MAX_UPLOADS_PER_OWNER)));
+    }
+
+    let pending: u64 = uploads.values().map(|u| u.file_size).sum();
+    if pending.saturating_add(file_size) > MAX_PENDING_UPLOAD_SIZE {
+        return Err((StatusCode::INSUFFICIENT_STORAGE, "Too many uploads in progress, try again later".to_owned()));
+    }
+
+    Ok(())
+}
+
+/// Drag-and-drop upload page. `?token=` redeems a one-time upload link and sets the session cookie.
+pub async fn upload_page(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    if let Some(token) = params.get("token") {
+        return match claim_upload_token(token).await {
+            Some((session_id, ttl)) => {
+                info!("Upload link redeemed");
+
+                let secure = if state.config.get().file_domain().starts_with("https://") { "; Secure" } else { "" };
+
+                Response::builder()
+                    .status(StatusCode::SEE_OTHER)
+                    .header(LOCATION, "/upload")
+                    .header(SET_COOKIE, format!(
+                        "{}={}; Path=/upload; HttpOnly; SameSite=Strict; Max-Age={}{}",
+                        SESSION_COOKIE, session_id, ttl.as_secs(), secure
+                    ))
+                    .body(Body::empty())
+                    .unwrap()
+            }
+            None => {
+                warn!("Rejected invalid, expired or already used upload link");
+
+                (StatusCode::FORBIDDEN, Html(
+                    "<h1>Upload link expired</h1>\
+                    <p>This upload link is invalid, expired or has already been used. Ask the bot for a new one with /uploadlink.</p>"
+                )).into_response()
+            }
+        };
+    }
+
+    let has_session = match session_cookie(&headers) {
+        Some(session_id) => verify_upload_session(session_id).await,
+        None => false,
+    };
+
+    Html(UPLOAD_PAGE
+        .replace("{{chunk_size}}", &UPLOAD_CHUNK_SIZE.to_string())
+        .replace("{{has_session}}", if has_session { "true" } else { "false" }))
+        .into_response()
+}
+
+/// Start a resumable upload
+pub async fn create_upload(
+    headers: HeaderMap,
+    Json(request): Json<CreateUploadRequest>,
+) -> Response {
+    let owner = match authorize_upload(&headers).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    let file_name = match get_file_name_from_path(&request.file_name) {
+        Some(name) if !name.is_empty() => name.to_owned(),
+        _ => format!("file_{}", nanoid!(8)),
+    };
+
+    let mime_type = match parse_mime_type(request.mime_type) {
+        Ok(mime_type) => mime_type,
+        Err(message) => return json_error(StatusCode::BAD_REQUEST, &message),
+    };
+
+    let mut uploads = UPLOADS.lock().await;
+    remove_stale_uploads(&mut uploads).await;
+
+    if let Err((status, message)) = check_upload_quota(&uploads, &owner, request.file_size) {
+        warn!("Rejected upload of {} ({} bytes): {}", file_name, request.file_size, message);
+
+        return json_error(status, &message);
+    }
+
+    let upload_id = nanoid!(16);
+    let path = std::env::temp_dir().join(format!("fileslink-upload-{}", upload_id));
+
+    if let Err(e) = fs::File::create(&path).await {
+        error!("Failed to create temporary upload file: {}", e);
+
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to start upload");
+    }
+
+    info!("Started upload {} for {} ({} bytes)", upload_id, file_name, request.file_size);
+
+    uploads.insert(upload_id.clone(), UploadSession {
+        owner,
+        file_name,
+        mime_type,
+        file_size: request.file_size,
+        received: 0,
+        path,
+        busy: false,
+        updated_at: Instant::now(),
+    });
+
+    (StatusCode::CREATED, Json(json!({
+        "upload_id": upload_id,
+        "chunk_size": UPLOAD_CHUNK_SIZE,
+        "received": 0,
+    }))).into_response()
+}
+
+/// Report how many bytes of an upload have been received, used to resume
+pub async fn upload_status(
+    headers: HeaderMap,
+    extract::Path(upload_id): extract::Path<String>,
+) -> Response {
+    let owner = match authorize_upload(&headers).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    let uploads = UPLOADS.lock().await;
+
+    match uploads.get(&upload_id) {
+        Some(upload) if upload.owner == owner => Json(json!({
+            "upload_id": upload_id,
+            "file_size": upload.file_size,
+            "received": upload.received,
+        })).into_response(),
+        _ => json_error(StatusCode::NOT_FOUND, "Upload not found"),
+    }
+}
+
+/// Append a chunk. `offset` must equal the number of bytes already received,
+/// otherwise 409 is returned with the current offset so the client can resume.
+pub async fn upload_chunk(
+    headers: HeaderMap,
+    extract::Path(upload_id): extract::Path<String>,
+    Query(query): Query<ChunkQuery>,
+    chunk: Bytes,
+) -> Response {
+    let owner = match authorize_upload(&headers).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    let path = {
+        let mut uploads = UPLOADS.lock().await;
+
+        let upload = match uploads.get_mut(&upload_id) {
+            Some(upload) if upload.owner == owner => upload,
+            _ => return json_error(StatusCode::NOT_FOUND, "Upload not found"),
+        };
+
+        if upload.busy || query.offset != upload.received {
+            return (StatusCode::CONFLICT, Json(json!({
+                "error": "Offset does not match received bytes",
+                "received": upload.received,
+            }))).into_response();
+        }
+
+        if upload.received + chunk.len() as u64 > upload.file_size {
+            return json_error(StatusCode::PAYLOAD_TOO_LARGE, "Chunk exceeds the declared file size");
+        }
+
+        upload.busy = true;
+        upload.path.clone()
+    };
+
+    let written = async {
+        let mut file = OpenOptions::new().append(true).open(&path).await?;
+        file.write_all(&chunk).await?;
+        file.flush().await
+    }.await;
+
+    let mut uploads = UPLOADS.lock().await;
+
+    let upload = match uploads.get_mut(&upload_id) {
+        Some(upload) => upload,
+        None => return json_error(StatusCode::NOT_FOUND, "Upload not found"),
+    };
+
+    upload.busy = false;
+    upload.updated_at = Instant::now();
+
+    if let Err(e) = written {
+        error!("Failed to write chunk for upload {}: {}", upload_id, e);
+
+        // The chunk may be partially written, truncate back to the last good offset
+        if let Ok(file) = OpenOptions::new().write(true).open(&path).await {
+            let _ = file.set_len(upload.received).await;
+        }
+
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to write chunk");
+    }
+
+    upload.received += chunk.len() as u64;
+
+    Json(json!({
+        "upload_id": upload_id,
+        "file_size": upload.file_size,
+        "received": upload.received,
+    })).into_response()
+}
+
+/// Finish an upload: store it in the channel through the same pipeline as bot uploads
+pub async fn complete_upload(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    extract::Path(upload_id): extract::Path<String>,
+) -> Response {
+    let owner = match authorize_upload(&headers).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    let upload = {
+        let mut uploads = UPLOADS.lock().await;
+
+        match uploads.get(&upload_id) {
+            Some(upload) if upload.owner == owner && !upload.busy => {
+                if upload.received != upload.file_size {
+                    return (StatusCode::CONFLICT, Json(json!({
+                        "error": "Upload is incomplete",
+                        "received": upload.received,
+                    }))).into_response();
+                }
+            }
+            _ => return json_error(StatusCode::NOT_FOUND, "Upload not found"),
+        }
+
+        uploads.remove(&upload_id).unwrap()
+    };
+
+    info!("Upload {} complete, storing {} ({} bytes)", upload_id, upload.file_name, upload.file_size);
+
+    let result = store_local_file(&state.bot, &state.config.get(), &upload.path, &upload.file_name, upload.mime_type).await;
+
+    let _ = fs::remove_file(&upload.path).await;
+
+    match result {
+        Ok(metadata) => (StatusCode::CREATED, Json(file_json(&metadata, &state.config.get().file_domain()))).into_response(),
+        Err(e) => {
+            error!("Failed to store upload {}: {}", upload.file_name, e);
+
+            json_error(StatusCode::BAD_GATEWAY, "Failed to store file in Telegram")
+        }
+    }
+}
+
+const UPLOAD_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <title>Upload files</title>
+    <style>
+        body { font-family: Arial, sans-serif; max-width: 720px; margin: 40px auto; padding: 0 16px; }
+        #drop { border: 3px dashed #aaa; border-radius: 8px; padding: 48px; text-align: center; color: #666; cursor: pointer; }
+        #drop.over { border-color: #3498db; color: #3498db; }
+        #login { margin-bottom: 20px; }
+        .file { margin: 14px 0; }
+        .file progress { width: 100%; }
+        .status { font-size: 0.9em; color: #666; word-break: break-all; }
+        .error { color: #c0392b; }
+    </style>
+</head>
+<body>
+    <h1>Upload files</h1>
+    <div id="login" hidden>
+        <p>Open an upload link from the bot (<code>/uploadlink</code>) or enter an API key with the upload scope.</p>
+        <input id="key" type="password" placeholder="API key" size="40">
+        <button id="save-key">Use key</button>
+    </div>
+    <div id="drop">Drop files here or click to choose</div>
+    <input id="picker" type="file" multiple hidden>
+    <div id="files"></div>
+    <script>
+        const CHUNK_SIZE = {{chunk_size}};
+        const HAS_SESSION = {{has_session}};
+
+        const login = document.getElementById('login');
+        if (!HAS_SESSION && !sessionStorage.getItem('fileslink_key')) login.hidden = false;
+        document.getElementById('save-key').onclick = () => {
+            sessionStorage.setItem('fileslink_key', document.getElementById('key').value.trim());
+            login.hidden = true;
+        };
+
+        async function api(method, url, body, headers) {
+            const key = sessionStorage.getItem('fileslink_key');
+            const h = Object.assign({}, headers || {});
+            if (key && !HAS_SESSION) h['Authorization'] = 'Bearer ' + key;
+            const r = await fetch(url, { method: method, body: body, headers: h, credentials: 'same-origin' });
+            const data = await r.json().catch(() => ({}));
+            if (!r.ok) { const e = new Error(data.error || ('HTTP ' + r.status)); e.status = r.status; throw e; }
+            return data;
+        }
+
+        function addRow(file) {
+            const row = document.createElement('div');
+            row.className = 'file';
+            const name = document.createElement('div');
+            name.textContent = file.name;
+            const progress = document.createElement('progress');
+            progress.max = 100; progress.value = 0;
+            const status = document.createElement('div');
+            status.className = 'status';
+            status.textContent = 'Waiting…';
+            row.append(name, progress, status);
+            document.getElementById('files').append(row);
+            return { progress: progress, status: status };
+        }
+
+        async function upload(file, row) {
+            // Resume an earlier attempt of the same file if the server still has it
+            const resumeKey = 'fileslink:' + file.name + ':' + file.size + ':' + file.lastModified;
+            let id = localStorage.getItem(resumeKey);
+            let received = 0;
+            if (id) {
+                try { received = (await api('GET', '/upload/sessions/' + id)).received; } catch (e) { id = null; }
+            }
+            if (!id) {
+                const s = await api('POST', '/upload/sessions',
+                    JSON.stringify({ file_name: file.name, file_size: file.size, mime_type: file.type || null }),
+                    { 'Content-Type': 'application/json' });
+                id = s.upload_id;
+                localStorage.setItem(resumeKey, id);
+            }
+            while (received < file.size) {
+                row.status.textContent = 'Uploading… ' + Math.floor(received * 100 / file.size) + '%';
+                const chunk = file.slice(received, received + CHUNK_SIZE);
+                for (let attempt = 1; ; attempt++) {
+                    try {
+                        received = (await api('PUT', '/upload/sessions/' + id + '?offset=' + received, chunk)).received;
+                        break;
+                    } catch (e) {
+                        if (attempt >= 5 || e.status === 401 || e.status === 404) throw e;
+                        await new Promise(r => setTimeout(r, 1000 * attempt));
+                        const st = await api('GET', '/upload/sessions/' + id).catch(() => null);
+                        if (st) { received = st.received; break; }
+                    }
+                }
+                row.progress.value = received * 100 / file.size;
+            }
+            row.status.textContent = 'Storing in Telegram…';
+            const stored = await api('POST', '/upload/sessions/' + id + '/complete');
+            localStorage.removeItem(resumeKey);
+            row.progress.value = 100;
+            const link = document.createElement('a');
+            link.href = stored.url;
+            link.textContent = stored.url;
+            row.status.textContent = '';
+            row.status.append(link);
+        }
+
+        async function handle(files) {
+            const rows = Array.from(files).map(f => [f, addRow(f)]);
+            for (const [file, row] of rows) {
+                try { await upload(file, row); }
+                catch (e) { row.status.textContent = 'Failed: ' + e.message; row.status.classList.add('error'); }
+            }
+        }
+
+        const drop = document.getElementById('drop');
+        const picker = document.getElementById('picker');
+        drop.onclick = () => picker.click();
+        picker.onchange = () => { handle(picker.files); picker.value = ''; };
+        drop.ondragover = e => { e.preventDefault(); drop.classList.add('over'); };
+        drop.ondragleave = () => drop.classList.remove('over');
+        drop.ondrop = e => { e.preventDefault(); drop.classList.remove('over'); handle(e.dataTransfer.files); };
+    </script>
+</body>
+</html>"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mime_type() {
+        assert_eq!(parse_mime_type(None), Ok(None));
+        assert_eq!(parse_mime_type(Some(String::new())), Ok(None));
+        assert_eq!(parse_mime_type(Some("application/octet-stream".to_owned())), Ok(None));
+        assert_eq!(parse_mime_type(Some("text/plain; charset=utf-8".to_owned())), Ok(Some("text/plain; charset=utf-8".to_owned())));
+        assert!(parse_mime_type(Some("é/x".to_owned())).is_err());
+        assert!(parse_mime_type(Some("text/plain\n".to_owned())).is_err());
+        assert!(parse_mime_type(Some("plain".to_owned())).is_err());
+    }
+
+    #[test]
+    fn test_session_cookie() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(session_cookie(&headers), None);
+
+        headers.insert(COOKIE, "theme=dark; fileslink_upload=abc123".parse().unwrap());
+        assert_eq!(session_cookie(&headers), Some("abc123"));
+    }
+
+    #[test]
+    fn test_check_upload_quota() {
+        let session = |owner: &str, file_size: u64| UploadSession {
+            owner: owner.to_owned(),
+            file_name: "a.bin".to_owned(),
+            mime_type: None,
+            file_size,
+            received: 0,
+            path: PathBuf::from("/tmp/a.bin"),
+            busy: false,
+            updated_at: Instant::now(),
+        };
+
+        let mut uploads = HashMap::new();
+        assert!(check_upload_quota(&uploads, "key:1", MAX_UPLOAD_SIZE).is_ok());
+        assert_eq!(check_upload_quota(&uploads, "key:1", MAX_UPLOAD_SIZE + 1).unwrap_err().0, StatusCode::PAYLOAD_TOO_LARGE);
+
+        for i in 0..MAX_UPLOADS_PER_OWNER {
+            uploads.insert(i.to_string(), session("key:1", 1));
+        }
+        assert_eq!(check_upload_quota(&uploads, "key:1", 1).unwrap_err().0, StatusCode::TOO_MANY_REQUESTS);
+        assert!(check_upload_quota(&uploads, "key:2", 1).is_ok());
+
+        uploads.insert("big".to_owned(), session("key:2", MAX_PENDING_UPLOAD_SIZE - 1));
+        assert_eq!(check_upload_quota(&uploads, "key:3", MAX_UPLOAD_SIZE).unwrap_err().0, StatusCode::INSUFFICIENT_STORAGE);
+    }
+}
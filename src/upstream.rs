@@ -0,0 +1,234 @@
+// Fetching stored files from Telegram: the Bot API for small files,
+// the FastTelethon (MTProto) service for everything the Bot API cannot serve
+
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::response::Response;
+use futures_util::StreamExt;
+use http::header::RETRY_AFTER;
+use http::StatusCode;
+use log::{debug, error, info, warn};
+use teloxide::net::Download;
+use teloxide::prelude::Requester;
+use teloxide::{ApiError, RequestError};
+
+use shared::config::Config;
+use shared::file_storage::FileMetadata;
+
+use crate::file_cache::ByteStream;
+use crate::file_paths::{cached_file_path, forget_file_path, remember_file_path, FilePath};
+
+/// Largest file the official Bot API lets bots download
+pub const BOT_API_DOWNLOAD_LIMIT: u64 = 20 * 1024 * 1024;
+
+/// Why a stored file could not be fetched
+#[derive(Debug, PartialEq)]
+pub enum FetchError {
+    /// Telegram no longer has the file under the stored ids
+    Missing,
+    /// The Bot API cannot serve the file and FastTelethon cannot be used for it
+    TooLarge,
+    /// Telegram or FastTelethon is unreachable or asked us to back off
+    Unavailable { retry_after: Option<Duration> },
+    /// Telegram or FastTelethon answered with an unexpected error
+    Upstream,
+}
+
+impl FetchError {
+    pub fn into_response(self) -> Response<Body> {
+        let (status, message) = match self {
+            FetchError::Missing => (StatusCode::NOT_FOUND, "File is no longer available in the storage channel"),
+            FetchError::TooLarge => (StatusCode::NOT_IMPLEMENTED, "File is too large for the Bot API and the large file download service is not available for it"),
+            FetchError::Unavailable { .. } => (StatusCode::SERVICE_UNAVAILABLE, "Storage is temporarily unavailable, please try again later"),
+            FetchError::Upstream => (StatusCode::BAD_GATEWAY, "Failed to retrieve file from storage"),
+        };
+
+        let mut builder = Response::builder().status(status);
+
+        if let FetchError::Unavailable { retry_after: Some(retry_after) } = self {
+            builder = builder.header(RETRY_AFTER, retry_after.as_secs().max(1));
+        }
+
+        builder.body(Body::from(message)).unwrap()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Route {
+    BotApi,
+    FastTelethon,
+}
+
+/// Pick the download path up front instead of waiting for the Bot API to refuse.
+/// A self-hosted Bot API server has no download limit, so only the official one
+/// routes by size. Files uploaded through FastTelethon have no Bot API file id.
+fn route_for(metadata: &FileMetadata, official_bot_api: bool) -> Route {
+    if metadata.telegram_file_id.is_empty() {
+        return Route::FastTelethon;
+    }
+
+    if official_bot_api && metadata.file_size as u64 > BOT_API_DOWNLOAD_LIMIT {
+        return Route::FastTelethon;
+    }
+
+    Route::BotApi
+}
+
+fn is_official_bot_api(telegram_api_url: &str) -> bool {
+    telegram_api_url.contains("://api.telegram.org")
+}
+
+/// Open a download of a stored file
+pub async fn open_upstream(bot: &teloxide::Bot, config: &Config, metadata: &FileMetadata) -> Result<ByteStream, FetchError> {
+    let official_bot_api = is_official_bot_api(&config.telegram_api_url());
+
+    if route_for(metadata, official_bot_api) == Route::FastTelethon {
+        debug!("Routing {} ({} bytes) through FastTelethon", metadata.unique_id, metadata.file_size);
+
+        return fetch_from_fasttelethon(config, metadata).await;
+    }
+
+    let file_path = match cached_file_path(&metadata.telegram_file_id) {
+        Some(file_path) => file_path,
+        None => match bot.get_file(&metadata.telegram_file_id).await {
+            Ok(file_info) => {
+                let file_path = FilePath { path: file_info.path, size: file_info.meta.size };
+                remember_file_path(&metadata.telegram_file_id, file_path.clone());
+
+                file_path
+            }
+            Err(RequestError::Api(ApiError::Unknown(description))) if description == "Bad Request: file is too big" => {
+                // The stored size was wrong or a limit changed
+                warn!("Bot API refused {} as too big, fetching through FastTelethon", metadata.unique_id);
+
+                return fetch_from_fasttelethon(config, metadata).await;
+            }
+            Err(e) => return Err(bot_api_error(metadata, e)),
+        },
+    };
+
+    debug!("Downloading {} ({} bytes) from Telegram", file_path.path, file_path.size);
+
+    let telegram_file_id = metadata.telegram_file_id.clone();
+
+    Ok(bot.download_file_stream(&file_path.path)
+        .map(move |chunk| chunk.map_err(|e| {
+            // The path may have expired early; ask Telegram for a fresh one next time
+            forget_file_path(&telegram_file_id);
+            e.to_string()
+        }))
+        .boxed())
+}
+
+fn bot_api_error(metadata: &FileMetadata, error: RequestError) -> FetchError {
+    match error {
+        RequestError::Api(ApiError::FileIdInvalid | ApiError::WrongFileId | ApiError::WrongFileIdOrUrl) => {
+            warn!("Telegram rejected the file id of {}", metadata.unique_id);
+            FetchError::Missing
+        }
+        RequestError::RetryAfter(retry_after) => {
+            warn!("Bot API rate limit hit, retry after {:?}", retry_after);
+            FetchError::Unavailable { retry_after: Some(retry_after) }
+        }
+        RequestError::Network(e) => {
+            error!("Bot API is unreachable: {}", e);
+            FetchError::Unavailable { retry_after: None }
+        }
+        e => {
+            error!("Failed to get file info for {} from Telegram: {}", metadata.unique_id, e);
+            FetchError::Upstream
+        }
+    }
+}
+
+/// Open a download of a file through the FastTelethon service
+async fn fetch_from_fasttelethon(config: &Config, metadata: &FileMetadata) -> Result<ByteStream, FetchError> {
+    let fasttelethon_url = config.fasttelethon_url();
+
+    let channel_id = match config.storage_channel_id() {
+        Ok(id) => id,
+        Err(_) => {
+            error!("STORAGE_CHANNEL_ID not configured, cannot use FastTelethon");
+            return Err(FetchError::TooLarge);
+        }
+    };
+
+    // FastTelethon downloads by storage channel message
+    let message_id = match metadata.message_id {
+        Some(id) => id,
+        None => {
+            warn!("File {} has no message_id, cannot use FastTelethon", metadata.unique_id);
+            return Err(FetchError::TooLarge);
+        }
+    };
+
+    let download_url = format!("{}/download/{}/{}", fasttelethon_url, channel_id, message_id);
+
+    info!("Fetching file from FastTelethon: {}", download_url);
+
+    match reqwest::get(&download_url).await {
+        Ok(response) if response.status().is_success() => {
+            Ok(response.bytes_stream().map(|chunk| chunk.map_err(|e| e.to_string())).boxed())
+        }
+        Ok(response) if response.status() == reqwest::StatusCode::NOT_FOUND => {
+            warn!("FastTelethon could not find message {} of {}", message_id, metadata.unique_id);
+            Err(FetchError::Missing)
+        }
+        Ok(response) if response.status() == reqwest::StatusCode::SERVICE_UNAVAILABLE => {
+            error!("FastTelethon is not ready");
+            Err(FetchError::Unavailable { retry_after: None })
+        }
+        Ok(response) => {
+            error!("FastTelethon returned error: {}", response.status());
+            Err(FetchError::Upstream)
+        }
+        Err(e) => {
+            error!("Failed to connect to FastTelethon: {:?}", e);
+            Err(FetchError::Unavailable { retry_after: None })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(telegram_file_id: &str, file_size: u32) -> FileMetadata {
+        FileMetadata {
+            unique_id: "abcd1234".to_owned(),
+            telegram_file_id: telegram_file_id.to_owned(),
+            file_name: "a.bin".to_owned(),
+            mime_type: None,
+            file_size,
+            uploaded_at: 0,
+            message_id: Some(7),
+        }
+    }
+
+    #[test]
+    fn test_route_for() {
+        assert_eq!(route_for(&file("BQAC", 1024), true), Route::BotApi);
+        assert_eq!(route_for(&file("BQAC", 20 * 1024 * 1024), true), Route::BotApi);
+        assert_eq!(route_for(&file("BQAC", 20 * 1024 * 1024 + 1), true), Route::FastTelethon);
+        assert_eq!(route_for(&file("BQAC", 100 * 1024 * 1024), false), Route::BotApi);
+        assert_eq!(route_for(&file("", 1024), false), Route::FastTelethon);
+    }
+
+    #[test]
+    fn test_is_official_bot_api() {
+        assert!(is_official_bot_api("https://api.telegram.org/"));
+        assert!(!is_official_bot_api("http://nginx:80/"));
+    }
+
+    #[test]
+    fn test_fetch_error_statuses() {
+        assert_eq!(FetchError::Missing.into_response().status(), StatusCode::NOT_FOUND);
+        assert_eq!(FetchError::TooLarge.into_response().status(), StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(FetchError::Upstream.into_response().status(), StatusCode::BAD_GATEWAY);
+
+        let response = FetchError::Unavailable { retry_after: Some(Duration::from_secs(30)) }.into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "30");
+    }
+}
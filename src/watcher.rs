@@ -0,0 +1,176 @@
+// Reloading config files when they change on disk
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+use notify::{EventKind, RecursiveMode, Watcher};
+use tokio::sync::{mpsc, Mutex};
+
+use shared::chat_config::{self, PermissionsConfig};
+use shared::cli_utils::reload_config;
+use shared::config::SharedConfig;
+use shared::config_file::config_file_path;
+
+/// Editors often write a file in several steps; wait for this much quiet before reloading
+pub const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Loaded once at startup, so a change only takes effect after a restart
+const DOTENV_PATH: &str = ".env";
+
+/// Watch `CONFIG_PATH` (and `.env` and `fileslink.toml` when they exist) and apply
+/// changes. Returns when watching is not possible, e.g. inotify limits are exhausted.
+pub async fn run_config_watcher(config: SharedConfig, permissions: Arc<Mutex<PermissionsConfig>>) {
+    let main_config = config_file_path();
+
+    let mut files = vec![PathBuf::from(chat_config::CONFIG_PATH)];
+    for path in [DOTENV_PATH, main_config.as_str()] {
+        if Path::new(path).exists() {
+            files.push(PathBuf::from(path));
+        }
+    }
+
+    let (tx, mut rx) = mpsc::channel(16);
+
+    // Dropping the watcher stops it, so it lives as long as this task
+    let _watcher = match watch_files(&files, DEBOUNCE, tx) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            warn!("Not watching config files, use `fileslink-cli update-permissions` after edits: {}", e);
+            return;
+        }
+    };
+
+    info!("Watching {} for changes", files.iter().map(|f| f.display().to_string()).collect::<Vec<_>>().join(", "));
+
+    while let Some(path) = rx.recv().await {
+        if path == Path::new(chat_config::CONFIG_PATH) {
+            info!("{} changed, reloading permissions", path.display());
+            chat_config::reload_config(&permissions).await;
+        } else if path == Path::new(&main_config) {
+            info!("{} changed, reloading configuration", path.display());
+            reload_config(&config);
+        } else {
+            warn!("{} changed; restart FilesLink to apply it", path.display());
+        }
+    }
+}
+
+/// The watched directory and file name of every file, so replacing a file by
+/// renaming over it (as most editors do) is noticed as well
+fn watch_targets(files: &[PathBuf]) -> Vec<(PathBuf, PathBuf)> {
+    files.iter().map(|file| {
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        (dir, file.clone())
+    }).collect()
+}
+
+/// Send each file in `files` to `changed` once its events have been quiet for `debounce`
+pub fn watch_files(files: &[PathBuf], debounce: Duration, changed: mpsc::Sender<PathBuf>) -> notify::Result<notify::RecommendedWatcher> {
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let _ = event_tx.send(event);
+    })?;
+
+    // Canonical directory and file name of every file, compared against event paths
+    let mut targets = Vec::new();
+    for (dir, file) in watch_targets(files) {
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+        let dir = dir.canonicalize()?;
+        targets.push((dir, file.file_name().map(|name| name.to_owned()), file));
+    }
+
+    let matching = move |event: &notify::Event| -> Vec<PathBuf> {
+        event.paths.iter().filter_map(|path| {
+            let dir = path.parent()?.canonicalize().ok()?;
+
+            targets.iter()
+                .find(|(target_dir, name, _)| *target_dir == dir && name.as_deref() == path.file_name())
+                .map(|(_, _, file)| file.clone())
+        }).collect()
+    };
+
+    tokio::spawn(async move {
+        let mut pending = HashSet::new();
+
+        loop {
+            let event = if pending.is_empty() {
+                event_rx.recv().await
+            } else {
+                match tokio::time::timeout(debounce, event_rx.recv()).await {
+                    Ok(event) => event,
+                    Err(_) => {
+                        for file in pending.drain() {
+                            if changed.send(file).await.is_err() {
+                                return;
+                            }
+                        }
+
+                        continue;
+                    }
+                }
+            };
+
+            match event {
+                None => return,
+                Some(Ok(event)) => {
+                    if matches!(event.kind, EventKind::Access(_)) {
+                        continue;
+                    }
+
+                    for file in matching(&event) {
+                        debug!("{} changed: {:?}", file.display(), event.kind);
+                        pending.insert(file);
+                    }
+                }
+                Some(Err(e)) => error!("Config watcher error: {}", e),
+            }
+        }
+    });
+
+    Ok(watcher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_watch_files_debounces() {
+        let dir = std::env::temp_dir().join(format!("fileslink-watch-{}", nanoid::nanoid!()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("permissions.json");
+        std::fs::write(&file, "{}").unwrap();
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let _watcher = watch_files(std::slice::from_ref(&file), Duration::from_millis(200), tx).unwrap();
+
+        for i in 0..3 {
+            std::fs::write(&file, format!("{{\"n\": {}}}", i)).unwrap();
+            std::fs::write(dir.join("other.json"), "{}").unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let changed = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap();
+        assert_eq!(changed, Some(file));
+        assert!(tokio::time::timeout(Duration::from_millis(500), rx.recv()).await.is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_watch_targets() {
+        let targets = watch_targets(&[PathBuf::from("config/permissions.json"), PathBuf::from(".env")]);
+
+        assert_eq!(targets[0].0, PathBuf::from("config"));
+        assert_eq!(targets[1].0, PathBuf::from("."));
+    }
+}